@@ -0,0 +1,429 @@
+//! the `analyze` subcommand: load run directories, join them by matrix and config axes, and print tables
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use eyre::{Context, Result};
+use itertools::Itertools;
+use tracing::warn;
+
+use crate::{
+    args::{AnalyzeArgs, TableFormat},
+    run_output::{Manifest, MANIFEST_FILE},
+};
+
+/// the settings that name the run rather than configure it, they are never an axis
+const IGNORED_KEYS: &[&str] = &["mtx_files", "result_file", "output_dir", "run_name"];
+
+/// the cycles of one matrix in one run
+#[derive(Debug, Clone)]
+pub struct RunRecord {
+    pub run: String,
+    pub matrix: String,
+    /// the flattened effective config of the run
+    pub config: BTreeMap<String, String>,
+    /// none if the matrix failed
+    pub cycles: Option<f64>,
+}
+
+/// a table that can be printed as csv or markdown
+#[derive(Debug, Default, PartialEq)]
+pub struct Table {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn to_csv(&self) -> String {
+        let escape = |cell: &String| {
+            if cell.contains([',', '"', '\n']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.clone()
+            }
+        };
+        std::iter::once(&self.header)
+            .chain(&self.rows)
+            .map(|row| row.iter().map(escape).join(",") + "\n")
+            .collect()
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let line = |row: &Vec<String>| format!("| {} |\n", row.join(" | "));
+        let mut output = line(&self.header);
+        output.push_str(&line(&vec!["---".to_string(); self.header.len()]));
+        self.rows.iter().for_each(|row| output.push_str(&line(row)));
+        output
+    }
+
+    pub fn format(&self, format: &TableFormat) -> String {
+        match format {
+            TableFormat::Csv => self.to_csv(),
+            TableFormat::Markdown => self.to_markdown(),
+        }
+    }
+}
+
+/// flatten a toml value into dotted keys and their values
+fn flatten_values(prefix: &str, value: &toml::Value, out: &mut BTreeMap<String, String>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten_values(&key, value, out);
+            }
+        }
+        toml::Value::String(value) => {
+            out.insert(prefix.to_string(), value.clone());
+        }
+        _ => {
+            out.insert(prefix.to_string(), value.to_string());
+        }
+    }
+}
+
+/// the run directories under `path`: itself if it has a manifest, otherwise its sub directories that have one
+pub fn find_runs(path: &Path) -> Result<Vec<PathBuf>> {
+    if path.join(MANIFEST_FILE).exists() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let runs = std::fs::read_dir(path)
+        .wrap_err(format!("{path:?} is not a run directory"))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|dir| dir.join(MANIFEST_FILE).exists())
+        .sorted()
+        .collect_vec();
+    if runs.is_empty() {
+        warn!("no run directory found in {:?}", path);
+    }
+    Ok(runs)
+}
+
+/// read the cycles of all matrices of a run directory,
+/// the matrices without the sim artifacts (like the runs of `-r pim`) are skipped
+pub fn load_run(run_dir: &Path) -> Result<Vec<RunRecord>> {
+    let manifest = Manifest::load(run_dir)?;
+    let mut config = BTreeMap::new();
+    let value: toml::Value = toml::from_str(&manifest.effective_config)
+        .wrap_err(format!("fail to parse the config of {run_dir:?}"))?;
+    flatten_values("", &value, &mut config);
+    config.retain(|key, _| !IGNORED_KEYS.contains(&key.as_str()));
+    let run = run_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    manifest
+        .matrices
+        .iter()
+        .filter_map(|matrix| {
+            let cycles = match &matrix.error {
+                Some(_) => None,
+                None => {
+                    let full_time = matrix
+                        .artifacts
+                        .iter()
+                        .find(|artifact| artifact.ends_with("full_time.json"));
+                    let full_time = match full_time {
+                        Some(full_time) => full_time,
+                        None => {
+                            warn!(
+                                "no full_time.json for {:?} in {:?}, skipped",
+                                matrix.file, run_dir
+                            );
+                            return None;
+                        }
+                    };
+                    let path = run_dir.join(full_time);
+                    let cycles = File::open(&path)
+                        .wrap_err(format!("{path:?}"))
+                        .and_then(|file| {
+                            serde_json::from_reader::<_, f64>(file)
+                                .wrap_err(format!("fail to parse {path:?}"))
+                        });
+                    match cycles {
+                        Ok(cycles) => Some(cycles),
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+            };
+            Some(Ok(RunRecord {
+                run: run.clone(),
+                matrix: matrix
+                    .file
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                config: config.clone(),
+                cycles,
+            }))
+        })
+        .collect()
+}
+
+/// load all runs under the paths
+pub fn load_runs(paths: &[PathBuf]) -> Result<Vec<RunRecord>> {
+    let mut records = vec![];
+    for path in paths {
+        for run in find_runs(path)? {
+            match load_run(&run) {
+                Ok(run_records) => records.extend(run_records),
+                Err(e) => warn!("skip the run {:?}: {:?}", run, e),
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// the config keys that are not the same in all records
+pub fn varying_axes(records: &[RunRecord]) -> Vec<String> {
+    let keys: BTreeSet<&String> = records.iter().flat_map(|r| r.config.keys()).collect();
+    keys.into_iter()
+        .filter(|key| {
+            records
+                .iter()
+                .map(|r| r.config.get(*key))
+                .collect::<BTreeSet<_>>()
+                .len()
+                > 1
+        })
+        .cloned()
+        .collect()
+}
+
+fn axis_values(record: &RunRecord, axes: &[String]) -> Vec<String> {
+    axes.iter()
+        .map(|axis| record.config.get(axis).cloned().unwrap_or_default())
+        .collect()
+}
+
+fn format_cycles(cycles: Option<f64>) -> String {
+    cycles.map_or("failed".to_string(), |c| c.to_string())
+}
+
+/// one row for each matrix and config, only the axes that change between runs are shown
+pub fn summary_table(records: &[RunRecord]) -> Table {
+    let axes = varying_axes(records);
+    let header = ["matrix"]
+        .into_iter()
+        .map(String::from)
+        .chain(axes.iter().cloned())
+        .chain(["cycles", "run"].into_iter().map(String::from))
+        .collect();
+    let rows = records
+        .iter()
+        .map(|record| {
+            let mut row = vec![record.matrix.clone()];
+            row.extend(axis_values(record, &axes));
+            row.push(format_cycles(record.cycles));
+            row.push(record.run.clone());
+            row
+        })
+        .sorted()
+        .collect();
+    Table { header, rows }
+}
+
+/// the result of comparing two result sets
+#[derive(Debug)]
+pub struct Diff {
+    pub table: Table,
+    /// the rows whose cycles changed beyond the tolerance, or only exist in one set
+    pub changed: usize,
+    pub total: usize,
+}
+
+/// join the two sets by matrix and the axes that change inside each set, compare the cycles.
+/// `tolerance` is relative: 0.01 means a change larger than 1% is reported.
+pub fn diff_table(base: &[RunRecord], new: &[RunRecord], tolerance: f64) -> Diff {
+    let axes = varying_axes(base)
+        .into_iter()
+        .chain(varying_axes(new))
+        .sorted()
+        .dedup()
+        .collect_vec();
+    let key = |record: &RunRecord| (record.matrix.clone(), axis_values(record, &axes));
+    let base_map: BTreeMap<_, _> = base.iter().map(|r| (key(r), r.cycles)).collect();
+    let new_map: BTreeMap<_, _> = new.iter().map(|r| (key(r), r.cycles)).collect();
+    let keys: BTreeSet<_> = base_map.keys().chain(new_map.keys()).collect();
+
+    let header = ["matrix"]
+        .into_iter()
+        .map(String::from)
+        .chain(axes.iter().cloned())
+        .chain(
+            ["base", "new", "change", "status"]
+                .into_iter()
+                .map(String::from),
+        )
+        .collect();
+    let mut changed = 0;
+    let rows = keys
+        .iter()
+        .map(|key| {
+            let (old_cycles, new_cycles) = (base_map.get(*key), new_map.get(*key));
+            let (change, status) = match (old_cycles, new_cycles) {
+                (Some(Some(old)), Some(Some(new))) => {
+                    let change = if *old == 0. {
+                        if *new == 0. {
+                            0.
+                        } else {
+                            f64::INFINITY
+                        }
+                    } else {
+                        (new - old) / old
+                    };
+                    let status = if change.abs() > tolerance {
+                        "changed"
+                    } else {
+                        "same"
+                    };
+                    (format!("{:+.2}%", change * 100.), status)
+                }
+                (Some(_), Some(_)) => (String::new(), "failed"),
+                (Some(_), None) => (String::new(), "only base"),
+                (None, _) => (String::new(), "only new"),
+            };
+            if status != "same" {
+                changed += 1;
+            }
+            let mut row = vec![key.0.clone()];
+            row.extend(key.1.iter().cloned());
+            row.push(old_cycles.map_or(String::new(), |c| format_cycles(*c)));
+            row.push(new_cycles.map_or(String::new(), |c| format_cycles(*c)));
+            row.push(change);
+            row.push(status.to_string());
+            row
+        })
+        .collect();
+    Diff {
+        table: Table { header, rows },
+        changed,
+        total: keys.len(),
+    }
+}
+
+/// run the `analyze` subcommand
+pub fn main(args: &AnalyzeArgs) -> Result<()> {
+    let records = load_runs(&args.runs)?;
+    let output = match &args.diff {
+        Some(new_runs) => {
+            let new_records = load_runs(new_runs)?;
+            let diff = diff_table(&records, &new_records, args.tolerance);
+            eprintln!(
+                "{} of {} results changed beyond {}%",
+                diff.changed,
+                diff.total,
+                args.tolerance * 100.
+            );
+            diff.table.format(&args.format)
+        }
+        None => summary_table(&records).format(&args.format),
+    };
+    match &args.output {
+        Some(path) => std::fs::write(path, output).wrap_err(format!("fail to write {path:?}"))?,
+        None => print!("{output}"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::fixtures::run_record as record;
+
+    #[test]
+    fn summary_test() {
+        let records = vec![
+            record("b", "m1", &[("banks", "4"), ("seed", "0")], 20.),
+            record("a", "m1", &[("banks", "2"), ("seed", "0")], 10.),
+        ];
+        assert_eq!(varying_axes(&records), vec!["banks".to_string()]);
+        let table = summary_table(&records);
+        assert_eq!(table.header, vec!["matrix", "banks", "cycles", "run"]);
+        assert_eq!(table.rows[0], vec!["m1", "2", "10", "a"]);
+        assert_eq!(
+            table.to_markdown(),
+            "| matrix | banks | cycles | run |\n| --- | --- | --- | --- |\n| m1 | 2 | 10 | a |\n| m1 | 4 | 20 | b |\n"
+        );
+        assert_eq!(
+            table.to_csv(),
+            "matrix,banks,cycles,run\nm1,2,10,a\nm1,4,20,b\n"
+        );
+    }
+
+    #[test]
+    fn diff_test() {
+        let base = vec![
+            record("a", "m1", &[("banks", "2")], 100.),
+            record("a", "m2", &[("banks", "2")], 100.),
+        ];
+        let new = vec![
+            record("b", "m1", &[("banks", "2")], 100.5),
+            record("b", "m2", &[("banks", "2")], 120.),
+            record("b", "m3", &[("banks", "2")], 1.),
+        ];
+        let diff = diff_table(&base, &new, 0.01);
+        assert_eq!(diff.total, 3);
+        assert_eq!(diff.changed, 2);
+        let status = diff.table.rows.iter().map(|r| r[4].as_str()).collect_vec();
+        assert_eq!(status, vec!["same", "changed", "only new"]);
+        assert_eq!(diff.table.rows[1][3], "+20.00%");
+    }
+
+    #[test]
+    fn load_run_test() {
+        use crate::{
+            run_output::RunOutput,
+            settings::{ConfigLayer, EffectiveSettings},
+        };
+        let output_dir = std::env::temp_dir().join("spmm_pim_analyze_test");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        for (name, banks, cycles) in [("run1", 2, 10.), ("run2", 4, 20.)] {
+            let effective = EffectiveSettings::load(&[
+                ConfigLayer::Set(format!("output_dir=\"{}\"", output_dir.display())),
+                ConfigLayer::Set(format!("run_name=\"{name}\"")),
+                ConfigLayer::Set(format!("banks={banks}")),
+            ])
+            .unwrap();
+            let mut output = RunOutput::create(&effective, "Sim").unwrap();
+            output.write_json("bfwa62/full_time.json", &cycles).unwrap();
+            output
+                .add_matrix(
+                    Path::new("mtx/bfwa62.mtx"),
+                    (62, 62),
+                    450,
+                    vec!["bfwa62/full_time.json".into()],
+                )
+                .unwrap();
+            output.finish().unwrap();
+        }
+        // a pim run has no sim artifacts, and a directory with a broken manifest is not a run
+        let effective = EffectiveSettings::load(&[
+            ConfigLayer::Set(format!("output_dir=\"{}\"", output_dir.display())),
+            ConfigLayer::Set("run_name=\"pim\"".to_string()),
+        ])
+        .unwrap();
+        let mut output = RunOutput::create(&effective, "Pim").unwrap();
+        output
+            .add_matrix(Path::new("mtx/bfwa62.mtx"), (62, 62), 450, vec![])
+            .unwrap();
+        output.finish().unwrap();
+        let broken = output_dir.join("broken");
+        std::fs::create_dir_all(&broken).unwrap();
+        std::fs::write(broken.join(MANIFEST_FILE), "not json").unwrap();
+
+        let records = load_runs(&[output_dir]).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(varying_axes(&records), vec!["mem_settings.banks"]);
+        let table = summary_table(&records);
+        assert_eq!(table.rows[1], vec!["bfwa62", "4", "20", "run2"]);
+    }
+}
@@ -1,21 +1,156 @@
 use std::path::PathBuf;
 
-use clap::{Parser, ValueHint};
+use clap::{Parser, Subcommand, ValueHint};
 use clap_complete::Shell;
+use serde::{Deserialize, Serialize};
+
+use crate::{settings::ConfigLayer, utils::generator::WorkloadSpec};
 
 #[derive(Parser, Debug)]
-#[clap(author="Jiangqiu Shen",version,about="a spmm pim simulator",long_about=None,trailing_var_arg=true)]
+#[clap(author="Jiangqiu Shen",version,about="a spmm pim simulator",long_about=None,trailing_var_arg=true,args_conflicts_with_subcommands=true)]
 pub struct Args {
     /// Generate completion for the given shell
     #[clap(long = "generate", short = 'g', arg_enum)]
     pub generator: Option<Shell>,
     #[clap(long = "run-mode", short = 'r', arg_enum)]
     pub run_mode: Option<RunMode>,
-    /// the path of config file, default is "default.toml"
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    #[clap(subcommand)]
+    pub subcommand: Option<SubCommand>,
+}
+
+/// where the settings come from, applied in order: presets, config files, then `--set`
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct ConfigArgs {
+    /// the built-in presets: default, ddr4, ddr4chunk, hbm, debug, large, all
+    #[clap(long = "preset", short = 'p')]
+    pub presets: Vec<String>,
+    /// override one setting, like `--set banks=4` or `--set mem_settings.row_mapping=Chunk`
+    #[clap(long = "set", short = 's')]
+    pub sets: Vec<String>,
+    /// the path of config file, default is "configs/default.toml" and "configs/ddr4.toml"
     #[clap(parse(from_os_str),value_hint=ValueHint::FilePath)]
     pub config_file: Vec<PathBuf>,
 }
+
+impl ConfigArgs {
+    /// the layers of the settings, use the default config files when no preset or file is given
+    pub fn layers(&self) -> Vec<ConfigLayer> {
+        let mut layers = self
+            .presets
+            .iter()
+            .cloned()
+            .map(ConfigLayer::Preset)
+            .collect::<Vec<_>>();
+        layers.extend(self.config_file.iter().cloned().map(ConfigLayer::File));
+        if layers.is_empty() {
+            layers.push(ConfigLayer::File("configs/default.toml".into()));
+            layers.push(ConfigLayer::File("configs/ddr4.toml".into()));
+        }
+        layers.extend(self.sets.iter().cloned().map(ConfigLayer::Set));
+        layers
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SubCommand {
+    /// print the effective settings as toml, with the source of each value
+    Config(ConfigArgs),
+    /// summarize the cycles of run directories, or compare two sets of runs
+    Analyze(AnalyzeArgs),
+    /// draw the idle/busy breakdowns and bank loads of runs, or the cycles of a sweep
+    Plot(PlotArgs),
+    /// generate a synthetic matrix and write it as a matrix market file
+    Gen(GenArgs),
+    /// print the nnz, row/column lengths, bank distribution and partial sums of matrices as json
+    Inspect(InspectArgs),
+    /// run a local http server, the jobs are submitted and polled as json
+    Serve(ServeArgs),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ServeArgs {
+    /// the base settings of the jobs, each job can add its own `set`
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    #[clap(long, short, default_value = "127.0.0.1:8080")]
+    pub addr: String,
+    /// the jobs that run at the same time
+    #[clap(long, short, default_value = "2")]
+    pub workers: usize,
+    /// the jobs that can wait for a worker, more are rejected
+    #[clap(long, default_value = "64")]
+    pub queue_size: usize,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct InspectArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+    /// the matrices to inspect, default is the mtx_files and workloads of the settings
+    #[clap(long = "matrix", short = 'm', parse(from_os_str), value_hint = ValueHint::FilePath)]
+    pub matrices: Vec<PathBuf>,
+    /// write the json into this file instead of stdout
+    #[clap(long, short, parse(from_os_str), value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct GenArgs {
+    #[clap(flatten)]
+    pub spec: WorkloadSpec,
+    /// the output file, default is `{name}.mtx`
+    #[clap(long, short, parse(from_os_str), value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PlotArgs {
+    /// the run directories, or directories that contain run directories like `results`
+    #[clap(required = true, parse(from_os_str), value_hint = ValueHint::DirPath)]
+    pub runs: Vec<PathBuf>,
+    /// draw the cycles of each matrix against this setting, like `sender_store_size`
+    #[clap(long)]
+    pub sweep: Option<String>,
+    #[clap(long, short, arg_enum, default_value = "svg")]
+    pub format: ImageFormat,
+    /// the file of the sweep chart, default is `sweep_{setting}.{format}`
+    #[clap(long, short, parse(from_os_str), value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+}
+
 #[derive(Debug, Clone, clap::ArgEnum)]
+pub enum ImageFormat {
+    Svg,
+    Png,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct AnalyzeArgs {
+    /// the run directories, or directories that contain run directories like `results`
+    #[clap(required = true, parse(from_os_str), value_hint = ValueHint::DirPath)]
+    pub runs: Vec<PathBuf>,
+    /// compare the cycles of `runs` (the base) with these runs
+    #[clap(long, multiple_values = true, parse(from_os_str), value_hint = ValueHint::DirPath)]
+    pub diff: Option<Vec<PathBuf>>,
+    /// the relative change that is reported by `--diff`, 0.01 is 1%
+    #[clap(long, short, default_value = "0.01")]
+    pub tolerance: f64,
+    #[clap(long, short, arg_enum, default_value = "markdown")]
+    pub format: TableFormat,
+    /// write the table into this file instead of stdout
+    #[clap(long, short, parse(from_os_str), value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, clap::ArgEnum)]
+pub enum TableFormat {
+    Csv,
+    Markdown,
+}
+
+#[derive(Debug, Clone, clap::ArgEnum, Serialize, Deserialize)]
 pub enum RunMode {
     Sim,
     Pim,
@@ -0,0 +1,128 @@
+//! compare the old sort based merge with the k-way merge of `merge_rows_into_one` over the mtx corpus
+//! usage: cargo run --release --bin merge_bench -- [merger_size] [mtx files...]
+//! when no file is given, all files in `mtx/` are used.
+
+use std::{path::PathBuf, rc::Rc, time::Instant};
+
+use itertools::Itertools;
+use spmm_pim::{csv_nodata::CsVecNodata, pim::merge_rows_into_one};
+use sprs::CsMat;
+
+/// the old implementation: fold each group of `merger_size` rows with extend + sort + dedup
+fn sort_merge(
+    tasks: Vec<Rc<CsVecNodata<usize>>>,
+    merger_size: usize,
+) -> (usize, usize, Vec<usize>) {
+    let mut tasks = tasks.into_iter().map(|x| x.indices.clone()).collect_vec();
+    let mut merge_cycles = 0;
+    let mut add_cycles = 0;
+    while tasks.len() > 1 {
+        tasks = tasks
+            .into_iter()
+            .chunks(merger_size)
+            .into_iter()
+            .map(|x| {
+                let x = x.collect_vec();
+                if x.len() == 1 {
+                    return x.into_iter().next().unwrap();
+                }
+                let old_len = x.iter().map(|y| y.len()).sum::<usize>();
+                // the old `Add`: extend + sort + dedup for each row
+                let all = x.into_iter().fold(vec![], |mut all, y| {
+                    all.extend(y);
+                    all.sort_unstable();
+                    all.dedup();
+                    all
+                });
+                merge_cycles += old_len;
+                add_cycles += old_len - all.len();
+                all
+            })
+            .collect_vec();
+    }
+    (merge_cycles, add_cycles, tasks.pop().unwrap())
+}
+
+fn main() -> eyre::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let merger_size = args
+        .next()
+        .map(|x| x.parse())
+        .transpose()?
+        .unwrap_or(8usize);
+    let mut files = args.map(PathBuf::from).collect_vec();
+    if files.is_empty() {
+        files = std::fs::read_dir("mtx")?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == "mtx"))
+            .sorted()
+            .collect();
+    }
+
+    println!("| file | nnz | tasks | sort(ms) | kway(ms) | speedup |");
+    println!("| --- | --- | --- | --- | --- | --- |");
+    let (mut total_sort, mut total_kway) = (0., 0.);
+    for file in files {
+        let csr: CsMat<i32> = match sprs::io::read_matrix_market(&file) {
+            Ok(matrix) => matrix.to_csr(),
+            Err(e) => {
+                eprintln!("skip {:?}: {}", file, e);
+                continue;
+            }
+        };
+        let b = csr.transpose_view().to_csr();
+        let b_rows = b
+            .outer_iterator()
+            .map(|row| Rc::new(CsVecNodata::from(row.to_owned())))
+            .collect_vec();
+        // each row of a is one task: the rows of b selected by its nonzeros
+        let tasks = csr
+            .outer_iterator()
+            .map(|row| {
+                row.indices()
+                    .iter()
+                    .map(|&i| b_rows[i].clone())
+                    .collect_vec()
+            })
+            .filter(|task| !task.is_empty())
+            .collect_vec();
+
+        let start = Instant::now();
+        let sort_result = tasks
+            .iter()
+            .map(|task| sort_merge(task.clone(), merger_size))
+            .collect_vec();
+        let sort_time = start.elapsed().as_secs_f64() * 1000.;
+
+        let start = Instant::now();
+        let kway_result = tasks
+            .iter()
+            .map(|task| merge_rows_into_one(task.clone(), merger_size))
+            .collect_vec();
+        let kway_time = start.elapsed().as_secs_f64() * 1000.;
+
+        for (old, new) in sort_result.iter().zip(&kway_result) {
+            assert_eq!(old.0, new.0, "merge cycles differ in {:?}", file);
+            assert_eq!(old.1, new.1, "add cycles differ in {:?}", file);
+            assert_eq!(old.2, new.2.indices, "results differ in {:?}", file);
+        }
+        total_sort += sort_time;
+        total_kway += kway_time;
+        println!(
+            "| {} | {} | {} | {:.3} | {:.3} | {:.2} |",
+            file.display(),
+            csr.nnz(),
+            tasks.len(),
+            sort_time,
+            kway_time,
+            sort_time / kway_time
+        );
+    }
+    println!(
+        "| total | | | {:.3} | {:.3} | {:.2} |",
+        total_sort,
+        total_kway,
+        total_sort / total_kway
+    );
+    Ok(())
+}
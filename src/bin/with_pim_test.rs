@@ -1,6 +1,10 @@
 use itertools::Itertools;
 
-use spmm_pim::{pim::Pim, settings::Settings, two_matrix::TwoMatrix};
+use spmm_pim::{
+    pim::{Pim, ResolvedRowMapping},
+    settings::Settings,
+    two_matrix::TwoMatrix,
+};
 use sprs::CsMat;
 use tracing::metadata::LevelFilter;
 mod types;
@@ -36,11 +40,12 @@ fn main() -> eyre::Result<()> {
         let csr_trans = csr.transpose_view().to_csr();
         let two_matrix = TwoMatrix::new(csr, csr_trans);
 
-        let mem_rows = two_matrix.mem_rows(&mem_settings);
+        let row_mapping = ResolvedRowMapping::new(&mem_settings, &two_matrix.b)?;
+        let mem_rows = two_matrix.mem_rows(&mem_settings, &row_mapping);
         let bank_reads = mem_rows.iter().cloned().map(|(_, b)| b).collect();
         let mem_rows = mem_rows.iter().cloned().map(|(a, _)| a).collect_vec();
         tracing::info!(?mem_rows);
-        let (bank_cycles, bank_partial_sum) = two_matrix.bank_merge(&mem_settings);
+        let (bank_cycles, bank_partial_sum) = two_matrix.bank_merge(&mem_settings, &row_mapping);
         tracing::info!(?bank_cycles);
         let (bank_sent, chip_recv) = two_matrix.chip_fetch_data(&mem_settings, &bank_partial_sum);
         tracing::info!(?bank_sent, ?chip_recv);
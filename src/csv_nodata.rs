@@ -1,9 +1,12 @@
 use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
     fmt::Debug,
     iter::Sum,
     ops::{Add, Deref, DerefMut},
 };
 
+use itertools::Itertools;
 use sprs::{CsVecI, SpIndex};
 
 #[derive(PartialEq, Eq, Clone, Default)]
@@ -65,6 +68,87 @@ where
     }
 }
 
+/// merge two sorted index slices into `out`, the duplicated indices are kept only once
+fn merge_two<I: SpIndex>(a: &[I], b: &[I], out: &mut Vec<I>) {
+    out.reserve(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let next = match a[i].cmp(&b[j]) {
+            Ordering::Less => {
+                i += 1;
+                a[i - 1]
+            }
+            Ordering::Greater => {
+                j += 1;
+                b[j - 1]
+            }
+            Ordering::Equal => {
+                i += 1;
+                j += 1;
+                a[i - 1]
+            }
+        };
+        push_unique(out, next);
+    }
+    for &index in a[i..].iter().chain(&b[j..]) {
+        push_unique(out, index);
+    }
+}
+
+#[inline]
+fn push_unique<I: SpIndex>(out: &mut Vec<I>, index: I) {
+    if out.last() != Some(&index) {
+        out.push(index);
+    }
+}
+
+/// k-way merge of sorted index slices, the result is the same as concat + sort + dedup.
+/// - a min heap holds the next index of each row, so it's O(n log k) with one pass over the rows
+/// - `out` is cleared first and is the only output buffer, so it can be reused as a scratch buffer
+pub fn merge_sorted_into<I: SpIndex>(rows: &[&[I]], out: &mut Vec<I>) {
+    debug_assert!(rows.iter().all(|row| row.windows(2).all(|w| w[0] <= w[1])));
+    out.clear();
+    match rows {
+        [] => {}
+        [a] => out.extend(a.iter().cloned().dedup()),
+        [a, b] => merge_two(a, b, out),
+        _ => {
+            out.reserve(rows.iter().map(|row| row.len()).sum());
+            // (next index, row, position of the next index in the row)
+            let mut heap: BinaryHeap<_> = rows
+                .iter()
+                .enumerate()
+                .filter(|(_, row)| !row.is_empty())
+                .map(|(row, indices)| Reverse((indices[0], row, 0)))
+                .collect();
+            while let Some(Reverse((index, row, position))) = heap.pop() {
+                push_unique(out, index);
+                if let Some(&next) = rows[row].get(position + 1) {
+                    heap.push(Reverse((next, row, position + 1)));
+                }
+            }
+        }
+    }
+}
+
+impl<T> CsVecNodata<T>
+where
+    T: SpIndex,
+{
+    /// merge all rows into one in linear time, the dim is the dim of the first row.
+    pub fn merge_all<'a>(rows: impl IntoIterator<Item = &'a Self>) -> Self
+    where
+        T: 'a,
+    {
+        let rows = rows.into_iter().collect_vec();
+        let dim = rows.first().map(|row| row.dim).unwrap_or_default();
+        let slices = rows.iter().map(|row| row.indices.as_slice()).collect_vec();
+        let mut indices = vec![];
+        merge_sorted_into(&slices, &mut indices);
+        CsVecNodata { dim, indices }
+    }
+}
+
 impl<T> Add for CsVecNodata<T>
 where
     T: SpIndex,
@@ -72,17 +156,24 @@ where
     type Output = Self;
 
     fn add(self, other: Self) -> Self {
-        let mut index1 = self.indices;
-        let index2 = other.indices;
-        // merge the two sorted index
+        self + &other
+    }
+}
 
-        index1.extend(index2);
-        index1.sort_unstable();
-        index1.dedup();
+/// merge a borrowed vector into self, used when the other one is shared and should not be copied
+impl<T> Add<&CsVecNodata<T>> for CsVecNodata<T>
+where
+    T: SpIndex,
+{
+    type Output = Self;
 
+    fn add(self, other: &Self) -> Self {
+        // merge the two sorted index
+        let mut indices = Vec::new();
+        merge_two(&self.indices, &other.indices, &mut indices);
         CsVecNodata {
             dim: self.dim,
-            indices: index1,
+            indices,
         }
     }
 }
@@ -115,7 +206,7 @@ where
 mod test {
     use sprs::CsVec;
 
-    use super::{get_csvec_nodata, CsVecNodata};
+    use super::{get_csvec_nodata, merge_sorted_into, CsVecNodata};
     #[test]
     fn test() {
         let csvec = CsVec::new(100, vec![0, 1, 4, 5, 6, 9], vec![1, 2, 5, 6, 7, 10]);
@@ -136,4 +227,48 @@ mod test {
         let out: CsVecNodata<usize> = vscs.into_iter().sum();
         println!("{:?}", out);
     }
+
+    /// the old implementation, concat + sort + dedup
+    fn naive_merge(rows: &[Vec<usize>]) -> Vec<usize> {
+        let mut all = rows.concat();
+        all.sort_unstable();
+        all.dedup();
+        all
+    }
+
+    #[test]
+    fn test_merge_all() {
+        let rows = vec![
+            vec![0, 3, 5, 9],
+            vec![],
+            vec![1, 3, 4, 9, 12],
+            vec![2, 5, 5, 13],
+            vec![0, 1, 2],
+        ];
+        let mut scratch = vec![42];
+        for k in 0..=rows.len() {
+            let vecs = rows[..k]
+                .iter()
+                .map(|x| CsVecNodata {
+                    dim: 20,
+                    indices: x.clone(),
+                })
+                .collect::<Vec<_>>();
+            let out = CsVecNodata::merge_all(&vecs);
+            assert_eq!(out.indices, naive_merge(&rows[..k]));
+            // the scratch buffer keeps nothing of the last merge
+            let slices = rows[..k].iter().map(|x| x.as_slice()).collect::<Vec<_>>();
+            merge_sorted_into(&slices, &mut scratch);
+            assert_eq!(scratch, out.indices);
+        }
+    }
+
+    #[test]
+    fn test_add_ref() {
+        let a: CsVecNodata<usize> = CsVec::new(100, vec![0, 1, 4], vec![1, 2, 5]).into();
+        let b: CsVecNodata<usize> = CsVec::new(100, vec![4, 8, 9], vec![1, 2, 9]).into();
+        let out = a.clone() + &b;
+        assert_eq!(out, a + b);
+        assert_eq!(out.indices, vec![0, 1, 4, 8, 9]);
+    }
 }
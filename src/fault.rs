@@ -0,0 +1,289 @@
+//! failed and slow banks or chips, the rows of the failed banks are moved to the healthy ones
+
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use sprs::CsMat;
+
+use crate::{
+    pim::{get_bank_id_from_row_id, get_flat_bank_id, ResolvedRowMapping},
+    settings::MemSettings,
+    sim::id_translation::{BankID, ChipID},
+};
+
+/// a bank that is slower than the others
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct SlowBank {
+    /// the flat bank id, `(channel * chips + chip) * banks + bank`
+    pub bank: usize,
+    /// the read and merge cycles are multiplied by it
+    pub slowdown: f64,
+}
+
+/// a chip whose banks are all slower than the others
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct SlowChip {
+    pub channel: usize,
+    pub chip: usize,
+    pub slowdown: f64,
+}
+
+/// the faults of the dimm. in toml:
+/// ```toml
+/// [mem_settings.faults]
+/// failed_banks = [3]
+/// failed_chips = [[0, 1]]
+/// slow_banks = [{ bank = 5, slowdown = 2.0 }]
+/// slow_chips = [{ channel = 1, chip = 0, slowdown = 1.5 }]
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Faults {
+    /// the flat ids of the banks that hold no rows
+    pub failed_banks: Vec<usize>,
+    /// the (channel, chip) of the chips whose banks hold no rows
+    pub failed_chips: Vec<ChipID>,
+    pub slow_banks: Vec<SlowBank>,
+    pub slow_chips: Vec<SlowChip>,
+}
+
+impl Faults {
+    pub fn is_empty(&self) -> bool {
+        self.failed_banks.is_empty()
+            && self.failed_chips.is_empty()
+            && self.slow_banks.is_empty()
+            && self.slow_chips.is_empty()
+    }
+}
+
+/// the faults resolved for each flat bank
+#[derive(Debug, Clone)]
+pub struct FaultMap {
+    failed: Vec<bool>,
+    slowdown: Vec<f64>,
+    chips: usize,
+    banks: usize,
+}
+
+impl FaultMap {
+    /// the ids out of range are ignored, see `MemSettings::validate`
+    pub fn new(mem_settings: &MemSettings) -> Self {
+        let (channels, chips, banks) = (
+            mem_settings.channels,
+            mem_settings.chips,
+            mem_settings.banks,
+        );
+        let num_banks = channels * chips * banks;
+        let faults = &mem_settings.faults;
+        let mut failed = vec![false; num_banks];
+        let mut slowdown = vec![1.; num_banks];
+        let chip_banks = |channel: usize, chip: usize| {
+            let first = (channel * chips + chip) * banks;
+            first..(first + banks).min(num_banks)
+        };
+        for &bank in faults.failed_banks.iter().filter(|&&bank| bank < num_banks) {
+            failed[bank] = true;
+        }
+        for &(channel, chip) in &faults.failed_chips {
+            if channel < channels && chip < chips {
+                chip_banks(channel, chip).for_each(|bank| failed[bank] = true);
+            }
+        }
+        for slow in faults
+            .slow_banks
+            .iter()
+            .filter(|slow| slow.bank < num_banks)
+        {
+            slowdown[slow.bank] *= slow.slowdown;
+        }
+        for slow in &faults.slow_chips {
+            if slow.channel < channels && slow.chip < chips {
+                chip_banks(slow.channel, slow.chip)
+                    .for_each(|bank| slowdown[bank] *= slow.slowdown);
+            }
+        }
+        Self {
+            failed,
+            slowdown,
+            chips,
+            banks,
+        }
+    }
+
+    pub fn has_failed(&self) -> bool {
+        self.failed.iter().any(|&failed| failed)
+    }
+
+    pub fn failed_banks(&self) -> usize {
+        self.failed.iter().filter(|&&failed| failed).count()
+    }
+
+    pub fn slow_banks(&self) -> usize {
+        self.slowdown
+            .iter()
+            .filter(|&&slowdown| slowdown != 1.)
+            .count()
+    }
+
+    pub fn is_failed(&self, bank: BankID) -> bool {
+        self.is_flat_failed(get_flat_bank_id(bank, self.chips, self.banks))
+    }
+
+    pub fn is_flat_failed(&self, flat_bank: usize) -> bool {
+        self.failed[flat_bank]
+    }
+
+    /// the factor of the read and merge cycles of the bank, 1 for a healthy bank
+    pub fn slowdown(&self, bank: BankID) -> f64 {
+        self.slowdown[get_flat_bank_id(bank, self.chips, self.banks)]
+    }
+
+    /// move the rows of the failed banks to the healthy bank with the fewest nonzeros
+    pub fn remap<N>(&self, mut flat_banks: Vec<usize>, matrix_b: &CsMat<N>) -> Vec<usize> {
+        let row_nnz = |row: usize| matrix_b.outer_view(row).map_or(0, |row| row.nnz());
+        let mut loads = vec![0; self.failed.len()];
+        for (row, &bank) in flat_banks.iter().enumerate() {
+            loads[bank] += row_nnz(row);
+        }
+        let mut healthy: BinaryHeap<_> = (0..self.failed.len())
+            .filter(|&bank| !self.failed[bank])
+            .map(|bank| Reverse((loads[bank], bank)))
+            .collect();
+        for (row, bank) in flat_banks.iter_mut().enumerate() {
+            if self.failed[*bank] {
+                let Reverse((load, target)) = healthy.pop().expect("no healthy bank");
+                *bank = target;
+                healthy.push(Reverse((load + row_nnz(row), target)));
+            }
+        }
+        flat_banks
+    }
+}
+
+/// the rows moved by the faults and the cycles lost to them
+#[derive(Debug, Clone, Serialize)]
+pub struct FaultReport {
+    pub failed_banks: usize,
+    pub slow_banks: usize,
+    /// the rows of B stored in a failed bank by the row mapping
+    pub remapped_rows: usize,
+    pub remapped_nnz: usize,
+    pub healthy_cycles: f64,
+    pub degraded_cycles: f64,
+    /// `degraded_cycles / healthy_cycles - 1`
+    pub performance_loss: f64,
+}
+
+impl FaultReport {
+    pub fn new<N>(
+        mem_settings: &MemSettings,
+        matrix_b: &CsMat<N>,
+        healthy_cycles: f64,
+        degraded_cycles: f64,
+    ) -> Result<Self> {
+        let fault_map = FaultMap::new(mem_settings);
+        let healthy_settings = MemSettings {
+            faults: Default::default(),
+            ..mem_settings.clone()
+        };
+        let healthy_mapping = ResolvedRowMapping::new(&healthy_settings, matrix_b)?;
+        let (mut remapped_rows, mut remapped_nnz) = (0, 0);
+        for (row, row_vec) in matrix_b.outer_iterator().enumerate() {
+            let (bank, _) = get_bank_id_from_row_id(
+                row,
+                mem_settings.channels,
+                mem_settings.chips,
+                mem_settings.banks,
+                matrix_b.rows(),
+                &healthy_mapping,
+            );
+            if fault_map.is_failed(bank) {
+                remapped_rows += 1;
+                remapped_nnz += row_vec.nnz();
+            }
+        }
+        Ok(Self {
+            failed_banks: fault_map.failed_banks(),
+            slow_banks: fault_map.slow_banks(),
+            remapped_rows,
+            remapped_nnz,
+            healthy_cycles,
+            degraded_cycles,
+            performance_loss: degraded_cycles / healthy_cycles - 1.,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::fixtures::{ones_in_rows, small_settings};
+
+    fn settings(faults: Faults) -> MemSettings {
+        MemSettings {
+            faults,
+            ..small_settings(2, 2, 2)
+        }
+    }
+
+    #[test]
+    fn fault_map() {
+        let map = FaultMap::new(&settings(Faults {
+            failed_banks: vec![1],
+            failed_chips: vec![(1, 0)],
+            slow_banks: vec![SlowBank {
+                bank: 2,
+                slowdown: 2.,
+            }],
+            slow_chips: vec![SlowChip {
+                channel: 0,
+                chip: 1,
+                slowdown: 1.5,
+            }],
+        }));
+        assert!(map.is_failed(((0, 0), 1)));
+        assert!(map.is_failed(((1, 0), 0)) && map.is_failed(((1, 0), 1)));
+        assert!(!map.is_failed(((1, 1), 0)));
+        assert_eq!(map.failed_banks(), 3);
+        // the bank 2 is in the slow chip (0, 1)
+        assert_eq!(map.slowdown(((0, 1), 0)), 3.);
+        assert_eq!(map.slowdown(((0, 1), 1)), 1.5);
+        assert_eq!(map.slowdown(((1, 1), 1)), 1.);
+        assert_eq!(map.slow_banks(), 2);
+    }
+
+    #[test]
+    fn remap_to_least_loaded() {
+        let map = FaultMap::new(&MemSettings {
+            faults: Faults {
+                failed_banks: vec![0],
+                ..Default::default()
+            },
+            ..small_settings(1, 1, 4)
+        });
+        let matrix = ones_in_rows(&[2, 1, 3, 1, 1, 1]);
+        // the loads of the healthy banks are 4, 1, 1
+        let flat_banks = map.remap(vec![0, 1, 1, 2, 3, 0], &matrix);
+        assert_eq!(flat_banks, vec![2, 1, 1, 2, 3, 3]);
+    }
+
+    #[test]
+    fn row_mapping_avoids_failed_banks() {
+        let mem_settings = settings(Faults {
+            failed_chips: vec![(0, 0)],
+            ..Default::default()
+        });
+        let matrix = ones_in_rows(&[1; 32]);
+        let mapping = ResolvedRowMapping::new(&mem_settings, &matrix).unwrap();
+        let map = FaultMap::new(&mem_settings);
+        let banks = (0..32)
+            .map(|row| get_bank_id_from_row_id(row, 2, 2, 2, 32, &mapping).0)
+            .collect::<Vec<_>>();
+        assert!(banks.iter().all(|&bank| !map.is_failed(bank)));
+        let report = FaultReport::new(&mem_settings, &matrix, 100., 125.).unwrap();
+        // the interleaved mapping puts a quarter of the rows in the chip (0, 0)
+        assert_eq!(report.remapped_rows, 8);
+        assert_eq!(report.performance_loss, 0.25);
+    }
+}
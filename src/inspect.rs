@@ -0,0 +1,371 @@
+//! the `inspect` subcommand: characterize a workload for a `MemSettings` without running the simulation
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use eyre::{Context, Result};
+use itertools::Itertools;
+use serde::Serialize;
+use sprs::CsMat;
+
+use crate::{
+    args::InspectArgs,
+    csv_nodata::merge_sorted_into,
+    pim::{get_bank_id_from_row_id, ResolvedRowMapping},
+    reorder_calculator::{self, ReorderMode, ReorderReport},
+    replication::{Replication, ReplicationPlan, ReplicationReport},
+    settings::{EffectiveSettings, MemSettings},
+};
+
+/// the counts of some units (rows, banks...), with the numbers to spot the imbalance
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Distribution {
+    pub counts: Vec<usize>,
+    pub max: usize,
+    pub mean: f64,
+    /// max / mean, 1 is balanced
+    pub imbalance: f64,
+}
+
+impl Distribution {
+    pub fn new(counts: Vec<usize>) -> Self {
+        let max = counts.iter().cloned().max().unwrap_or(0);
+        let mean = counts.iter().sum::<usize>() as f64 / counts.len().max(1) as f64;
+        let imbalance = if mean == 0. { 1. } else { max as f64 / mean };
+        Self {
+            counts,
+            max,
+            mean,
+            imbalance,
+        }
+    }
+}
+
+/// the number of tasks (nonzeros of A) sent to each channel, chip and bank
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TaskDistribution {
+    pub channel: Distribution,
+    /// indexed by `channel * chips + chip`
+    pub chip: Distribution,
+    /// indexed by `(channel * chips + chip) * banks + bank`
+    pub bank: Distribution,
+    /// the chips with the same id in all channels are added together
+    pub chip_standalone: Distribution,
+    /// the banks with the same id in all chips are added together
+    pub bank_standalone: Distribution,
+}
+
+impl TaskDistribution {
+    /// the task of a nonzero `(row, col)` of A goes to the bank that stores the row `col` of B
+    pub fn new(
+        matrix_a: &CsMat<i32>,
+        num_rows: usize,
+        channels: usize,
+        chips: usize,
+        banks: usize,
+        row_mapping: &ResolvedRowMapping,
+    ) -> Self {
+        let mut channel_dist = vec![0; channels];
+        let mut chip_dist = vec![0; chips * channels];
+        let mut chip_standalone = vec![0; chips];
+        let mut bank_dist = vec![0; banks * chips * channels];
+        let mut bank_standalone = vec![0; banks];
+        for (_, (_row_id, col_id)) in matrix_a.iter() {
+            let (((channel, chip), bank), _) =
+                get_bank_id_from_row_id(col_id, channels, chips, banks, num_rows, row_mapping);
+            channel_dist[channel] += 1;
+            chip_dist[chip + (channel * chips)] += 1;
+            chip_standalone[chip] += 1;
+            bank_dist[bank + (channel * chips * banks) + (chip * banks)] += 1;
+            bank_standalone[bank] += 1;
+        }
+        Self {
+            channel: Distribution::new(channel_dist),
+            chip: Distribution::new(chip_dist),
+            bank: Distribution::new(bank_dist),
+            chip_standalone: Distribution::new(chip_standalone),
+            bank_standalone: Distribution::new(bank_standalone),
+        }
+    }
+}
+
+/// the lengths of rows or columns in power of two buckets
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct LengthHistogram {
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    /// (from, to, count), the lengths in `[from, to)`
+    pub buckets: Vec<(usize, usize, usize)>,
+}
+
+impl LengthHistogram {
+    pub fn new(lengths: impl IntoIterator<Item = usize>) -> Self {
+        let lengths = lengths.into_iter().collect_vec();
+        // 0 is the bucket of empty lines, otherwise `[2^(bucket - 1), 2^bucket)`
+        let bucket_of = |len: usize| (usize::BITS - len.leading_zeros()) as usize;
+        let mut counts = BTreeMap::new();
+        for len in &lengths {
+            *counts.entry(bucket_of(*len)).or_insert(0) += 1;
+        }
+        let buckets = counts
+            .into_iter()
+            .map(|(bucket, count)| match bucket {
+                0 => (0, 1, count),
+                _ => (1 << (bucket - 1), 1 << bucket, count),
+            })
+            .collect();
+        Self {
+            min: lengths.iter().cloned().min().unwrap_or(0),
+            max: lengths.iter().cloned().max().unwrap_or(0),
+            mean: lengths.iter().sum::<usize>() as f64 / lengths.len().max(1) as f64,
+            buckets,
+        }
+    }
+}
+
+/// the size of the partial sums sent out of each level, if every level merges all partial sums of a row.
+/// the real simulator merges in groups of `merger_size`, so these are lower bounds.
+#[derive(Debug, Clone, Serialize, PartialEq, Default)]
+pub struct PartialSumSizes {
+    /// the multiplications, the sum of the B rows selected by the nonzeros of A
+    pub products: usize,
+    pub bank: usize,
+    pub chip: usize,
+    pub channel: usize,
+    /// the nnz of A x B
+    pub output_nnz: usize,
+}
+
+type LevelId = (usize, usize, usize);
+
+/// merge the rows of each group into one partial sum
+fn merge_level(groups: BTreeMap<LevelId, Vec<&[usize]>>) -> BTreeMap<LevelId, Vec<usize>> {
+    groups
+        .into_iter()
+        .map(|(key, rows)| {
+            let mut merged = vec![];
+            merge_sorted_into(&rows, &mut merged);
+            (key, merged)
+        })
+        .collect()
+}
+
+/// group the partial sums of the lower level by the id of the upper level
+fn group(
+    sums: &BTreeMap<LevelId, Vec<usize>>,
+    upper: fn(&LevelId) -> LevelId,
+) -> BTreeMap<LevelId, Vec<&[usize]>> {
+    let mut groups: BTreeMap<_, Vec<&[usize]>> = BTreeMap::new();
+    for (key, sum) in sums {
+        groups.entry(upper(key)).or_default().push(sum.as_slice());
+    }
+    groups
+}
+
+/// estimate the partial sums of A x B by merging the selected rows of B level by level
+pub fn partial_sum_sizes(
+    matrix_a: &CsMat<i32>,
+    matrix_b: &CsMat<i32>,
+    mem_settings: &MemSettings,
+    row_mapping: &ResolvedRowMapping,
+) -> PartialSumSizes {
+    let (channels, chips, banks) = (
+        mem_settings.channels,
+        mem_settings.chips,
+        mem_settings.banks,
+    );
+    let b_rows = matrix_b
+        .outer_iterator()
+        .map(|row| row.indices().to_vec())
+        .collect_vec();
+    let mut sizes = PartialSumSizes::default();
+    for row in matrix_a.outer_iterator() {
+        // the B rows of this task grouped by bank
+        let mut bank_rows: BTreeMap<LevelId, Vec<&[usize]>> = BTreeMap::new();
+        for &col in row.indices() {
+            let (((channel, chip), bank), _) =
+                get_bank_id_from_row_id(col, channels, chips, banks, matrix_b.rows(), row_mapping);
+            sizes.products += b_rows[col].len();
+            bank_rows
+                .entry((channel, chip, bank))
+                .or_default()
+                .push(&b_rows[col]);
+        }
+        let bank_sums = merge_level(bank_rows);
+        sizes.bank += bank_sums.values().map(Vec::len).sum::<usize>();
+        let chip_sums = merge_level(group(&bank_sums, |k| (k.0, k.1, 0)));
+        sizes.chip += chip_sums.values().map(Vec::len).sum::<usize>();
+        let channel_sums = merge_level(group(&chip_sums, |k| (k.0, 0, 0)));
+        sizes.channel += channel_sums.values().map(Vec::len).sum::<usize>();
+        let output = merge_level(group(&channel_sums, |_| (0, 0, 0)));
+        sizes.output_nnz += output.values().map(Vec::len).sum::<usize>();
+    }
+    sizes
+}
+
+/// the characterization of one matrix, A is the matrix and B is its transpose, the same as the simulation
+#[derive(Debug, Clone, Serialize)]
+pub struct Inspection {
+    pub file: PathBuf,
+    pub rows: usize,
+    pub cols: usize,
+    pub nnz: usize,
+    pub row_lengths: LengthHistogram,
+    pub col_lengths: LengthHistogram,
+    pub tasks: TaskDistribution,
+    pub partial_sums: PartialSumSizes,
+    /// the reordering applied before the inspection, if any
+    pub reorder: Option<ReorderReport>,
+    /// the copies of the hot rows of B, if `replication` is set
+    pub replication: Option<ReplicationReport>,
+}
+
+impl Inspection {
+    pub fn new(file: &Path, matrix_a: CsMat<i32>, mem_settings: &MemSettings) -> Result<Self> {
+        let matrix_b = matrix_a.transpose_view().to_csr();
+        mem_settings
+            .validate_with_rows(matrix_b.rows())
+            .wrap_err(format!("{file:?} does not fit the settings"))?;
+        let row_mapping = ResolvedRowMapping::new(mem_settings, &matrix_b)?;
+        let tasks = TaskDistribution::new(
+            &matrix_a,
+            matrix_b.rows(),
+            mem_settings.channels,
+            mem_settings.chips,
+            mem_settings.banks,
+            &row_mapping,
+        );
+        let partial_sums = partial_sum_sizes(&matrix_a, &matrix_b, mem_settings, &row_mapping);
+        let replication = (mem_settings.replication != Replication::None).then(|| {
+            ReplicationPlan::new(mem_settings, &matrix_a, &row_mapping).report(
+                &matrix_a,
+                &matrix_b,
+                &row_mapping,
+            )
+        });
+        Ok(Self {
+            file: file.to_path_buf(),
+            rows: matrix_a.rows(),
+            cols: matrix_a.cols(),
+            nnz: matrix_a.nnz(),
+            row_lengths: LengthHistogram::new(matrix_a.outer_iterator().map(|r| r.nnz())),
+            col_lengths: LengthHistogram::new(matrix_b.outer_iterator().map(|r| r.nnz())),
+            tasks,
+            partial_sums,
+            reorder: None,
+            replication,
+        })
+    }
+}
+
+/// run the `inspect` subcommand, the result is printed as json
+pub fn main(args: &InspectArgs) -> Result<()> {
+    let effective = EffectiveSettings::load(&args.config.layers())?;
+    let settings = &effective.settings;
+    settings.mem_settings.validate()?;
+    // the matrices are inspected after the same reordering as the simulation
+    let inspect = |file: &Path, matrix: CsMat<i32>| -> Result<Inspection> {
+        match settings.reorder {
+            ReorderMode::None => Inspection::new(file, matrix, &settings.mem_settings),
+            mode => {
+                let (matrix, report) = reorder_calculator::reorder(&matrix, mode)?;
+                let mut inspection = Inspection::new(file, matrix, &settings.mem_settings)?;
+                inspection.reorder = Some(report);
+                Ok(inspection)
+            }
+        }
+    };
+    let mut matrices = vec![];
+    let files = if args.matrices.is_empty() {
+        &settings.mtx_files
+    } else {
+        &args.matrices
+    };
+    for file in files {
+        let matrix: CsMat<i32> = sprs::io::read_matrix_market(file)
+            .wrap_err(format!("{:?} is error!", file))?
+            .to_csr();
+        matrices.push(inspect(file.as_path(), matrix)?);
+    }
+    if args.matrices.is_empty() {
+        for workload in &settings.workloads {
+            let matrix = workload.generate()?;
+            let name = PathBuf::from(format!("{}.mtx", workload.name()));
+            matrices.push(inspect(name.as_path(), matrix)?);
+        }
+    }
+    let output = serde_json::to_string_pretty(&matrices)?;
+    match &args.output {
+        Some(path) => std::fs::write(path, output).wrap_err(format!("fail to write {path:?}"))?,
+        None => println!("{output}"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        settings::RowMapping,
+        utils::fixtures::{ones_at, small_settings},
+    };
+
+    use super::*;
+
+    /// a 4x4 matrix: row 0 has 3 nonzeros, the others have 1
+    fn matrix() -> CsMat<i32> {
+        ones_at((4, 4), [(0, 0), (0, 1), (0, 3), (1, 1), (2, 2), (3, 3)])
+    }
+
+    fn settings() -> MemSettings {
+        MemSettings {
+            row_mapping: RowMapping::Chunk,
+            ..small_settings(1, 2, 2)
+        }
+    }
+
+    #[test]
+    fn histogram_test() {
+        let histogram = LengthHistogram::new([0, 1, 3, 3, 8]);
+        assert_eq!(
+            histogram.buckets,
+            vec![(0, 1, 1), (1, 2, 1), (2, 4, 2), (8, 16, 1)]
+        );
+        assert_eq!(histogram.max, 8);
+        assert_eq!(histogram.mean, 3.);
+    }
+
+    #[test]
+    fn inspect_test() {
+        let inspection = Inspection::new(Path::new("test.mtx"), matrix(), &settings()).unwrap();
+        assert_eq!(inspection.nnz, 6);
+        assert_eq!(inspection.row_lengths.max, 3);
+        // one row of B per bank, the column 3 and 1 have 2 nonzeros
+        assert_eq!(inspection.tasks.bank.counts, vec![1, 2, 1, 2]);
+        assert_eq!(inspection.tasks.chip.counts, vec![3, 3]);
+        assert_eq!(inspection.tasks.bank.imbalance, 2. / 1.5);
+
+        // B = A^T, C = A x A^T
+        let partial_sums = &inspection.partial_sums;
+        // the columns of A have 1, 2, 1, 2 nonzeros
+        assert_eq!(partial_sums.products, (1 + 2 + 2) + 2 + 1 + 2);
+        let a = matrix();
+        let c = &a * &a.transpose_view().to_csr();
+        assert_eq!(partial_sums.output_nnz, c.nnz());
+        assert!(partial_sums.bank >= partial_sums.chip);
+        assert!(partial_sums.chip >= partial_sums.channel);
+        assert!(partial_sums.channel >= partial_sums.output_nnz);
+    }
+
+    #[test]
+    fn too_few_rows() {
+        // 4 rows of B can not be chunked over 8 banks
+        let settings = MemSettings {
+            chips: 4,
+            ..settings()
+        };
+        assert!(Inspection::new(Path::new("test.mtx"), matrix(), &settings).is_err());
+    }
+}
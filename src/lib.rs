@@ -1,13 +1,21 @@
+pub mod analyze;
 pub mod args;
 pub mod bsr;
 pub mod bsr_row_builder;
 pub mod csv_nodata;
+pub mod fault;
+pub mod inspect;
 pub mod non_pim;
 pub mod pim;
+#[cfg(feature = "python")]
+pub mod python;
 pub mod reorder_calculator;
 pub mod reorder_system;
+pub mod replication;
 pub mod result;
 pub mod run_main;
+pub mod run_output;
+pub mod server;
 pub mod settings;
 pub mod sim;
 pub mod two_matrix;
@@ -24,6 +32,7 @@ use wasm_bindgen::prelude::*;
 use crate::{
     run::run_exp_csr,
     settings::{BufferMode, MemSettings, RowMapping},
+    two_matrix::TwoMatrix,
 };
 
 pub(crate) fn init_logger() {
@@ -54,14 +63,7 @@ pub async fn run1(name: String) -> Result<String, JsValue> {
         .await
         .map_err(JsError::from)?;
 
-    let mut filebuf = BufReader::new(res.as_bytes());
-    let tri: TriMat<i32> =
-        sprs::io::read_matrix_market_from_bufread(&mut filebuf).map_err(JsError::from)?;
-    let path = Path::new(&name);
-
-    let mut full_result = Results { all: vec![] };
-    let mut ok_list = vec![];
-    let mut err_list = vec![];
+    let csr = read_mtx_text(&res)?;
     let mem_settings = MemSettings {
         row_size: 512,
         banks: 8,
@@ -85,15 +87,63 @@ pub async fn run1(name: String) -> Result<String, JsValue> {
         dimm_buffer_lines: 8,
         channel_buffer_lines: 8,
         chip_buffer_lines: 8,
+        dimm_buffer_policy: Default::default(),
+        channel_buffer_policy: Default::default(),
+        chip_buffer_policy: Default::default(),
+        dimm_merger_policy: Default::default(),
+        channel_merger_policy: Default::default(),
+        chip_merger_policy: Default::default(),
+        spill: Default::default(),
+        flow_control: Default::default(),
+        queue_sizes: Default::default(),
         buffer_mode: BufferMode::BindMerger,
         sender_store_size: 4,
         task_scheduler_mode: Default::default(),
         task_scheduler_chunk_size: 4,
+        seed: 0,
+        replication: Default::default(),
+        faults: Default::default(),
+        overrides: vec![],
+        job_arbitration: Default::default(),
+        job_sharing: Default::default(),
+        bank_cache_size: 0,
+        bank_cache_policy: Default::default(),
+    };
+    run_analytical_csr(Path::new(&name), &csr, &mem_settings)
+}
+
+fn read_mtx_text(mtx: &str) -> Result<CsMat<i32>, JsValue> {
+    let mut filebuf = BufReader::new(mtx.as_bytes());
+    let tri: TriMat<i32> =
+        sprs::io::read_matrix_market_from_bufread(&mut filebuf).map_err(JsError::from)?;
+    Ok(tri.to_csr())
+}
+
+/// the json of the fields of `mem_settings` changed from the defaults, the defaults if empty.
+/// they are checked against `csr`, whose transpose is the B of the runs
+fn read_settings_json(settings: &str, csr: &CsMat<i32>) -> Result<MemSettings, JsValue> {
+    let to_js = |e: eyre::Report| JsValue::from_str(&format!("{e:?}"));
+    let mem_settings = if settings.trim().is_empty() {
+        MemSettings::default()
+    } else {
+        let values: serde_json::Value = serde_json::from_str(settings).map_err(JsError::from)?;
+        MemSettings::default().merged(values).map_err(to_js)?
     };
-    let csr: CsMat<_> = tri.to_csr();
+    mem_settings.validate_with_rows(csr.cols()).map_err(to_js)?;
+    Ok(mem_settings)
+}
 
-    run_1d_c_unroll_buf!(path;&csr;&mem_settings;full_result;ok_list;err_list; run_exp_csr; 64,128,256,512,1024,2048);
-    run_2d_unroll_buf!(path;&csr;&mem_settings;full_result;ok_list;err_list; run_exp_csr; (2,32),(4,16),(8,8),(2,64),(4,32),(8,16),(2,128),(4,64),(8,32),(16,16),(2,256),(4,128),(8,64),(16,32),
+/// the analytical model for all the bsr sizes, as json
+fn run_analytical_csr(
+    path: &Path,
+    csr: &CsMat<i32>,
+    mem_settings: &MemSettings,
+) -> Result<String, JsValue> {
+    let mut full_result = Results { all: vec![] };
+    let mut ok_list = vec![];
+    let mut err_list = vec![];
+    run_1d_c_unroll_buf!(path;csr;mem_settings;full_result;ok_list;err_list; run_exp_csr; 64,128,256,512,1024,2048);
+    run_2d_unroll_buf!(path;csr;mem_settings;full_result;ok_list;err_list; run_exp_csr; (2,32),(4,16),(8,8),(2,64),(4,32),(8,16),(2,128),(4,64),(8,32),(16,16),(2,256),(4,128),(8,64),(16,32),
         (2,512),(4,256),(8,128),(16,64),(32,32), (2,1024),(4,512),(8,256),(16,128),(32,64));
     if !err_list.is_empty() {
         return Err(JsValue::from_str(&format!("{:?}", err_list)));
@@ -108,6 +158,26 @@ pub async fn run1(name: String) -> Result<String, JsValue> {
     serde_json::to_string_pretty(&combined_result).map_err(|e| JsValue::from_str(&e.to_string()))
 }
 
+/// the analytical model of `run1` on a local matrix market text, `name` only labels the results.
+/// `settings` is the json of the fields of `mem_settings` changed from the defaults
+#[wasm_bindgen]
+pub fn run_analytical(name: String, mtx: String, settings: String) -> Result<String, JsValue> {
+    let csr = read_mtx_text(&mtx)?;
+    let mem_settings = read_settings_json(&settings, &csr)?;
+    run_analytical_csr(Path::new(&name), &csr, &mem_settings)
+}
+
+/// the event-driven simulation of A x A^T on a local matrix market text, the `SimReport` as json
+#[wasm_bindgen]
+pub fn run_simulator(mtx: String, settings: String) -> Result<String, JsValue> {
+    let csr = read_mtx_text(&mtx)?;
+    let mem_settings = read_settings_json(&settings, &csr)?;
+    let trans_pose = csr.transpose_view().to_csr();
+    let report = sim::Simulator::run(&mem_settings, TwoMatrix::new(csr, trans_pose))
+        .map_err(|e| JsValue::from_str(&format!("{e:?}")))?;
+    serde_json::to_string_pretty(&report).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
 #[cfg(test)]
 mod test {
     use eyre::Result;
@@ -138,6 +208,25 @@ mod test {
         debug!("{:?}", bsr);
         Ok(())
     }
+    #[test]
+    fn test_local_text() -> Result<()> {
+        let mtx = std::fs::read_to_string("mtx/test.mtx")?;
+        let csr = super::read_mtx_text(&mtx).unwrap();
+        assert_eq!(
+            csr.nnz(),
+            sprs::io::read_matrix_market::<i32, usize, _>("mtx/test.mtx")?.nnz()
+        );
+        let settings =
+            super::read_settings_json(r#"{"banks": 4, "spill": {"region_lines": 8}}"#, &csr)
+                .unwrap();
+        assert_eq!(settings.banks, 4);
+        assert_eq!(settings.spill.region_lines, 8);
+        let report: serde_json::Value =
+            serde_json::from_str(&super::run_simulator(mtx, String::new()).unwrap())?;
+        assert!(report["cycles"].as_f64().unwrap() > 0.);
+        Ok(())
+    }
+
     #[wasm_bindgen_test]
     fn test_wasm() {}
 }
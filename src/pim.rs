@@ -2,20 +2,25 @@
 //! this module define the trait Pim.
 
 use std::{
-    collections::BTreeMap,
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
     fmt::Debug,
     iter::Sum,
     ops::{Deref, Mul},
+    path::Path,
+    rc::Rc,
 };
 
+use eyre::{bail, ensure, Context, Result};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use sprs::SpIndex;
+use sprs::{CsMat, SpIndex};
 use tracing::debug;
 
 use crate::{
     csv_nodata::CsVecNodata,
-    settings::{MemSettings, RealRowMapping},
+    fault::FaultMap,
+    settings::{MemSettings, RowMapping},
     sim::id_translation::BankID,
 };
 
@@ -206,10 +211,19 @@ impl From<(usize, usize)> for MergeCycle {
 /// for a matrix, or two matrix to implement this trait, it can get the number of cycles to perform matrix multiplication in this matrix.
 pub trait Pim {
     /// the cycles to read memory rows. and the data read from memory
-    fn mem_rows(&self, mem_settings: &MemSettings) -> Vec<(usize, usize)>;
+    /// - `row_mapping` is `ResolvedRowMapping::new` of the same `mem_settings`
+    fn mem_rows(
+        &self,
+        mem_settings: &MemSettings,
+        row_mapping: &ResolvedRowMapping,
+    ) -> Vec<(usize, usize)>;
     /// the cycles to perform merge in bank level.
     /// output: (merge cycle for each bank  , partial sum for each bank)
-    fn bank_merge(&self, mem_settings: &MemSettings) -> (Vec<MergeCycle>, Vec<PartialSum<usize>>);
+    fn bank_merge(
+        &self,
+        mem_settings: &MemSettings,
+        row_mapping: &ResolvedRowMapping,
+    ) -> (Vec<MergeCycle>, Vec<PartialSum<usize>>);
     /// the cycles to fetch partial sum from bank
     /// - input bank_merge_result will have the partial sum for each bank
     /// - output: will have cycles for (each bank sent,each chip received)
@@ -275,6 +289,120 @@ pub fn get_bank_id_from_flat_bank_id(
 
     ((channel_id, chip_id), bank_id)
 }
+/// the inverse of `get_bank_id_from_flat_bank_id`
+pub fn get_flat_bank_id(((channel, chip), bank): BankID, chips: usize, banks: usize) -> usize {
+    (channel * chips + chip) * banks + bank
+}
+
+/// the row mapping of one matrix B, the policies that depend on the rows are resolved into a table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolvedRowMapping {
+    Chunk,
+    Interleaved(usize),
+    Xor(usize),
+    /// the bank and the row id in bank of each row
+    Table(Vec<(BankID, usize)>),
+}
+
+impl ResolvedRowMapping {
+    pub fn new<N>(mem_settings: &MemSettings, matrix_b: &CsMat<N>) -> Result<Self> {
+        let (channels, chips, banks) = (
+            mem_settings.channels,
+            mem_settings.chips,
+            mem_settings.banks,
+        );
+        let num_banks = channels * chips * banks;
+        let fault_map = FaultMap::new(mem_settings);
+        let flat_banks = match &mem_settings.row_mapping {
+            RowMapping::Chunk | RowMapping::Interleaved | RowMapping::Xor => {
+                let mapping = match mem_settings.row_mapping {
+                    RowMapping::Chunk => Self::Chunk,
+                    RowMapping::Interleaved => Self::Interleaved(mem_settings.interleaved_chunk),
+                    _ => Self::Xor(mem_settings.interleaved_chunk),
+                };
+                if !fault_map.has_failed() {
+                    return Ok(mapping);
+                }
+                (0..matrix_b.rows())
+                    .map(|row| {
+                        let (bank, _) = get_bank_id_from_row_id(
+                            row,
+                            channels,
+                            chips,
+                            banks,
+                            matrix_b.rows(),
+                            &mapping,
+                        );
+                        get_flat_bank_id(bank, chips, banks)
+                    })
+                    .collect()
+            }
+            RowMapping::NnzBalanced => {
+                // longest processing time first: the longest row goes to the least loaded bank
+                let mut loads: BinaryHeap<_> =
+                    (0..num_banks).map(|bank| Reverse((0, bank))).collect();
+                let mut flat_banks = vec![0; matrix_b.rows()];
+                let rows = matrix_b
+                    .outer_iterator()
+                    .enumerate()
+                    .map(|(row_id, row)| (row_id, row.nnz()))
+                    .sorted_by_key(|&(_, nnz)| Reverse(nnz));
+                for (row_id, nnz) in rows {
+                    let Reverse((load, bank)) = loads.pop().unwrap();
+                    flat_banks[row_id] = bank;
+                    loads.push(Reverse((load + nnz, bank)));
+                }
+                flat_banks
+            }
+            RowMapping::Explicit(path) => read_explicit_mapping(path, matrix_b.rows(), num_banks)?,
+        };
+        // the rows of the failed banks are moved before numbering the rows in each bank
+        let flat_banks = if fault_map.has_failed() {
+            fault_map.remap(flat_banks, matrix_b)
+        } else {
+            flat_banks
+        };
+        // the rows in one bank are stored in the order of the row id
+        let mut next_row_in_bank = vec![0; num_banks];
+        let table = flat_banks
+            .into_iter()
+            .map(|flat_bank| {
+                let row_in_bank = next_row_in_bank[flat_bank];
+                next_row_in_bank[flat_bank] += 1;
+                (
+                    get_bank_id_from_flat_bank_id(flat_bank, channels, chips, banks),
+                    row_in_bank,
+                )
+            })
+            .collect();
+        Ok(Self::Table(table))
+    }
+}
+
+/// the flat bank id of each of `num_rows` rows in the file of `RowMapping::Explicit`
+pub fn read_explicit_mapping(path: &Path, num_rows: usize, num_banks: usize) -> Result<Vec<usize>> {
+    let content =
+        std::fs::read_to_string(path).wrap_err(format!("fail to read the row mapping {path:?}"))?;
+    let flat_banks: Vec<usize> = content
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.parse()
+                .wrap_err(format!("invalid bank id {line:?} in {path:?}"))
+        })
+        .collect::<Result<_>>()?;
+    ensure!(
+        flat_banks.len() == num_rows,
+        "the row mapping {path:?} has {} rows, the matrix has {num_rows}",
+        flat_banks.len(),
+    );
+    if let Some(bank) = flat_banks.iter().find(|&&bank| bank >= num_banks) {
+        bail!("the bank {bank} in {path:?} is out of range, there are {num_banks} banks");
+    }
+    Ok(flat_banks)
+}
+
 /// return (BankID, row_id in bank)
 pub fn get_bank_id_from_row_id(
     row_id: usize,
@@ -282,11 +410,11 @@ pub fn get_bank_id_from_row_id(
     chips: usize,
     banks: usize,
     num_rows: usize,
-    row_mapping: &RealRowMapping,
+    row_mapping: &ResolvedRowMapping,
 ) -> (BankID, usize) {
     let num_banks = banks * chips * channels;
     match row_mapping {
-        crate::settings::RealRowMapping::Chunk => {
+        ResolvedRowMapping::Chunk => {
             let rows_per_bank = num_rows / num_banks;
 
             let bank_id = if rows_per_bank == 0 {
@@ -309,7 +437,7 @@ pub fn get_bank_id_from_row_id(
                 )
             }
         }
-        crate::settings::RealRowMapping::Interleaved(chunk_size) => {
+        ResolvedRowMapping::Interleaved(chunk_size) => {
             let row_id = row_id / chunk_size;
             let channel_id = row_id % channels;
             let row_id = row_id / channels;
@@ -319,6 +447,22 @@ pub fn get_bank_id_from_row_id(
             let row_id = row_id / banks;
             (((channel_id, chip_id), bank_id), row_id)
         }
+        ResolvedRowMapping::Xor(chunk_size) => {
+            let chunk_id = row_id / chunk_size;
+            let mut hash = 0;
+            let mut digits = chunk_id;
+            while digits > 0 {
+                hash ^= digits % num_banks;
+                digits /= num_banks;
+            }
+            // the same order as `Interleaved`, the channel changes first
+            let hash = hash % num_banks;
+            let channel_id = hash % channels;
+            let chip_id = hash / channels % chips;
+            let bank_id = hash / channels / chips;
+            (((channel_id, chip_id), bank_id), chunk_id / num_banks)
+        }
+        ResolvedRowMapping::Table(table) => table[row_id],
     }
 }
 
@@ -350,11 +494,12 @@ where
 }
 /// - merget a list of tasks into one patrial sum
 /// - merger_size: the number of merger heads
+/// - the rows are shared, only the merged rows are new vectors
 /// - output: (merger_cycle, add_cycle, partial_sum)
 pub fn merge_rows_into_one(
-    tasks: Vec<CsVecNodata<usize>>,
+    tasks: Vec<Rc<CsVecNodata<usize>>>,
     merger_size: usize,
-) -> (usize, usize, CsVecNodata<usize>) {
+) -> (usize, usize, Rc<CsVecNodata<usize>>) {
     let mut tasks = tasks;
     let mut merge_cycles = 0usize;
     let mut add_cycles = 0usize;
@@ -368,14 +513,11 @@ pub fn merge_rows_into_one(
                 if x.len() == 1 {
                     ((0, 0), x.pop().unwrap())
                 } else {
-                    let (old_len, result_vec) = x
-                        .into_iter()
-                        .fold((0, CsVecNodata::default()), |(total_len, cal_vec), y| {
-                            (total_len + y.len(), cal_vec + y)
-                        });
+                    let old_len = x.iter().map(|y| y.len()).sum::<usize>();
+                    let result_vec = CsVecNodata::merge_all(x.iter().map(|y| &**y));
                     let new_len = result_vec.len();
                     // add cycle, merge cycle and result vec
-                    ((old_len - new_len, old_len), result_vec)
+                    ((old_len - new_len, old_len), Rc::new(result_vec))
                 }
             })
             .collect_vec();
@@ -465,10 +607,8 @@ where
                             if x.len() == 1 {
                                 ((0, 0), x.pop().unwrap())
                             } else {
-                                let (old_len, result_vec) = x.into_iter().fold(
-                                    (0, CsVecNodata::default()),
-                                    |(total_len, cal_vec), y| (total_len + y.len(), cal_vec + y),
-                                );
+                                let old_len = x.iter().map(|y| y.len()).sum::<usize>();
+                                let result_vec = CsVecNodata::merge_all(&x);
                                 let new_len = result_vec.len();
                                 // add cycle, merge cycle and result vec
                                 ((old_len - new_len, old_len), result_vec)
@@ -1020,6 +1160,7 @@ pub fn internal_merge(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::fixtures::{ones_in_rows, small_settings};
     #[test]
     fn test_slice_iter() {
         let a = [[1, 2], [2, 3], [3, 4]];
@@ -1030,4 +1171,61 @@ mod tests {
         });
         println!("{:?}", c);
     }
+
+    fn resolve(row_mapping: RowMapping, lens: &[usize]) -> Result<ResolvedRowMapping> {
+        let mem_settings = MemSettings {
+            row_mapping,
+            interleaved_chunk: 1,
+            ..small_settings(1, 1, 4)
+        };
+        ResolvedRowMapping::new(&mem_settings, &ones_in_rows(lens))
+    }
+
+    #[test]
+    fn xor_mapping_spreads_strides() {
+        let xor = resolve(RowMapping::Xor, &[1; 16]).unwrap();
+        let interleaved = resolve(RowMapping::Interleaved, &[1; 16]).unwrap();
+        let banks_of = |mapping: &ResolvedRowMapping| {
+            [0, 4, 8, 12].map(|row| get_bank_id_from_row_id(row, 1, 1, 4, 16, mapping).0 .1)
+        };
+        assert_eq!(banks_of(&interleaved), [0, 0, 0, 0]);
+        assert_eq!(banks_of(&xor), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn nnz_balanced_mapping() {
+        let mapping = resolve(RowMapping::NnzBalanced, &[8, 4, 4, 2, 2, 2, 2, 1]).unwrap();
+        let mut loads = vec![0; 4];
+        for (row, len) in [8, 4, 4, 2, 2, 2, 2, 1].into_iter().enumerate() {
+            let (((_, _), bank), _) = get_bank_id_from_row_id(row, 1, 1, 4, 8, &mapping);
+            loads[bank] += len;
+        }
+        assert_eq!(loads, vec![8, 6, 6, 5]);
+        // the rows in the same bank are numbered by row id, the rows 1 and 5 are in bank 1
+        assert_eq!(
+            get_bank_id_from_row_id(5, 1, 1, 4, 8, &mapping),
+            (((0, 0), 1), 1)
+        );
+    }
+
+    #[test]
+    fn explicit_mapping() {
+        let path = std::env::temp_dir().join(format!(
+            "spmm_pim_explicit_mapping_{}.txt",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "# flat bank id of each row\n3\n0 # the second row\n\n3\n",
+        )
+        .unwrap();
+        let mapping = resolve(RowMapping::Explicit(path.clone()), &[1, 1, 1]).unwrap();
+        assert_eq!(
+            mapping,
+            ResolvedRowMapping::Table(vec![(((0, 0), 3), 0), (((0, 0), 0), 0), (((0, 0), 3), 1)])
+        );
+        assert!(resolve(RowMapping::Explicit(path.clone()), &[1, 1]).is_err());
+        std::fs::write(&path, "4\n").unwrap();
+        assert!(resolve(RowMapping::Explicit(path), &[1]).is_err());
+    }
 }
@@ -0,0 +1,469 @@
+//! the python module `spmm_pim`, build it with `maturin develop --release`, see `pyproject.toml`.
+//! the settings and the results are converted by serde, so they are the same dicts as the json files of the cli
+//! ```python
+//! import scipy.io, spmm_pim
+//! csr = scipy.io.mmread("mtx/bfwa62.mtx").tocsr()
+//! matrix = spmm_pim.Matrix.from_csr(csr.shape, csr.indptr, csr.indices, csr.data)
+//! settings = spmm_pim.MemSettings({"banks": 4, "row_mapping": "Chunk"})
+//! result = spmm_pim.simulate(settings, matrix)
+//! print(result["cycles"], result["merger_stats"]["policies"])
+//! # one step of the analytical model with the partial sums of the level below
+//! model = spmm_pim.PimModel(settings, matrix)
+//! cycles, partial_sums = model.bank_merge()
+//! print(model.chip_fetch_data(partial_sums), partial_sums.sizes())
+//! ```
+
+use std::fmt::Display;
+
+use pyo3::{exceptions::PyValueError, prelude::*};
+use pythonize::{depythonize, pythonize};
+use serde::Serialize;
+use sprs::{CsMat, TriMat};
+
+use crate::{
+    pim::{MergeCycle, PartialSum, Pim, ResolvedRowMapping},
+    settings::MemSettings,
+    sim::Simulator,
+    two_matrix::TwoMatrix,
+};
+
+fn value_error(e: impl Display) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+/// the memory settings, the missing fields are the defaults like in the toml configs
+#[pyclass(name = "MemSettings")]
+#[derive(Clone)]
+pub struct PyMemSettings {
+    inner: MemSettings,
+}
+
+#[pymethods]
+impl PyMemSettings {
+    /// `values` is a dict of the fields of `mem_settings`, like `{"banks": 4, "spill": {"region_lines": 8}}`
+    #[new]
+    fn new(values: Option<&PyAny>) -> PyResult<Self> {
+        let settings = Self {
+            inner: MemSettings::default(),
+        };
+        settings.updated(values)
+    }
+
+    #[staticmethod]
+    fn from_toml(content: &str) -> PyResult<Self> {
+        let inner: MemSettings = toml::from_str(content).map_err(value_error)?;
+        inner.validate().map_err(value_error)?;
+        Ok(Self { inner })
+    }
+
+    /// a copy with the fields of `values` changed, for the sweeps of the design space
+    fn updated(&self, values: Option<&PyAny>) -> PyResult<Self> {
+        let inner = match values {
+            Some(values) => {
+                let values: serde_json::Value = depythonize(values).map_err(value_error)?;
+                self.inner.merged(values).map_err(value_error)?
+            }
+            None => self.inner.clone(),
+        };
+        inner.validate().map_err(value_error)?;
+        Ok(Self { inner })
+    }
+
+    fn to_dict(&self, py: Python) -> PyResult<PyObject> {
+        pythonize(py, &self.inner).map_err(value_error)
+    }
+
+    fn to_toml(&self) -> PyResult<String> {
+        self.inner.to_toml().map_err(value_error)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.inner)
+    }
+}
+
+/// a sparse matrix, only the pattern changes the cycles, the values are rounded to integers
+#[pyclass(name = "Matrix")]
+#[derive(Clone)]
+pub struct PyMatrix {
+    csr: CsMat<i32>,
+}
+
+/// the values of the nonzeros, all ones if not given
+fn values(data: Option<Vec<f64>>, nnz: usize) -> Vec<i32> {
+    data.map_or_else(
+        || vec![1; nnz],
+        |data| data.into_iter().map(|value| value.round() as i32).collect(),
+    )
+}
+
+#[pymethods]
+impl PyMatrix {
+    /// the arrays of `scipy.sparse.csr_matrix`
+    #[staticmethod]
+    fn from_csr(
+        shape: (usize, usize),
+        indptr: Vec<usize>,
+        indices: Vec<usize>,
+        data: Option<Vec<f64>>,
+    ) -> PyResult<Self> {
+        let data = values(data, indices.len());
+        let csr = CsMat::try_new(shape, indptr, indices, data).map_err(|(.., e)| value_error(e))?;
+        Ok(Self { csr })
+    }
+
+    /// the arrays of `scipy.sparse.coo_matrix`, the duplicated entries are summed
+    #[staticmethod]
+    fn from_coo(
+        shape: (usize, usize),
+        row: Vec<usize>,
+        col: Vec<usize>,
+        data: Option<Vec<f64>>,
+    ) -> PyResult<Self> {
+        if row.len() != col.len()
+            || row
+                .iter()
+                .zip(&col)
+                .any(|(&r, &c)| r >= shape.0 || c >= shape.1)
+        {
+            return Err(value_error(format!(
+                "the row and col should have the same length and be in the shape {shape:?}"
+            )));
+        }
+        let data = values(data, row.len());
+        if data.len() != row.len() {
+            return Err(value_error("the data should have one value per entry"));
+        }
+        let tri = TriMat::from_triplets(shape, row, col, data);
+        Ok(Self { csr: tri.to_csr() })
+    }
+
+    #[staticmethod]
+    fn from_mtx(path: &str) -> PyResult<Self> {
+        let tri: TriMat<i32> = sprs::io::read_matrix_market(path).map_err(value_error)?;
+        Ok(Self { csr: tri.to_csr() })
+    }
+
+    #[getter]
+    fn shape(&self) -> (usize, usize) {
+        self.csr.shape()
+    }
+
+    #[getter]
+    fn nnz(&self) -> usize {
+        self.csr.nnz()
+    }
+
+    /// `(shape, indptr, indices, data)`, `scipy.sparse.csr_matrix((data, indices, indptr), shape)` builds it back
+    fn to_csr(&self) -> ((usize, usize), Vec<usize>, Vec<usize>, Vec<i32>) {
+        (
+            self.csr.shape(),
+            self.csr.indptr().to_proper().to_vec(),
+            self.csr.indices().to_vec(),
+            self.csr.data().to_vec(),
+        )
+    }
+
+    fn transpose(&self) -> Self {
+        Self {
+            csr: self.csr.transpose_view().to_csr(),
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Matrix(shape={:?}, nnz={})",
+            self.csr.shape(),
+            self.csr.nnz()
+        )
+    }
+}
+
+/// the two matrices of a multiplication, B is A^T if not given like the cli
+fn two_matrix(a: &PyMatrix, b: Option<&PyMatrix>) -> PyResult<TwoMatrix<i32, i32>> {
+    let b = b.map_or_else(|| a.csr.transpose_view().to_csr(), |b| b.csr.clone());
+    if a.csr.cols() != b.rows() {
+        return Err(value_error(format!(
+            "A {:?} and B {:?} can not be multiplied",
+            a.csr.shape(),
+            b.shape()
+        )));
+    }
+    Ok(TwoMatrix::new(a.csr.clone(), b))
+}
+
+/// simulate A x B cycle by cycle, the stats are the dicts of the json files of the cli
+#[pyfunction]
+fn simulate(
+    py: Python,
+    settings: PyRef<PyMemSettings>,
+    a: PyRef<PyMatrix>,
+    b: Option<PyRef<PyMatrix>>,
+) -> PyResult<PyObject> {
+    let report = Simulator::run(&settings.inner, two_matrix(&a, b.as_deref())?)
+        .map_err(|e| value_error(format!("{e:?}")))?;
+    pythonize(py, &report).map_err(value_error)
+}
+
+#[derive(Serialize)]
+struct PimSteps {
+    /// `Pim::mem_rows` of each bank
+    mem_rows: Vec<(usize, usize)>,
+    bank_merge: Vec<MergeCycle>,
+    /// the cycles of each bank to send and of each chip to receive
+    chip_fetch_data: (Vec<usize>, Vec<usize>),
+    chip_merge: Vec<MergeCycle>,
+    channel_fetch_data: (Vec<usize>, Vec<usize>),
+    channel_merge: Vec<MergeCycle>,
+    dimm_fetch_data: (Vec<usize>, usize),
+    dimm_merge: MergeCycle,
+    write_result: usize,
+}
+
+/// the partial sums of each unit of a level, the output of a merge step and the input of the steps above it
+#[pyclass(name = "PartialSums")]
+#[derive(Clone)]
+pub struct PyPartialSums {
+    inner: Vec<PartialSum<usize>>,
+}
+
+#[pymethods]
+impl PyPartialSums {
+    fn __len__(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// the `(target_row, nnz)` of each partial sum of each unit
+    fn sizes(&self) -> Vec<Vec<(usize, usize)>> {
+        self.inner
+            .iter()
+            .map(|unit| {
+                unit.data
+                    .iter()
+                    .map(|(row, sum)| (*row, sum.nnz()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("PartialSums(units={})", self.inner.len())
+    }
+}
+
+/// the analytical model of `Pim` on A x B, B is A^T if not given.
+/// each step runs on its own, the merge steps return their cycles and the partial sums for the next level
+#[pyclass(name = "PimModel")]
+pub struct PyPimModel {
+    mem_settings: MemSettings,
+    matrix: TwoMatrix<i32, i32>,
+    row_mapping: ResolvedRowMapping,
+}
+
+impl PyPimModel {
+    fn build(mem_settings: &MemSettings, matrix: TwoMatrix<i32, i32>) -> PyResult<Self> {
+        // the settings are checked without the matrix when they are built
+        mem_settings
+            .validate_with_rows(matrix.b.rows())
+            .map_err(value_error)?;
+        let row_mapping = ResolvedRowMapping::new(mem_settings, &matrix.b).map_err(value_error)?;
+        Ok(Self {
+            mem_settings: mem_settings.clone(),
+            matrix,
+            row_mapping,
+        })
+    }
+}
+
+/// the cycles as python objects and the partial sums of a merge step
+fn merged(
+    py: Python,
+    (cycles, inner): (impl Serialize, Vec<PartialSum<usize>>),
+) -> PyResult<(PyObject, PyPartialSums)> {
+    Ok((
+        pythonize(py, &cycles).map_err(value_error)?,
+        PyPartialSums { inner },
+    ))
+}
+
+#[pymethods]
+impl PyPimModel {
+    #[new]
+    fn new(
+        settings: PyRef<PyMemSettings>,
+        a: PyRef<PyMatrix>,
+        b: Option<PyRef<PyMatrix>>,
+    ) -> PyResult<Self> {
+        Self::build(&settings.inner, two_matrix(&a, b.as_deref())?)
+    }
+
+    /// the `(rows, bytes)` read by each bank
+    fn mem_rows(&self) -> Vec<(usize, usize)> {
+        self.matrix.mem_rows(&self.mem_settings, &self.row_mapping)
+    }
+
+    fn bank_merge(&self, py: Python) -> PyResult<(PyObject, PyPartialSums)> {
+        merged(
+            py,
+            self.matrix
+                .bank_merge(&self.mem_settings, &self.row_mapping),
+        )
+    }
+
+    /// the cycles of each bank to send and of each chip to receive
+    fn chip_fetch_data(&self, partial_sums: PyRef<PyPartialSums>) -> (Vec<usize>, Vec<usize>) {
+        self.matrix
+            .chip_fetch_data(&self.mem_settings, &partial_sums.inner)
+    }
+
+    fn chip_merge(
+        &self,
+        py: Python,
+        partial_sums: PyRef<PyPartialSums>,
+    ) -> PyResult<(PyObject, PyPartialSums)> {
+        merged(
+            py,
+            self.matrix
+                .chip_merge(&self.mem_settings, &partial_sums.inner),
+        )
+    }
+
+    fn channel_fetch_data(&self, partial_sums: PyRef<PyPartialSums>) -> (Vec<usize>, Vec<usize>) {
+        self.matrix
+            .channel_fetch_data(&self.mem_settings, &partial_sums.inner)
+    }
+
+    fn channel_merge(
+        &self,
+        py: Python,
+        partial_sums: PyRef<PyPartialSums>,
+    ) -> PyResult<(PyObject, PyPartialSums)> {
+        merged(
+            py,
+            self.matrix
+                .channel_merge(&self.mem_settings, &partial_sums.inner),
+        )
+    }
+
+    fn dimm_fetch_data(&self, partial_sums: PyRef<PyPartialSums>) -> (Vec<usize>, usize) {
+        self.matrix
+            .dimm_fetch_data(&self.mem_settings, &partial_sums.inner)
+    }
+
+    /// the partial sums of the dimm have one unit
+    fn dimm_merge(
+        &self,
+        py: Python,
+        partial_sums: PyRef<PyPartialSums>,
+    ) -> PyResult<(PyObject, PyPartialSums)> {
+        let (cycles, sum) = self
+            .matrix
+            .dimm_merge(&self.mem_settings, &partial_sums.inner);
+        merged(py, (cycles, vec![sum]))
+    }
+
+    /// the partial sums of `dimm_merge`
+    fn write_result(&self, partial_sums: PyRef<PyPartialSums>) -> PyResult<usize> {
+        match partial_sums.inner.as_slice() {
+            [sum] => Ok(self.matrix.write_result(&self.mem_settings, sum)),
+            _ => Err(value_error(
+                "the result is written from the one unit of the dimm",
+            )),
+        }
+    }
+}
+
+/// all the steps of the analytical model of `Pim`, each level uses the partial sums of the level below
+#[pyfunction]
+fn pim_steps(
+    py: Python,
+    settings: PyRef<PyMemSettings>,
+    a: PyRef<PyMatrix>,
+    b: Option<PyRef<PyMatrix>>,
+) -> PyResult<PyObject> {
+    let PyPimModel {
+        mem_settings,
+        matrix: two_matrix,
+        row_mapping,
+    } = PyPimModel::build(&settings.inner, two_matrix(&a, b.as_deref())?)?;
+    let mem_settings = &mem_settings;
+    let mem_rows = two_matrix.mem_rows(mem_settings, &row_mapping);
+    let (bank_merge, partial_sum) = two_matrix.bank_merge(mem_settings, &row_mapping);
+    let chip_fetch_data = two_matrix.chip_fetch_data(mem_settings, &partial_sum);
+    let (chip_merge, partial_sum) = two_matrix.chip_merge(mem_settings, &partial_sum);
+    let channel_fetch_data = two_matrix.channel_fetch_data(mem_settings, &partial_sum);
+    let (channel_merge, partial_sum) = two_matrix.channel_merge(mem_settings, &partial_sum);
+    let dimm_fetch_data = two_matrix.dimm_fetch_data(mem_settings, &partial_sum);
+    let (dimm_merge, partial_sum) = two_matrix.dimm_merge(mem_settings, &partial_sum);
+    let steps = PimSteps {
+        mem_rows,
+        bank_merge,
+        chip_fetch_data,
+        chip_merge,
+        channel_fetch_data,
+        channel_merge,
+        dimm_fetch_data,
+        dimm_merge,
+        write_result: two_matrix.write_result(mem_settings, &partial_sum),
+    };
+    pythonize(py, &steps).map_err(value_error)
+}
+
+#[pymodule]
+fn spmm_pim(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyMemSettings>()?;
+    m.add_class::<PyMatrix>()?;
+    m.add_class::<PyPartialSums>()?;
+    m.add_class::<PyPimModel>()?;
+    m.add_function(wrap_pyfunction!(simulate, m)?)?;
+    m.add_function(wrap_pyfunction!(pim_steps, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::settings::RowMapping;
+
+    use super::*;
+
+    #[test]
+    fn values_test() {
+        assert_eq!(values(None, 2), vec![1, 1]);
+        assert_eq!(values(Some(vec![1.4, 2.6]), 2), vec![1, 3]);
+    }
+
+    #[test]
+    fn from_coo_test() {
+        // the duplicated entry (1, 0) is summed
+        let matrix = PyMatrix::from_coo((2, 3), vec![0, 1, 1], vec![2, 0, 0], None).unwrap();
+        assert_eq!(matrix.shape(), (2, 3));
+        assert_eq!(matrix.nnz(), 2);
+        assert_eq!(matrix.to_csr().3, vec![1, 2]);
+        // out of the shape, the lengths of row and col, the length of data
+        assert!(PyMatrix::from_coo((2, 3), vec![2], vec![0], None).is_err());
+        assert!(PyMatrix::from_coo((2, 3), vec![0], vec![3], None).is_err());
+        assert!(PyMatrix::from_coo((2, 3), vec![0, 1], vec![0], None).is_err());
+        assert!(PyMatrix::from_coo((2, 3), vec![0], vec![0], Some(vec![1., 2.])).is_err());
+    }
+
+    #[test]
+    fn two_matrix_test() {
+        let a = PyMatrix::from_coo((2, 3), vec![0, 1], vec![0, 2], None).unwrap();
+        assert_eq!(two_matrix(&a, None).unwrap().b.shape(), (3, 2));
+        assert!(two_matrix(&a, Some(&a)).is_err());
+    }
+
+    #[test]
+    fn pim_model_test() {
+        let a = PyMatrix::from_mtx("mtx/test.mtx").unwrap();
+        let model =
+            PyPimModel::build(&MemSettings::default(), two_matrix(&a, None).unwrap()).unwrap();
+        assert_eq!(model.mem_rows().len(), 8);
+        // the 6 rows of B can not be chunked over 8 banks
+        let chunk = MemSettings {
+            row_mapping: RowMapping::Chunk,
+            ..Default::default()
+        };
+        assert!(PyPimModel::build(&chunk, two_matrix(&a, None).unwrap()).is_err());
+    }
+}
@@ -1 +1,314 @@
+//! the row and column permutations applied to A before the simulation, B is the transpose of the permuted A
 
+use std::{collections::VecDeque, time::Instant};
+
+use eyre::{ensure, Result};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sprs::{CsMat, TriMat};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub enum ReorderMode {
+    /// keep the order of the matrix file
+    #[default]
+    None,
+    /// reverse Cuthill-McKee on the pattern of A + A^T, the same permutation for rows and columns, A should be square
+    Rcm,
+    /// the rows and the columns are sorted by their nnz, the longest first
+    DegreeSort,
+    /// the rows that share columns are put next to each other, the columns follow the first use
+    Cluster,
+}
+
+/// new id to old id, `rows[i]` is the row of the original matrix at the row `i` of the permuted matrix
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Permutation {
+    pub rows: Vec<usize>,
+    pub cols: Vec<usize>,
+}
+
+/// the cost of the permutation and its effect on the shape of the matrix
+#[derive(Debug, Clone, Serialize)]
+pub struct ReorderReport {
+    pub mode: ReorderMode,
+    /// the time to compute and apply the permutation in seconds
+    pub time: f64,
+    /// the max distance of a nonzero from the diagonal
+    pub bandwidth_before: usize,
+    pub bandwidth_after: usize,
+}
+
+/// the columns with more rows are not used to find the neighbors in `Cluster`, they connect nearly all rows
+const CLUSTER_MAX_COLUMN_LEN: usize = 1024;
+
+pub fn bandwidth(matrix: &CsMat<i32>) -> usize {
+    matrix
+        .iter()
+        .map(|(_, (row, col))| row.abs_diff(col))
+        .max()
+        .unwrap_or(0)
+}
+
+/// compute the permutation of `mode`, None is the identity
+pub fn permutation(matrix: &CsMat<i32>, mode: ReorderMode) -> Result<Permutation> {
+    let identity = |n: usize| (0..n).collect_vec();
+    let perm = match mode {
+        ReorderMode::None => Permutation {
+            rows: identity(matrix.rows()),
+            cols: identity(matrix.cols()),
+        },
+        ReorderMode::Rcm => {
+            ensure!(
+                matrix.rows() == matrix.cols(),
+                "rcm needs a square matrix, got {:?}",
+                matrix.shape()
+            );
+            let order = reverse_cuthill_mckee(matrix);
+            Permutation {
+                rows: order.clone(),
+                cols: order,
+            }
+        }
+        ReorderMode::DegreeSort => {
+            let transpose = matrix.transpose_view().to_csr();
+            let by_len = |lens: Vec<usize>| {
+                (0..lens.len())
+                    .sorted_by_key(|&i| std::cmp::Reverse(lens[i]))
+                    .collect_vec()
+            };
+            Permutation {
+                rows: by_len(matrix.outer_iterator().map(|r| r.nnz()).collect()),
+                cols: by_len(transpose.outer_iterator().map(|c| c.nnz()).collect()),
+            }
+        }
+        ReorderMode::Cluster => cluster(matrix),
+    };
+    Ok(perm)
+}
+
+/// the matrix with `permuted[i][j] = matrix[perm.rows[i]][perm.cols[j]]`
+pub fn apply(matrix: &CsMat<i32>, perm: &Permutation) -> CsMat<i32> {
+    let inverse = |order: &[usize]| {
+        let mut inv = vec![0; order.len()];
+        for (new, &old) in order.iter().enumerate() {
+            inv[old] = new;
+        }
+        inv
+    };
+    let (rows, cols) = (inverse(&perm.rows), inverse(&perm.cols));
+    let mut tri = TriMat::new(matrix.shape());
+    for (&value, (row, col)) in matrix.iter() {
+        tri.add_triplet(rows[row], cols[col], value);
+    }
+    tri.to_csr()
+}
+
+/// permute the matrix, return it with the cost of the reordering
+pub fn reorder(matrix: &CsMat<i32>, mode: ReorderMode) -> Result<(CsMat<i32>, ReorderReport)> {
+    let start = Instant::now();
+    let perm = permutation(matrix, mode)?;
+    let permuted = apply(matrix, &perm);
+    let report = ReorderReport {
+        mode,
+        time: start.elapsed().as_secs_f64(),
+        bandwidth_before: bandwidth(matrix),
+        bandwidth_after: bandwidth(&permuted),
+    };
+    Ok((permuted, report))
+}
+
+/// bfs from the lowest degree node of each component, the neighbors are visited by degree, then reverse the order
+fn reverse_cuthill_mckee(matrix: &CsMat<i32>) -> Vec<usize> {
+    let n = matrix.rows();
+    let transpose = matrix.transpose_view().to_csr();
+    let neighbors = (0..n)
+        .map(|i| {
+            let row = matrix.outer_view(i).unwrap();
+            let col = transpose.outer_view(i).unwrap();
+            row.indices()
+                .iter()
+                .chain(col.indices())
+                .cloned()
+                .filter(|&j| j != i)
+                .sorted()
+                .dedup()
+                .collect_vec()
+        })
+        .collect_vec();
+    let degree = |i: usize| neighbors[i].len();
+    let mut visited = vec![false; n];
+    let mut order = Vec::with_capacity(n);
+    for start in (0..n).sorted_by_key(|&i| degree(i)) {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut queue = VecDeque::from([start]);
+        while let Some(node) = queue.pop_front() {
+            order.push(node);
+            let next = neighbors[node]
+                .iter()
+                .cloned()
+                .filter(|&j| !visited[j])
+                .sorted_by_key(|&j| degree(j))
+                .collect_vec();
+            for j in next {
+                visited[j] = true;
+                queue.push_back(j);
+            }
+        }
+    }
+    order.reverse();
+    order
+}
+
+/// start from the longest unvisited row, then always move to the unvisited row that shares the most columns with the current one
+fn cluster(matrix: &CsMat<i32>) -> Permutation {
+    let n = matrix.rows();
+    let transpose = matrix.transpose_view().to_csr();
+    let mut visited = vec![false; n];
+    let mut shared = vec![0usize; n];
+    let mut rows = Vec::with_capacity(n);
+    let seeds = (0..n)
+        .sorted_by_key(|&i| std::cmp::Reverse(matrix.outer_view(i).unwrap().nnz()))
+        .collect_vec();
+    for seed in seeds {
+        if visited[seed] {
+            continue;
+        }
+        let mut current = seed;
+        loop {
+            visited[current] = true;
+            rows.push(current);
+            // count the shared columns of the unvisited rows
+            let mut touched = vec![];
+            for &col in matrix.outer_view(current).unwrap().indices() {
+                let col_rows = transpose.outer_view(col).unwrap();
+                if col_rows.nnz() > CLUSTER_MAX_COLUMN_LEN {
+                    continue;
+                }
+                for &row in col_rows.indices() {
+                    if !visited[row] {
+                        if shared[row] == 0 {
+                            touched.push(row);
+                        }
+                        shared[row] += 1;
+                    }
+                }
+            }
+            let next = touched
+                .iter()
+                .cloned()
+                .max_by_key(|&row| (shared[row], std::cmp::Reverse(row)));
+            for row in touched {
+                shared[row] = 0;
+            }
+            match next {
+                Some(row) => current = row,
+                None => break,
+            }
+        }
+    }
+    // the columns in the order they are first used, the empty columns at the end
+    let mut col_used = vec![false; matrix.cols()];
+    let mut cols = Vec::with_capacity(matrix.cols());
+    for &row in &rows {
+        for &col in matrix.outer_view(row).unwrap().indices() {
+            if !col_used[col] {
+                col_used[col] = true;
+                cols.push(col);
+            }
+        }
+    }
+    cols.extend((0..matrix.cols()).filter(|&col| !col_used[col]));
+    Permutation { rows, cols }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{seq::SliceRandom, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    use super::*;
+
+    fn from_entries(shape: (usize, usize), entries: &[(usize, usize)]) -> CsMat<i32> {
+        let mut tri = TriMat::new(shape);
+        for &(row, col) in entries {
+            tri.add_triplet(row, col, 1);
+        }
+        tri.to_csr()
+    }
+
+    /// a tridiagonal matrix with shuffled rows and columns
+    fn shuffled_band(n: usize) -> CsMat<i32> {
+        let entries = (0..n)
+            .flat_map(|i| [(i, i), (i, (i + 1) % n), ((i + 1) % n, i)])
+            .filter(|(row, col)| row.abs_diff(*col) <= 1)
+            .collect_vec();
+        let band = from_entries((n, n), &entries);
+        let mut order = (0..n).collect_vec();
+        order.shuffle(&mut XorShiftRng::seed_from_u64(1));
+        apply(
+            &band,
+            &Permutation {
+                rows: order.clone(),
+                cols: order,
+            },
+        )
+    }
+
+    fn is_permutation(order: &[usize]) -> bool {
+        order.iter().cloned().sorted().eq(0..order.len())
+    }
+
+    #[test]
+    fn all_modes_are_permutations() {
+        let matrix = shuffled_band(64);
+        for mode in [
+            ReorderMode::None,
+            ReorderMode::Rcm,
+            ReorderMode::DegreeSort,
+            ReorderMode::Cluster,
+        ] {
+            let (permuted, report) = reorder(&matrix, mode).unwrap();
+            let perm = permutation(&matrix, mode).unwrap();
+            assert!(is_permutation(&perm.rows) && is_permutation(&perm.cols));
+            assert_eq!(permuted.nnz(), matrix.nnz());
+            assert_eq!(report.bandwidth_before, bandwidth(&matrix));
+        }
+    }
+
+    #[test]
+    fn rcm_restores_band() {
+        let matrix = shuffled_band(64);
+        assert!(bandwidth(&matrix) > 1);
+        let (_, report) = reorder(&matrix, ReorderMode::Rcm).unwrap();
+        assert_eq!(report.bandwidth_after, 1);
+        assert!(reorder(&from_entries((2, 3), &[(0, 0)]), ReorderMode::Rcm).is_err());
+    }
+
+    #[test]
+    fn degree_sort_and_cluster() {
+        // row 2 is the longest, rows 0 and 3 share the columns 0 and 1, column 0 is the longest
+        let matrix = from_entries(
+            (4, 4),
+            &[
+                (0, 0),
+                (0, 1),
+                (1, 2),
+                (2, 0),
+                (2, 2),
+                (2, 3),
+                (3, 0),
+                (3, 1),
+            ],
+        );
+        let perm = permutation(&matrix, ReorderMode::DegreeSort).unwrap();
+        assert_eq!(perm.rows, vec![2, 0, 3, 1]);
+        assert_eq!(perm.cols, vec![0, 1, 2, 3]);
+
+        let perm = permutation(&matrix, ReorderMode::Cluster).unwrap();
+        assert_eq!(perm.rows, vec![2, 0, 3, 1]);
+        assert_eq!(perm.cols, vec![0, 2, 3, 1]);
+    }
+}
@@ -0,0 +1,275 @@
+//! copy the most referenced rows of B into several banks, the tasks go to the least loaded copy
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sprs::CsMat;
+
+use crate::{
+    fault::FaultMap,
+    inspect::Distribution,
+    pim::{
+        get_bank_id_from_flat_bank_id, get_bank_id_from_row_id, get_flat_bank_id,
+        ResolvedRowMapping,
+    },
+    settings::MemSettings,
+    sim::id_translation::BankID,
+};
+
+/// which rows of B are copied. in toml: `replication = { TopK = { rows = 16, replicas = 4 } }`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Default)]
+pub enum Replication {
+    #[default]
+    None,
+    /// the `rows` most referenced rows
+    TopK { rows: usize, replicas: usize },
+    /// the rows referenced by at least `references` nonzeros of A
+    Threshold { references: usize, replicas: usize },
+}
+
+/// the bytes of one nonzero of B in the bank, the index and the value
+const NNZ_BYTES: usize = 8;
+/// the same as the bank read: one 2048 bytes row every 16 cycles
+const WRITE_ROW_BYTES: usize = 2048;
+const WRITE_ROW_CYCLES: f64 = 16.;
+
+/// the banks of the copies of each replicated row, and the work sent to each bank so far
+#[derive(Debug, Clone, Default)]
+pub struct ReplicationPlan {
+    /// row of B to the flat ids of the banks that hold it, the first one is the bank of the row mapping
+    replicas: BTreeMap<usize, Vec<usize>>,
+    /// the nonzeros of B read by the tasks of each flat bank
+    loads: Vec<usize>,
+    channels: usize,
+    chips: usize,
+    banks: usize,
+}
+
+/// the cost of the replication and the tasks per bank with and without it
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicationReport {
+    pub replicated_rows: usize,
+    /// the nonzeros stored again in other banks
+    pub extra_nnz: usize,
+    pub extra_bytes: usize,
+    /// the cycles to write the copies into the banks
+    pub write_cycles: f64,
+    /// the tasks of each bank when the rows of A are sent in order
+    pub bank_tasks_before: Distribution,
+    pub bank_tasks_after: Distribution,
+}
+
+impl ReplicationPlan {
+    pub fn new(
+        mem_settings: &MemSettings,
+        matrix_a: &CsMat<i32>,
+        row_mapping: &ResolvedRowMapping,
+    ) -> Self {
+        let (channels, chips, banks) = (
+            mem_settings.channels,
+            mem_settings.chips,
+            mem_settings.banks,
+        );
+        let num_banks = channels * chips * banks;
+        let mut references = vec![0; matrix_a.cols()];
+        for (_, (_, col)) in matrix_a.iter() {
+            references[col] += 1;
+        }
+        let (rows, replicas) = match mem_settings.replication {
+            Replication::None => (vec![], 1),
+            Replication::TopK { rows, replicas } => (
+                (0..references.len())
+                    .filter(|&row| references[row] > 0)
+                    .sorted_by_key(|&row| std::cmp::Reverse(references[row]))
+                    .take(rows)
+                    .collect_vec(),
+                replicas,
+            ),
+            Replication::Threshold {
+                references: threshold,
+                replicas,
+            } => (
+                (0..references.len())
+                    .filter(|&row| references[row] > 0 && references[row] >= threshold)
+                    .collect_vec(),
+                replicas,
+            ),
+        };
+        // the copies are spread evenly from the home bank, so they are in different chips and channels
+        let fault_map = FaultMap::new(mem_settings);
+        let replicas = replicas.clamp(1, num_banks);
+        let step = num_banks / replicas;
+        let replicas = rows
+            .into_iter()
+            .map(|row| {
+                let (home, _) = get_bank_id_from_row_id(
+                    row,
+                    channels,
+                    chips,
+                    banks,
+                    matrix_a.cols(),
+                    row_mapping,
+                );
+                let home = get_flat_bank_id(home, chips, banks);
+                let copies = (0..replicas)
+                    .map(|i| (home + i * step) % num_banks)
+                    .filter(|&bank| !fault_map.is_flat_failed(bank))
+                    .collect_vec();
+                (row, copies)
+            })
+            .filter(|(_, copies)| copies.len() > 1)
+            .collect();
+        Self {
+            replicas,
+            loads: vec![0; num_banks],
+            channels,
+            chips,
+            banks,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replicas.is_empty()
+    }
+
+    /// the bank of the task that reads `row` of B with `nnz` nonzeros, `home` is the bank of the row mapping.
+    /// a plan without copies (like the default one) always returns `home`
+    pub fn route(&mut self, row: usize, home: BankID, nnz: usize) -> BankID {
+        if self.is_empty() {
+            return home;
+        }
+        let target = match self.replicas.get(&row) {
+            // the first least loaded copy, the home bank wins a tie
+            Some(copies) => *copies.iter().min_by_key(|&&bank| self.loads[bank]).unwrap(),
+            None => get_flat_bank_id(home, self.chips, self.banks),
+        };
+        self.loads[target] += nnz;
+        get_bank_id_from_flat_bank_id(target, self.channels, self.chips, self.banks)
+    }
+
+    /// route the tasks of A in order, and count the copies of B
+    pub fn report(
+        &self,
+        matrix_a: &CsMat<i32>,
+        matrix_b: &CsMat<i32>,
+        row_mapping: &ResolvedRowMapping,
+    ) -> ReplicationReport {
+        let mut plan = self.clone();
+        plan.loads.iter_mut().for_each(|load| *load = 0);
+        let mut before = vec![0; plan.loads.len()];
+        let mut after = vec![0; plan.loads.len()];
+        for (_, (_, col)) in matrix_a.iter() {
+            let (home, _) = get_bank_id_from_row_id(
+                col,
+                self.channels,
+                self.chips,
+                self.banks,
+                matrix_b.rows(),
+                row_mapping,
+            );
+            let nnz = matrix_b.outer_view(col).unwrap().nnz();
+            let target = plan.route(col, home, nnz);
+            before[get_flat_bank_id(home, self.chips, self.banks)] += 1;
+            after[get_flat_bank_id(target, self.chips, self.banks)] += 1;
+        }
+        let copy_bytes = self
+            .replicas
+            .iter()
+            .map(|(&row, copies)| {
+                let bytes = matrix_b.outer_view(row).unwrap().nnz() * NNZ_BYTES;
+                (copies.len() - 1, bytes)
+            })
+            .collect_vec();
+        let extra_bytes = copy_bytes
+            .iter()
+            .map(|(copies, bytes)| copies * bytes)
+            .sum::<usize>();
+        let write_cycles = copy_bytes
+            .iter()
+            .map(|(copies, bytes)| {
+                (copies * ((bytes + WRITE_ROW_BYTES - 1) / WRITE_ROW_BYTES)) as f64
+                    * WRITE_ROW_CYCLES
+            })
+            .sum();
+        ReplicationReport {
+            replicated_rows: self.replicas.len(),
+            extra_nnz: extra_bytes / NNZ_BYTES,
+            extra_bytes,
+            write_cycles,
+            bank_tasks_before: Distribution::new(before),
+            bank_tasks_after: Distribution::new(after),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        settings::RowMapping,
+        utils::fixtures::{ones_at, small_settings},
+    };
+
+    use super::*;
+
+    /// every row of A references the column 0, the others are referenced once
+    fn power_law() -> CsMat<i32> {
+        ones_at(
+            (8, 8),
+            (0..8)
+                .map(|row| (row, 0))
+                .chain((1..8).map(|row| (row, row))),
+        )
+    }
+
+    fn settings(replication: Replication) -> MemSettings {
+        MemSettings {
+            row_mapping: RowMapping::Chunk,
+            replication,
+            ..small_settings(1, 2, 2)
+        }
+    }
+
+    #[test]
+    fn replicate_hot_rows() {
+        let a = power_law();
+        let b = a.transpose_view().to_csr();
+        let mapping = ResolvedRowMapping::Chunk;
+        let top = ReplicationPlan::new(
+            &settings(Replication::TopK {
+                rows: 1,
+                replicas: 4,
+            }),
+            &a,
+            &mapping,
+        );
+        let threshold = ReplicationPlan::new(
+            &settings(Replication::Threshold {
+                references: 2,
+                replicas: 4,
+            }),
+            &a,
+            &mapping,
+        );
+        assert_eq!(top.replicas, threshold.replicas);
+        assert_eq!(top.replicas[&0], vec![0, 1, 2, 3]);
+
+        let report = top.report(&a, &b, &mapping);
+        assert_eq!(report.replicated_rows, 1);
+        // the row 0 of B has 8 nonzeros, copied 3 times
+        assert_eq!(report.extra_nnz, 24);
+        assert_eq!(report.write_cycles, 3. * 16.);
+        // the bank 0 holds the rows 0 and 1, it has 8 + 1 tasks without replication
+        assert_eq!(report.bank_tasks_before.counts, vec![9, 2, 2, 2]);
+        assert!(report.bank_tasks_after.imbalance < report.bank_tasks_before.imbalance);
+        assert_eq!(report.bank_tasks_after.counts.iter().sum::<usize>(), 15);
+
+        let none = ReplicationPlan::new(&settings(Replication::None), &a, &mapping);
+        assert!(none.is_empty());
+        let report = none.report(&a, &b, &mapping);
+        assert_eq!(report.bank_tasks_after, report.bank_tasks_before);
+        // the plan of a task sender without `with_replication`
+        let home = ((0, 1), 1);
+        assert_eq!(ReplicationPlan::default().route(0, home, 8), home);
+    }
+}
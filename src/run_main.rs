@@ -1,18 +1,27 @@
-use std::fs;
-use std::fs::File;
 use std::io::{self};
+use std::path::{Path, PathBuf};
 
 use super::{
-    args::{Args, RunMode},
+    analyze,
+    args::{Args, RunMode, SubCommand},
+    fault::FaultReport,
+    inspect,
+    pim::ResolvedRowMapping,
+    reorder_calculator::{self, ReorderMode},
+    replication::{Replication, ReplicationPlan},
     result::{self, Results},
     run_2d_unroll_buf,
-    settings::Settings,
+    run_output::RunOutput,
+    server,
+    settings::{EffectiveSettings, MemSettings, Settings},
     two_matrix::TwoMatrix,
-    utils::run::run_exp_csr,
+    utils::{plot, run::run_exp_csr},
 };
 use crate::init_logger;
+use crate::sim::job::Job;
 use crate::sim::sim_time::AllTimeStats;
-use crate::sim::Simulator;
+use crate::sim::stream::StreamSpec;
+use crate::sim::{SimReport, Simulator};
 use clap::{Command, IntoApp};
 use clap_complete::Generator;
 use eyre::{Context, Result};
@@ -20,6 +29,167 @@ use itertools::Itertools;
 use sprs::CsMat;
 use tracing::{debug, error, info};
 
+/// simulate one matrix and write its results into the run directory
+fn run_sim_matrix(
+    name: &Path,
+    settings: &Settings,
+    output: &mut RunOutput,
+    all_results: &mut AllTimeStats,
+) -> Result<()> {
+    let csr: CsMat<i32> = sprs::io::read_matrix_market(name)
+        .wrap_err(format!("{:?} is error!", name))?
+        .to_csr();
+    let mtx_file_name = name.file_stem().unwrap();
+    let file_path = mtx_file_name.to_string_lossy();
+    let mut artifacts = vec![];
+    let csr = match settings.reorder {
+        ReorderMode::None => csr,
+        mode => {
+            let (csr, report) = reorder_calculator::reorder(&csr, mode)?;
+            info!("{:?}: {:?}", name, report);
+            artifacts.push(format!("{file_path}/reorder.json"));
+            output.write_json(&artifacts[0], &report)?;
+            csr
+        }
+    };
+    let shape = csr.shape();
+    let nnz = csr.nnz();
+    let trans_pose = csr.transpose_view().to_csr();
+    if settings.mem_settings.replication != Replication::None {
+        // the same plan as the task sender, to check if the copies pay off
+        let row_mapping = ResolvedRowMapping::new(&settings.mem_settings, &trans_pose)?;
+        let plan = ReplicationPlan::new(&settings.mem_settings, &csr, &row_mapping);
+        let report = plan.report(&csr, &trans_pose, &row_mapping);
+        info!("{:?}: {:?}", name, report);
+        let file = format!("{file_path}/replication.json");
+        output.write_json(&file, &report)?;
+        artifacts.push(file);
+    }
+    // the same matrix without the faults, to measure the performance loss
+    let healthy = if settings.mem_settings.faults.is_empty() {
+        None
+    } else {
+        let healthy_settings = MemSettings {
+            faults: Default::default(),
+            ..settings.mem_settings.clone()
+        };
+        let healthy_time = Simulator::run(
+            &healthy_settings,
+            TwoMatrix::new(csr.clone(), trans_pose.clone()),
+        )?
+        .cycles;
+        Some((healthy_time, trans_pose.clone()))
+    };
+    let two_matrix = TwoMatrix::new(csr, trans_pose);
+    let SimReport {
+        cycles: time,
+        time_stats,
+        detailed_time_stats,
+        end_time_stats,
+        bank_cache: bank_cache_stats,
+        merger_stats,
+        spill_stats,
+    } = Simulator::run(&settings.mem_settings, two_matrix)?;
+    // the results of each matrix are in its own directory
+    let stats_files = [
+        format!("{file_path}/full_time.json"),
+        format!("{file_path}/time_stats.json"),
+        format!("{file_path}/detailed_time_stats.json"),
+        format!("{file_path}/end_time_stats.json"),
+        format!("{file_path}/merger_stats.json"),
+    ];
+    output.write_json(&stats_files[0], &time)?;
+    output.write_json(&stats_files[1], &time_stats)?;
+    output.write_json(&stats_files[2], &detailed_time_stats)?;
+    output.write_json(&stats_files[3], &end_time_stats)?;
+    output.write_json(&stats_files[4], &merger_stats)?;
+    artifacts.extend(stats_files);
+    if settings.mem_settings.bank_cache_size > 0 {
+        info!(
+            "{:?}: bank cache hit rate: {}, saved read time: {}",
+            name, bank_cache_stats.hit_rate, bank_cache_stats.saved_read_time
+        );
+        let file = format!("{file_path}/bank_cache.json");
+        output.write_json(&file, &bank_cache_stats)?;
+        artifacts.push(file);
+    }
+    if let Some((healthy_time, matrix_b)) = healthy {
+        let report = FaultReport::new(&settings.mem_settings, &matrix_b, healthy_time, time)?;
+        info!(
+            "{:?}: {} failed banks, {} remapped rows, performance loss: {}",
+            name, report.failed_banks, report.remapped_rows, report.performance_loss
+        );
+        let file = format!("{file_path}/faults.json");
+        output.write_json(&file, &report)?;
+        artifacts.push(file);
+    }
+    if settings.mem_settings.spill.is_enabled() {
+        info!(
+            "{:?}: spilled partial sums: {}, extra cycles: {}",
+            name, spill_stats.spills, spill_stats.extra_cycles
+        );
+        let file = format!("{file_path}/spill.json");
+        output.write_json(&file, &spill_stats)?;
+        artifacts.push(file);
+    }
+    output.add_matrix(
+        name,
+        shape,
+        nnz,
+        artifacts.into_iter().map(Into::into).collect(),
+    )?;
+
+    all_results.data.push((file_path.to_string(), time_stats));
+    Ok(())
+}
+
+/// simulate the jobs of the config together and write the report into the run directory
+fn run_sim_jobs(settings: &Settings, output: &mut RunOutput) -> Result<()> {
+    let jobs = settings
+        .jobs
+        .iter()
+        .map(|spec| {
+            let csr: CsMat<i32> = sprs::io::read_matrix_market(&spec.mtx_file)
+                .wrap_err(format!("{:?} is error!", spec.mtx_file))?
+                .to_csr();
+            let trans_pose = csr.transpose_view().to_csr();
+            Ok(Job {
+                name: spec
+                    .mtx_file
+                    .file_stem()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string(),
+                matrix: TwoMatrix::new(csr, trans_pose),
+                arrival: spec.arrival,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let report = Simulator::run_jobs(&settings.mem_settings, jobs)?;
+    for job in &report.jobs {
+        info!(
+            "job {}: finished at {}, slowdown: {}",
+            job.name, job.finish, job.slowdown
+        );
+    }
+    output.write_json("jobs/jobs.json", &report)?;
+    Ok(())
+}
+
+/// simulate the stream of the config and write the report into the run directory
+fn run_sim_stream(spec: &StreamSpec, settings: &Settings, output: &mut RunOutput) -> Result<()> {
+    let (matrix_b, batches) = spec.load()?;
+    let report = Simulator::run_stream(&settings.mem_settings, matrix_b, batches)?;
+    info!(
+        "stream: {} batches, throughput: {} rows per cycle, mean latency: {}",
+        report.batches.len(),
+        report.throughput,
+        report.mean_latency
+    );
+    output.write_json("stream/stream.json", &report)?;
+    Ok(())
+}
+
 fn print_completions<G: Generator>(gen: G, cmd: &mut Command) {
     clap_complete::generate(gen, cmd, cmd.get_name().to_string(), &mut io::stdout());
 }
@@ -32,69 +202,66 @@ pub fn main(args: Args) -> Result<()> {
         print_completions(generator, &mut cmd);
         return Ok(());
     }
+    match &args.subcommand {
+        Some(SubCommand::Config(config)) => {
+            let effective = EffectiveSettings::load(&config.layers())?;
+            print!("{}", effective.to_toml()?);
+            return Ok(());
+        }
+        Some(SubCommand::Analyze(analyze_args)) => return analyze::main(analyze_args),
+        Some(SubCommand::Plot(plot_args)) => return plot::main(plot_args),
+        Some(SubCommand::Inspect(inspect_args)) => return inspect::main(inspect_args),
+        Some(SubCommand::Serve(serve_args)) => return server::main(serve_args),
+        Some(SubCommand::Gen(gen_args)) => {
+            let output = gen_args
+                .output
+                .clone()
+                .unwrap_or_else(|| format!("{}.mtx", gen_args.spec.name()).into());
+            let matrix = gen_args.spec.write(&output)?;
+            info!(
+                "{:?}: {:?} with {} nonzeros",
+                output,
+                matrix.shape(),
+                matrix.nnz()
+            );
+            return Ok(());
+        }
+        None => {}
+    }
     info!("start sim with {:?}", args);
 
     debug!("{:?}", args);
-    let mut config_files = args.config_file;
-    if config_files.is_empty() {
-        config_files.push("configs/default.toml".into());
-        config_files.push("configs/ddr4.toml".into());
-    }
-
     println!("config");
 
-    let settings = Settings::new(&config_files).wrap_err("fail to create Setting object")?;
+    let effective =
+        EffectiveSettings::load(&args.config.layers()).wrap_err("fail to create Setting object")?;
+    effective.settings.mem_settings.validate()?;
 
-    debug!("{:?}", settings);
-    let mtxs = settings.mtx_files.clone();
+    debug!("{:?}", effective.settings);
     let run_mode = args.run_mode.unwrap_or(RunMode::Sim);
-    fs::create_dir_all("results")?;
+    let mut output = RunOutput::create(&effective, &format!("{run_mode:?}"))?;
+    let settings = effective.settings;
+    let mut mtxs = settings.mtx_files.clone();
+    // the synthetic workloads are written into the run directory, then used like the other files
+    for workload in &settings.workloads {
+        let path = output.artifact_path(format!("workloads/{}.mtx", workload.name()))?;
+        workload.write(&path)?;
+        mtxs.push(path);
+    }
     match run_mode {
         RunMode::Sim => {
             info!("sim start");
-            let graph_name = settings.mtx_files;
+            let graph_name = &mtxs;
             let mut all_results = AllTimeStats { data: Vec::new() };
             let results: Vec<eyre::Result<_>> = graph_name
                 .iter()
                 .map(|name| {
                     info!("graph: {:?}", name);
-                    let csr: CsMat<i32> = sprs::io::read_matrix_market(name)
-                        .wrap_err(format!("{:?} is error!", name))?
-                        .to_csr();
-                    let mtx_file_name = name.file_stem().unwrap();
-                    let trans_pose = csr.transpose_view().to_csr();
-                    let two_matrix = TwoMatrix::new(csr, trans_pose);
-                    let (time, time_stats, detailed_time_status, end_time_stats) =
-                        Simulator::run(&settings.mem_settings, two_matrix)?;
-                    let time_stats = time_stats.to_rate();
-                    let detailed_time_status = detailed_time_status.to_rate();
-                    let file_path = mtx_file_name.to_string_lossy();
-                    let task_queue_size = settings.mem_settings.sender_store_size;
-                    let interleaving_chunk_size = settings.mem_settings.interleaved_chunk;
-                    let row_mapping=&settings.mem_settings.row_mapping;
-                    let scheduler_mode=&settings.mem_settings.task_scheduler_mode;
-                    let batch_size=settings.mem_settings.task_scheduler_chunk_size;
-                    serde_json::to_writer_pretty(
-                        File::create(format!("results/full_time_{row_mapping:?}_{scheduler_mode:?}_{task_queue_size}_{interleaving_chunk_size}_{batch_size}_{file_path}.json"))?,
-                        &time,
-                    )?;
-                    serde_json::to_writer_pretty(
-                        File::create(format!("results/time_stats_{row_mapping:?}_{scheduler_mode:?}_{task_queue_size}_{interleaving_chunk_size}_{batch_size}_{file_path}.json"))?,
-                        &time_stats,
-                    )?;
-                    serde_json::to_writer_pretty(
-                        File::create(format!("results/detailed_time_{row_mapping:?}_{scheduler_mode:?}_{task_queue_size}_{interleaving_chunk_size}_status_{batch_size}_{file_path}.json"))?,
-                        &detailed_time_status,
-                    )?;
-                    serde_json::to_writer_pretty(
-                        File::create(format!("results/end_time_{row_mapping:?}_{scheduler_mode:?}_{task_queue_size}_{interleaving_chunk_size}_stats_{batch_size}_{file_path}.json"))?,
-                        &end_time_stats,
-                    )?;
-
-                    all_results.data.push((file_path.to_string(), time_stats));
-                    // write the result to file
-
-                    Ok(name)
+                    let result = run_sim_matrix(name, &settings, &mut output, &mut all_results);
+                    if let Err(e) = &result {
+                        output.add_failed_matrix(name, e);
+                    }
+                    result.map(|_| name)
                 })
                 .collect_vec();
             for r in results {
@@ -103,13 +270,22 @@ pub fn main(args: Args) -> Result<()> {
                     Err(e) => error!("{:?}", e),
                 }
             }
-            // let time_stats_output = serde_json::to_string_pretty(&all_results)
-            //     .wrap_err("fail to serialize all_results")?;
-            // // write the result to file "all_results.json"
-            // let file_name = "results/time_stats_all_results.json";
-            // let mut _file = File::create(&file_name)
-            //     .wrap_err(format!("the path: {} is invalid!", file_name))?;
-            // writeln!(_file, "{}", time_stats_output)?;
+            output.write_json("time_stats_all.json", &all_results)?;
+            if !settings.jobs.is_empty() {
+                // a failed run is recorded in the manifest like a failed matrix
+                if let Err(e) = run_sim_jobs(&settings, &mut output) {
+                    error!("{:?}", e);
+                    output.add_failed_run("jobs", &e);
+                }
+            }
+            if let Some(spec) = &settings.stream {
+                if let Err(e) = run_sim_stream(spec, &settings, &mut output) {
+                    error!("{:?}", e);
+                    output.add_failed_run("stream", &e);
+                }
+            }
+            let run_dir = output.finish()?;
+            info!("all results are in {:?}", run_dir);
             Ok(())
         }
         RunMode::Pim => {
@@ -124,20 +300,30 @@ pub fn main(args: Args) -> Result<()> {
                         // run_1d_c_unroll_buf!(i;&csr;&settings.mem_settings;full_result;ok_list;err_list; run_exp_csr; 64,128,256,512,1024,2048);
                         // run_2d_unroll_buf!(i;&csr;&settings.mem_settings; full_result;ok_list;err_list; run_exp_csr; (2,32),(4,16),(8,8),(2,64),(4,32),(8,16),(2,128),(4,64),(8,32),(16,16),(2,256),(4,128),(8,64),(16,32),
                         // (2,512),(4,256),(8,128),(16,64),(32,32), (2,1024),(4,512),(8,256),(16,128),(32,64));
+                        output.add_matrix(i, csr.shape(), csr.nnz(), vec![])?;
                         run_2d_unroll_buf!(i;&csr;&settings.mem_settings; full_result;ok_list;err_list; run_exp_csr;(1,1),(4,4));
                     }
                     Err(e) => {
                         err_list.push(i);
+                        output.add_failed_matrix(i, &eyre::eyre!("{}", e));
                         error!("{}", e);
                     }
                 }
             }
-            let file_name = settings.result_file;
+            // the result file is put into the run directory
+            let file_name = settings
+                .result_file
+                .file_name()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| "result.json".into());
             full_result
-                .save_to_file(&file_name)
+                .save_to_file(&output.artifact_path(file_name.with_extension("json"))?)
                 .wrap_err("file to save result")?;
-            result::save_result_list(&ok_list, &err_list, &file_name)
+            output.artifact_path(file_name.with_extension("ok.json"))?;
+            output.artifact_path(file_name.with_extension("err.json"))?;
+            result::save_result_list(&ok_list, &err_list, &output.dir.join(&file_name))
                 .wrap_err("file to save result")?;
+            let run_dir = output.finish()?;
             info!(
                 "running time: {:?}'s",
                 std::time::Instant::now()
@@ -146,6 +332,7 @@ pub fn main(args: Args) -> Result<()> {
             );
             info!("the list of files succeeded: {:?}", ok_list);
             info!("the list of files failed: {:?}", err_list);
+            info!("all results are in {:?}", run_dir);
             Ok(())
         }
     }
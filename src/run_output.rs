@@ -0,0 +1,260 @@
+//! the output directory of one run, all result files of the run are written here and listed in `manifest.json`
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::settings::EffectiveSettings;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const EFFECTIVE_CONFIG_FILE: &str = "effective_config.toml";
+
+/// 64 bit FNV-1a, stable between builds, used for the checksum of matrices and configs
+pub fn fnv1a64(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// the matrix used in a run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatrixRecord {
+    pub file: PathBuf,
+    /// fnv1a64 of the file content
+    pub checksum: String,
+    pub rows: usize,
+    pub cols: usize,
+    pub nnz: usize,
+    /// the error if this matrix failed
+    pub error: Option<String>,
+    /// the artifacts of this matrix, relative to the run directory
+    pub artifacts: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub crate_version: String,
+    pub run_mode: String,
+    /// unix time in seconds
+    pub start_time: u64,
+    /// wall-clock time of the whole run in seconds
+    pub wall_time: f64,
+    pub seed: u64,
+    /// fnv1a64 of the effective config
+    pub config_checksum: String,
+    /// the effective config, the same as `effective_config.toml`
+    pub effective_config: String,
+    pub matrices: Vec<MatrixRecord>,
+    /// the errors of the runs that are not of one matrix, like `jobs`, by the name of the run
+    #[serde(default)]
+    pub errors: BTreeMap<String, String>,
+    /// all files of this run, relative to the run directory
+    pub artifacts: Vec<PathBuf>,
+}
+
+impl Manifest {
+    /// read the manifest of a run directory
+    pub fn load(run_dir: &Path) -> Result<Self> {
+        let path = run_dir.join(MANIFEST_FILE);
+        let file = File::open(&path).wrap_err(format!("fail to open {path:?}"))?;
+        serde_json::from_reader(file).wrap_err(format!("fail to parse {path:?}"))
+    }
+}
+
+/// one directory per run: `{output_dir}/{run_name}`
+pub struct RunOutput {
+    pub dir: PathBuf,
+    start: Instant,
+    manifest: Manifest,
+}
+
+impl RunOutput {
+    /// create the run directory and write the effective config into it
+    pub fn create(effective: &EffectiveSettings, run_mode: &str) -> Result<Self> {
+        let effective_config = effective.to_toml()?;
+        let config_checksum = format!("{:016x}", fnv1a64(effective_config.as_bytes()));
+        let start_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let settings = &effective.settings;
+        let dir = match &settings.run_name {
+            Some(name) => settings.output_dir.join(name),
+            None => {
+                // the same config started in the same second get a suffix
+                let name = format!("run-{start_time}-{}", &config_checksum[..8]);
+                let mut dir = settings.output_dir.join(&name);
+                let mut suffix = 1;
+                while dir.exists() {
+                    dir = settings.output_dir.join(format!("{name}-{suffix}"));
+                    suffix += 1;
+                }
+                dir
+            }
+        };
+        fs::create_dir_all(&dir).wrap_err(format!("fail to create run directory {dir:?}"))?;
+        info!("the output directory of this run: {:?}", dir);
+        let mut output = Self {
+            dir,
+            start: Instant::now(),
+            manifest: Manifest {
+                crate_version: env!("CARGO_PKG_VERSION").to_string(),
+                run_mode: run_mode.to_string(),
+                start_time,
+                wall_time: 0.,
+                seed: settings.mem_settings.seed,
+                config_checksum,
+                effective_config: effective_config.clone(),
+                matrices: vec![],
+                errors: Default::default(),
+                artifacts: vec![],
+            },
+        };
+        output.write_string(EFFECTIVE_CONFIG_FILE, &effective_config)?;
+        Ok(output)
+    }
+
+    /// the absolute path of an artifact, the parent directory is created and the artifact is recorded
+    pub fn artifact_path(&mut self, relative: impl AsRef<Path>) -> Result<PathBuf> {
+        let relative = relative.as_ref();
+        let path = self.dir.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if !self.manifest.artifacts.iter().any(|x| x == relative) {
+            self.manifest.artifacts.push(relative.to_path_buf());
+        }
+        Ok(path)
+    }
+
+    pub fn write_json<T: Serialize + ?Sized>(
+        &mut self,
+        relative: impl AsRef<Path>,
+        value: &T,
+    ) -> Result<PathBuf> {
+        let path = self.artifact_path(relative)?;
+        serde_json::to_writer_pretty(File::create(&path)?, value)
+            .wrap_err(format!("fail to write {path:?}"))?;
+        Ok(path)
+    }
+
+    pub fn write_string(&mut self, relative: impl AsRef<Path>, content: &str) -> Result<PathBuf> {
+        let path = self.artifact_path(relative)?;
+        fs::write(&path, content).wrap_err(format!("fail to write {path:?}"))?;
+        Ok(path)
+    }
+
+    /// record a matrix, its checksum is computed from the file
+    pub fn add_matrix(
+        &mut self,
+        file: &Path,
+        shape: (usize, usize),
+        nnz: usize,
+        artifacts: Vec<PathBuf>,
+    ) -> Result<()> {
+        let checksum = format!("{:016x}", fnv1a64(&fs::read(file)?));
+        self.manifest.matrices.push(MatrixRecord {
+            file: file.to_path_buf(),
+            checksum,
+            rows: shape.0,
+            cols: shape.1,
+            nnz,
+            error: None,
+            artifacts,
+        });
+        Ok(())
+    }
+
+    /// record a matrix that failed
+    pub fn add_failed_matrix(&mut self, file: &Path, error: &eyre::Report) {
+        let checksum = fs::read(file)
+            .map(|content| format!("{:016x}", fnv1a64(&content)))
+            .unwrap_or_default();
+        self.manifest.matrices.push(MatrixRecord {
+            file: file.to_path_buf(),
+            checksum,
+            rows: 0,
+            cols: 0,
+            nnz: 0,
+            error: Some(format!("{error:#}")),
+            artifacts: vec![],
+        });
+    }
+
+    /// record a run that failed and is not of one matrix
+    pub fn add_failed_run(&mut self, name: &str, error: &eyre::Report) {
+        self.manifest
+            .errors
+            .insert(name.to_string(), format!("{error:#}"));
+    }
+
+    /// write the manifest, return the run directory
+    pub fn finish(mut self) -> Result<PathBuf> {
+        self.manifest.wall_time = self.start.elapsed().as_secs_f64();
+        let path = self.dir.join(MANIFEST_FILE);
+        serde_json::to_writer_pretty(File::create(&path)?, &self.manifest)
+            .wrap_err(format!("fail to write {path:?}"))?;
+        Ok(self.dir)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::settings::{ConfigLayer, EffectiveSettings};
+
+    use super::*;
+
+    #[test]
+    fn fnv_test() {
+        assert_eq!(fnv1a64(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a64(b"a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn manifest_test() {
+        let output_dir = std::env::temp_dir().join("spmm_pim_manifest_test");
+        let _ = fs::remove_dir_all(&output_dir);
+        let effective = EffectiveSettings::load(&[
+            ConfigLayer::Set(format!("output_dir=\"{}\"", output_dir.display())),
+            ConfigLayer::Set("run_name=\"test\"".to_string()),
+        ])
+        .unwrap();
+        let mut output = RunOutput::create(&effective, "sim").unwrap();
+        output.add_failed_run("jobs", &eyre::eyre!("no job"));
+        let artifact = output.write_json("bfwa62/full_time.json", &1.0).unwrap();
+        output
+            .add_matrix(
+                Path::new("mtx/bfwa62.mtx"),
+                (62, 62),
+                450,
+                vec!["bfwa62/full_time.json".into()],
+            )
+            .unwrap();
+        let dir = output.finish().unwrap();
+        assert_eq!(dir, output_dir.join("test"));
+        assert!(artifact.exists());
+
+        let manifest = Manifest::load(&dir).unwrap();
+        assert_eq!(manifest.crate_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            manifest.artifacts,
+            vec![
+                PathBuf::from(EFFECTIVE_CONFIG_FILE),
+                PathBuf::from("bfwa62/full_time.json")
+            ]
+        );
+        assert_eq!(manifest.matrices[0].checksum.len(), 16);
+        assert_eq!(manifest.errors["jobs"], "no job");
+        assert_eq!(
+            fs::read_to_string(dir.join(EFFECTIVE_CONFIG_FILE)).unwrap(),
+            manifest.effective_config
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//! the `serve` subcommand: a local http server that runs the submitted jobs on a pool of workers.
+//! ```text
+//! POST /jobs              {"matrix": "mtx/test.mtx", "mode": "Sim", "set": ["banks=4"], "mem_settings": {"row_mapping": "Chunk"}}
+//!                         -> {"id": 0}
+//! GET  /jobs              -> [{"id": 0, "matrix": "mtx/test.mtx", "status": "Running"}]
+//! GET  /jobs/{id}         -> {"status": "Done", "result": {...}}
+//! GET  /jobs/{id}/result  -> the result, 409 until the job is done
+//! ```
+
+use std::{
+    io::Read,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sprs::CsMat;
+use tracing::{error, info};
+
+use crate::{
+    args::{RunMode, ServeArgs},
+    result::Results,
+    settings::{ConfigLayer, EffectiveSettings, MemSettings},
+    sim::Simulator,
+    two_matrix::TwoMatrix,
+    utils::run::run_exp_csr,
+};
+
+/// the body of `POST /jobs`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JobRequest {
+    pub matrix: PathBuf,
+    /// `Sim` if not set, like the cli
+    #[serde(default)]
+    pub mode: Option<RunMode>,
+    /// the overrides like `--set`, applied over the config of the server
+    #[serde(default)]
+    pub set: Vec<String>,
+    /// the fields of `mem_settings` changed after `set`, like the python `MemSettings.updated`
+    #[serde(default)]
+    pub mem_settings: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done { result: Value },
+    Failed { error: String },
+}
+
+/// the jobs of the server, the id of a job is its index
+pub struct JobServer {
+    layers: Vec<ConfigLayer>,
+    jobs: Mutex<Vec<(JobRequest, JobState)>>,
+    queue: SyncSender<usize>,
+}
+
+impl JobServer {
+    /// start `workers` workers, at most `queue_size` jobs wait for them
+    pub fn start(layers: Vec<ConfigLayer>, workers: usize, queue_size: usize) -> Arc<Self> {
+        let (queue, receiver) = mpsc::sync_channel(queue_size);
+        let server = Arc::new(Self {
+            layers,
+            jobs: Mutex::new(vec![]),
+            queue,
+        });
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..workers.max(1) {
+            let server = server.clone();
+            let receiver = receiver.clone();
+            thread::spawn(move || server.work(&receiver));
+        }
+        server
+    }
+
+    fn work(&self, receiver: &Mutex<Receiver<usize>>) {
+        loop {
+            let id = match receiver.lock().unwrap().recv() {
+                Ok(id) => id,
+                Err(_) => return,
+            };
+            let request = self.set_state(id, JobState::Running);
+            info!("job {id}: {:?}", request);
+            // a panic of the simulator only fails its job
+            let state = match catch_unwind(AssertUnwindSafe(|| self.run_job(&request))) {
+                Ok(Ok(result)) => JobState::Done { result },
+                Ok(Err(e)) => JobState::Failed {
+                    error: format!("{e:?}"),
+                },
+                Err(_) => JobState::Failed {
+                    error: "the simulator panicked".to_string(),
+                },
+            };
+            if let JobState::Failed { error } = &state {
+                error!("job {id}: {error}");
+            }
+            self.set_state(id, state);
+        }
+    }
+
+    /// set the state of a job and return its request
+    fn set_state(&self, id: usize, state: JobState) -> JobRequest {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs[id].1 = state;
+        jobs[id].0.clone()
+    }
+
+    /// the settings of a job, checked with the rows of its B
+    fn mem_settings(&self, request: &JobRequest, b_rows: usize) -> Result<MemSettings> {
+        let mut layers = self.layers.clone();
+        layers.extend(request.set.iter().cloned().map(ConfigLayer::Set));
+        let mut mem_settings = EffectiveSettings::load(&layers)?.settings.mem_settings;
+        if let Some(values) = &request.mem_settings {
+            mem_settings = mem_settings.merged(values.clone())?;
+        }
+        mem_settings.validate_with_rows(b_rows)?;
+        Ok(mem_settings)
+    }
+
+    /// run a job like the cli, the result is the json of `SimReport` or of the pim `Results`
+    fn run_job(&self, request: &JobRequest) -> Result<Value> {
+        let csr: CsMat<i32> = sprs::io::read_matrix_market(&request.matrix)
+            .wrap_err(format!("{:?} is error!", request.matrix))?
+            .to_csr();
+        // B is A^T in both modes
+        let mem_settings = self.mem_settings(request, csr.cols())?;
+        let result = match request.mode.clone().unwrap_or(RunMode::Sim) {
+            RunMode::Sim => {
+                let trans_pose = csr.transpose_view().to_csr();
+                let two_matrix = TwoMatrix::new(csr, trans_pose);
+                serde_json::to_value(Simulator::run(&mem_settings, two_matrix)?)?
+            }
+            RunMode::Pim => {
+                let path = request.matrix.as_path();
+                let mut full_result = Results { all: vec![] };
+                let mut errors = vec![];
+                for (size, result) in [
+                    ("1x1", run_exp_csr::<1, 1>(path, &csr, &mem_settings)),
+                    ("4x4", run_exp_csr::<4, 4>(path, &csr, &mem_settings)),
+                ] {
+                    match result {
+                        Ok(result) => full_result.all.push(result),
+                        Err(e) => errors.push(format!("{size}: {e:?}")),
+                    }
+                }
+                if !errors.is_empty() {
+                    eyre::bail!(
+                        "fail to run the pim model of {:?}: {}",
+                        path,
+                        errors.join("; ")
+                    );
+                }
+                serde_json::to_value(full_result)?
+            }
+        };
+        Ok(result)
+    }
+
+    /// queue a job, fails when the queue is full
+    pub fn submit(&self, request: JobRequest) -> Result<usize> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.len();
+        match self.queue.try_send(id) {
+            Ok(()) => {
+                jobs.push((request, JobState::Queued));
+                Ok(id)
+            }
+            Err(TrySendError::Full(_)) => eyre::bail!("the queue is full, try later"),
+            Err(TrySendError::Disconnected(_)) => eyre::bail!("the workers are stopped"),
+        }
+    }
+
+    pub fn state(&self, id: usize) -> Option<JobState> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|(_, state)| state.clone())
+    }
+
+    /// the status code and the json body of a request
+    pub fn handle(&self, method: &str, url: &str, body: &str) -> (u16, Value) {
+        let path = url.split('?').next().unwrap_or_default();
+        let segments = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        let not_found = (
+            404,
+            json!({ "error": format!("no route for {method} {path}") }),
+        );
+        match (method, segments.as_slice()) {
+            ("POST", ["jobs"]) => match serde_json::from_str::<JobRequest>(body) {
+                Ok(request) => match self.submit(request) {
+                    Ok(id) => (202, json!({ "id": id })),
+                    Err(e) => (503, json!({ "error": e.to_string() })),
+                },
+                Err(e) => (400, json!({ "error": e.to_string() })),
+            },
+            ("GET", ["jobs"]) => {
+                let jobs = self.jobs.lock().unwrap();
+                let list = jobs
+                    .iter()
+                    .enumerate()
+                    .map(|(id, (request, state))| {
+                        let status = match state {
+                            JobState::Queued => "Queued",
+                            JobState::Running => "Running",
+                            JobState::Done { .. } => "Done",
+                            JobState::Failed { .. } => "Failed",
+                        };
+                        json!({ "id": id, "matrix": request.matrix, "status": status })
+                    })
+                    .collect::<Vec<_>>();
+                (200, Value::Array(list))
+            }
+            ("GET", ["jobs", id, rest @ ..]) => {
+                let state = match id.parse().ok().and_then(|id| self.state(id)) {
+                    Some(state) => state,
+                    None => return (404, json!({ "error": format!("no job {id}") })),
+                };
+                match (rest, state) {
+                    ([], state) => (200, serde_json::to_value(state).unwrap()),
+                    (["result"], JobState::Done { result }) => (200, result),
+                    (["result"], state) => (409, serde_json::to_value(state).unwrap()),
+                    _ => not_found,
+                }
+            }
+            _ => not_found,
+        }
+    }
+}
+
+fn header(name: &str, value: &str) -> tiny_http::Header {
+    tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+pub fn main(args: &ServeArgs) -> Result<()> {
+    let layers = args.config.layers();
+    // fail early on a bad config instead of in every job
+    EffectiveSettings::load(&layers)?
+        .settings
+        .mem_settings
+        .validate()?;
+    let server = JobServer::start(layers, args.workers, args.queue_size);
+    let http = tiny_http::Server::http(&args.addr)
+        .map_err(|e| eyre::eyre!("fail to listen on {}: {}", args.addr, e))?;
+    info!(
+        "serving on http://{} with {} workers",
+        args.addr, args.workers
+    );
+    for mut request in http.incoming_requests() {
+        let mut body = String::new();
+        let (code, value) = match request.as_reader().read_to_string(&mut body) {
+            // the preflight of the browsers, the headers below allow everything
+            Ok(_) if request.method() == &tiny_http::Method::Options => (204, Value::Null),
+            Ok(_) => server.handle(request.method().as_str(), request.url(), &body),
+            Err(e) => (400, json!({ "error": e.to_string() })),
+        };
+        let response = tiny_http::Response::from_string(value.to_string())
+            .with_status_code(code)
+            .with_header(header("Content-Type", "application/json"))
+            .with_header(header("Access-Control-Allow-Origin", "*"))
+            .with_header(header("Access-Control-Allow-Headers", "Content-Type"))
+            .with_header(header("Access-Control-Allow-Methods", "GET, POST, OPTIONS"));
+        if let Err(e) = request.respond(response) {
+            error!("fail to respond: {e}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn jobs() {
+        let layers = vec![
+            ConfigLayer::File("configs/debug.toml".into()),
+            ConfigLayer::File("configs/ddr4.toml".into()),
+        ];
+        let server = JobServer::start(layers, 2, 4);
+        let (code, body) = server.handle(
+            "POST",
+            "/jobs",
+            r#"{"matrix": "mtx/test.mtx", "set": ["banks=4"]}"#,
+        );
+        assert_eq!(code, 202);
+        let id = body["id"].as_u64().unwrap();
+        let (code, _) = server.handle("POST", "/jobs", r#"{"matrix": "mtx/missing.mtx"}"#);
+        assert_eq!(code, 202);
+        // the 6 rows of B can not be chunked over the banks of ddr4
+        let (code, _) = server.handle(
+            "POST",
+            "/jobs",
+            r#"{"matrix": "mtx/test.mtx", "mem_settings": {"row_mapping": "Chunk"}}"#,
+        );
+        assert_eq!(code, 202);
+        assert_eq!(server.handle("POST", "/jobs", "{}").0, 400);
+        assert_eq!(server.handle("GET", "/jobs/9", "").0, 404);
+        assert_eq!(server.handle("DELETE", "/jobs", "").0, 404);
+
+        // a dead worker fails the test instead of hanging it
+        let deadline = Instant::now() + Duration::from_secs(120);
+        let wait = |id: usize| loop {
+            match server.state(id).unwrap() {
+                JobState::Queued | JobState::Running => {
+                    assert!(
+                        Instant::now() < deadline,
+                        "the job {id} is not done in time"
+                    );
+                    thread::sleep(Duration::from_millis(10))
+                }
+                state => return state,
+            }
+        };
+        assert!(matches!(wait(id as usize), JobState::Done { .. }));
+        let (code, result) = server.handle("GET", &format!("/jobs/{id}/result"), "");
+        assert_eq!(code, 200);
+        assert!(result["cycles"].as_f64().unwrap() > 0.);
+        assert!(matches!(wait(1), JobState::Failed { .. }));
+        assert_eq!(server.handle("GET", "/jobs/1/result", "").0, 409);
+        match wait(2) {
+            JobState::Failed { error } => assert!(!error.contains("panicked"), "{error}"),
+            state => panic!("{state:?}"),
+        }
+
+        let (_, list) = server.handle("GET", "/jobs", "");
+        assert_eq!(list[0]["status"], "Done");
+        assert_eq!(list[1]["status"], "Failed");
+    }
+}
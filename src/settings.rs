@@ -1,5 +1,8 @@
 use config::File;
+use config::FileFormat;
 use enum_as_inner::EnumAsInner;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -7,40 +10,56 @@ use config::Config;
 use eyre::Context;
 use eyre::Result;
 use itertools::Itertools;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
 
-/// the toml file do not support enum with value
-pub enum RealRowMapping {
-    Chunk,
-    Interleaved(usize),
-}
+use crate::{
+    fault::{FaultMap, Faults},
+    pim::read_explicit_mapping,
+    reorder_calculator::ReorderMode,
+    replication::Replication,
+    sim::{
+        bank_cache::CachePolicy,
+        buffer_status::BufferAdmission,
+        credit::FlowControl,
+        id_translation::{BankID, ChannelID, ChipID},
+        job::{JobArbitration, JobSharing, JobSpec},
+        merger_status::MergerAllocation,
+        spill::Spill,
+        stream::StreamSpec,
+    },
+    utils::generator::WorkloadSpec,
+};
 
-#[derive(Debug, Deserialize, Clone)]
+/// how the rows of B are placed in the banks, see `ResolvedRowMapping` for the mapping of one matrix.
+/// the variants with a value are written as a table in toml: `row_mapping = { Explicit = "mapping.txt" }`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, EnumAsInner)]
 pub enum RowMapping {
+    /// the rows are split into one continuous chunk per bank
     Chunk,
+    /// every `interleaved_chunk` rows go to the next bank, the channel changes first
     Interleaved,
+    /// like `Interleaved`, but the bank is the xor of all bank-sized digits of the chunk id,
+    /// so the rows with a stride of the number of banks are spread over the banks
+    Xor,
+    /// the longest row goes first to the bank with the least nonzeros
+    NnzBalanced,
+    /// a file with one flat bank id (`(channel * chips + chip) * banks + bank`) per row, `#` starts a comment
+    Explicit(PathBuf),
 }
 impl Default for RowMapping {
     fn default() -> Self {
         RowMapping::Chunk
     }
 }
-impl RowMapping {
-    pub fn to_real_row_mapping(&self, size: usize) -> RealRowMapping {
-        match self {
-            RowMapping::Chunk => RealRowMapping::Chunk,
-            RowMapping::Interleaved => RealRowMapping::Interleaved(size),
-        }
-    }
-}
 
-#[derive(Debug, Deserialize, Clone, Default, EnumAsInner)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, EnumAsInner)]
 pub enum BufferMode {
     #[default]
     BindMerger,
     Standalone,
 }
-#[derive(Debug, Deserialize, Clone, Default, EnumAsInner)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, EnumAsInner)]
 pub enum TaskSchedulerMode {
     #[default]
     Sequence,
@@ -48,7 +67,130 @@ pub enum TaskSchedulerMode {
     ChunkShuffle,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// the capacity of the queues between the components of a level, the same for the dimm, the channels and the chips
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct QueueSizes {
+    /// the signals from the lower pes to the signal collector
+    pub signal: usize,
+    /// the ready queue ids from the signal collector to the data collector
+    pub ready: usize,
+    pub collector_to_dispatcher: usize,
+    pub dispatcher_to_merger: usize,
+    /// also used by the bank mergers
+    pub merger_to_sender: usize,
+}
+
+impl Default for QueueSizes {
+    fn default() -> Self {
+        Self {
+            signal: 128,
+            ready: 128,
+            collector_to_dispatcher: 1,
+            dispatcher_to_merger: 1,
+            merger_to_sender: 1,
+        }
+    }
+}
+
+/// the settings of some channels, chips or banks, a missing selector matches all of them. in toml:
+/// ```toml
+/// # a stronger merger on chip 0 of every channel
+/// [[mem_settings.overrides]]
+/// chip = 0
+/// chip_merger_size = 8
+/// ```
+/// the overrides are applied in order, the channel fields need no chip and bank, the chip fields need no bank
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct InstanceOverride {
+    pub channel: Option<usize>,
+    pub chip: Option<usize>,
+    pub bank: Option<usize>,
+
+    pub channel_merger_size: Option<usize>,
+    pub channel_merger_count: Option<usize>,
+    pub channel_buffer_lines: Option<usize>,
+    pub channel_buffer_policy: Option<BufferAdmission>,
+    pub channel_merger_policy: Option<MergerAllocation>,
+
+    pub chip_merger_size: Option<usize>,
+    pub chip_merger_count: Option<usize>,
+    pub chip_buffer_lines: Option<usize>,
+    pub chip_buffer_policy: Option<BufferAdmission>,
+    pub chip_merger_policy: Option<MergerAllocation>,
+
+    pub bank_merger_size: Option<usize>,
+    pub bank_merger_count: Option<usize>,
+    pub bank_adder_size: Option<usize>,
+    pub bank_cache_size: Option<usize>,
+    pub bank_cache_policy: Option<CachePolicy>,
+}
+
+/// copy the fields that are set in the override
+macro_rules! apply_override {
+    ($settings:expr, $instance:expr, $($field:ident),*) => {
+        $(
+            if let Some(value) = $instance.$field {
+                $settings.$field = value;
+            }
+        )*
+    };
+}
+
+impl InstanceOverride {
+    /// `chip` and `bank` are None for the instances of the upper levels, they only match the overrides without them
+    fn matches(&self, channel: usize, chip: Option<usize>, bank: Option<usize>) -> bool {
+        let selects = |selector: Option<usize>, id: Option<usize>| match (selector, id) {
+            (None, _) => true,
+            (Some(selector), Some(id)) => selector == id,
+            (Some(_), None) => false,
+        };
+        selects(self.channel, Some(channel)) && selects(self.chip, chip) && selects(self.bank, bank)
+    }
+
+    fn has_channel_fields(&self) -> bool {
+        self.channel_merger_size.is_some()
+            || self.channel_merger_count.is_some()
+            || self.channel_buffer_lines.is_some()
+            || self.channel_buffer_policy.is_some()
+            || self.channel_merger_policy.is_some()
+    }
+
+    fn has_chip_fields(&self) -> bool {
+        self.chip_merger_size.is_some()
+            || self.chip_merger_count.is_some()
+            || self.chip_buffer_lines.is_some()
+            || self.chip_buffer_policy.is_some()
+            || self.chip_merger_policy.is_some()
+    }
+
+    fn apply(&self, settings: &mut MemSettings) {
+        apply_override!(
+            settings,
+            self,
+            channel_merger_size,
+            channel_merger_count,
+            channel_buffer_lines,
+            channel_buffer_policy,
+            channel_merger_policy,
+            chip_merger_size,
+            chip_merger_count,
+            chip_buffer_lines,
+            chip_buffer_policy,
+            chip_merger_policy,
+            bank_merger_size,
+            bank_merger_count,
+            bank_adder_size,
+            bank_cache_size,
+            bank_cache_policy
+        );
+    }
+}
+
+/// the missing fields are filled by `MemSettings::default()`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
 pub struct MemSettings {
     pub buffer_mode: BufferMode,
 
@@ -95,8 +237,36 @@ pub struct MemSettings {
     pub dimm_buffer_lines: usize,
     pub channel_buffer_lines: usize,
     pub chip_buffer_lines: usize,
+    /// which waiting line gets a free buffer line, one policy per level
+    pub dimm_buffer_policy: BufferAdmission,
+    pub channel_buffer_policy: BufferAdmission,
+    pub chip_buffer_policy: BufferAdmission,
+    /// which idle merger takes a task, one policy per level
+    pub dimm_merger_policy: MergerAllocation,
+    pub channel_merger_policy: MergerAllocation,
+    pub chip_merger_policy: MergerAllocation,
+    /// write the partial sums refused by a full chip or channel buffer into a dram region
+    pub spill: Spill,
+    /// the credits of the task links between the levels
+    pub flow_control: FlowControl,
+    /// the queues inside the dimm, channel and chip levels
+    pub queue_sizes: QueueSizes,
     pub task_scheduler_mode: TaskSchedulerMode,
     pub task_scheduler_chunk_size: usize,
+    /// the seed of the shuffle task schedulers
+    pub seed: u64,
+    /// copy the hot rows of B into several banks
+    pub replication: Replication,
+    /// the failed and slow banks and chips
+    pub faults: Faults,
+    /// the bytes of the B row cache in each bank, 0 disables it
+    pub bank_cache_size: usize,
+    pub bank_cache_policy: CachePolicy,
+    /// the settings of some channels, chips or banks, see `InstanceOverride`
+    pub overrides: Vec<InstanceOverride>,
+    /// which job sends its next task when several jobs share the dimm
+    pub job_arbitration: JobArbitration,
+    pub job_sharing: JobSharing,
 }
 
 impl Default for MemSettings {
@@ -126,18 +296,64 @@ impl Default for MemSettings {
             dimm_buffer_lines: 2,
             channel_buffer_lines: 2,
             chip_buffer_lines: 2,
+            dimm_buffer_policy: Default::default(),
+            channel_buffer_policy: Default::default(),
+            chip_buffer_policy: Default::default(),
+            dimm_merger_policy: Default::default(),
+            channel_merger_policy: Default::default(),
+            chip_merger_policy: Default::default(),
+            spill: Default::default(),
+            flow_control: Default::default(),
+            queue_sizes: Default::default(),
             task_scheduler_mode: Default::default(),
             task_scheduler_chunk_size: Default::default(),
+            seed: 0,
+            replication: Default::default(),
+            faults: Default::default(),
+            overrides: vec![],
+            bank_cache_size: 0,
+            bank_cache_policy: Default::default(),
+            job_arbitration: Default::default(),
+            job_sharing: Default::default(),
         }
     }
 }
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
 pub struct Settings {
     pub mtx_files: Vec<PathBuf>,
     pub result_file: PathBuf,
+    /// the root of all run directories
+    pub output_dir: PathBuf,
+    /// the name of the run directory, a new unique name is generated if not set
+    pub run_name: Option<String>,
+    /// the synthetic matrices that are generated into the run directory and used with `mtx_files`
+    pub workloads: Vec<WorkloadSpec>,
+    /// the permutation applied to every matrix before the simulation
+    pub reorder: ReorderMode,
+    /// the jobs that run together on the dimm, `mtx_files` are run alone as before
+    pub jobs: Vec<JobSpec>,
+    /// the batches of A that are run against one resident B
+    pub stream: Option<StreamSpec>,
     pub mem_settings: MemSettings,
 }
 
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mtx_files: vec![],
+            result_file: "results/result.json".into(),
+            output_dir: "results".into(),
+            run_name: None,
+            workloads: vec![],
+            reorder: Default::default(),
+            jobs: vec![],
+            stream: None,
+            mem_settings: Default::default(),
+        }
+    }
+}
+
 impl Settings {
     pub fn new(config: &[impl AsRef<Path>]) -> Result<Self> {
         let names = config
@@ -157,6 +373,63 @@ impl Settings {
 }
 
 impl MemSettings {
+    /// the settings of one channel, the chip and bank fields are the ones of its chips and banks without a selector
+    pub fn for_channel(&self, channel: ChannelID) -> Self {
+        self.with_overrides(channel, None, None)
+    }
+
+    pub fn for_chip(&self, (channel, chip): ChipID) -> Self {
+        self.with_overrides(channel, Some(chip), None)
+    }
+
+    pub fn for_bank(&self, ((channel, chip), bank): BankID) -> Self {
+        self.with_overrides(channel, Some(chip), Some(bank))
+    }
+
+    fn with_overrides(&self, channel: usize, chip: Option<usize>, bank: Option<usize>) -> Self {
+        let mut settings = self.clone();
+        for instance in &self.overrides {
+            if instance.matches(channel, chip, bank) {
+                instance.apply(&mut settings);
+            }
+        }
+        settings
+    }
+
+    /// the settings with the fields of `values` changed, the tables like `spill` are merged field by field.
+    /// a table with a key that is not in the current value, like another variant of a policy, replaces it
+    pub fn merged(&self, values: serde_json::Value) -> Result<Self> {
+        fn merge(base: &mut serde_json::Value, values: serde_json::Value) {
+            match (base, values) {
+                (serde_json::Value::Object(base), serde_json::Value::Object(values))
+                    if values.keys().all(|key| base.contains_key(key)) =>
+                {
+                    for (key, value) in values {
+                        merge(base.entry(key).or_insert(serde_json::Value::Null), value);
+                    }
+                }
+                (base, value) => *base = value,
+            }
+        }
+        let mut settings = serde_json::to_value(self)?;
+        if let (Some(fields), Some(values)) = (settings.as_object(), values.as_object()) {
+            let unknown = values
+                .keys()
+                .filter(|key| !fields.contains_key(*key))
+                .collect_vec();
+            if !unknown.is_empty() {
+                eyre::bail!("unknown fields of mem_settings: {:?}", unknown);
+            }
+        }
+        merge(&mut settings, values);
+        serde_json::from_value(settings).wrap_err("fail to merge the settings")
+    }
+
+    /// the toml of the settings, the tables like `spill` go after the plain values
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::Value::try_from(self)?.to_string())
+    }
+
     pub fn new(config: &[impl AsRef<Path>]) -> Result<Self> {
         let names = config
             .iter()
@@ -173,3 +446,1000 @@ impl MemSettings {
         Ok(ret)
     }
 }
+
+/// the named presets that can be selected by `--preset`, they are built into the binary
+pub const PRESETS: &[(&str, &str)] = &[
+    ("default", include_str!("../configs/default.toml")),
+    ("ddr4", include_str!("../configs/ddr4.toml")),
+    ("ddr4chunk", include_str!("../configs/ddr4chunk.toml")),
+    ("hbm", include_str!("../configs/hmb.toml")),
+    ("debug", include_str!("../configs/debug.toml")),
+    ("large", include_str!("../configs/large.toml")),
+    ("all", include_str!("../configs/all.config.toml")),
+];
+
+/// the keys of `Settings` that are not in mem_settings
+const TOP_LEVEL_KEYS: &[&str] = &[
+    "mtx_files",
+    "result_file",
+    "output_dir",
+    "run_name",
+    "workloads",
+    "reorder",
+    "jobs",
+    "stream",
+];
+
+/// one layer of the settings, the later layers override the former ones
+#[derive(Debug, Clone)]
+pub enum ConfigLayer {
+    /// a name in `PRESETS`
+    Preset(String),
+    /// a toml file
+    File(PathBuf),
+    /// `key=value`, the key is the dotted path like `mem_settings.banks`,
+    /// the key of mem_settings can omit the `mem_settings.` prefix
+    Set(String),
+}
+
+impl Display for ConfigLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigLayer::Preset(name) => write!(f, "preset:{name}"),
+            ConfigLayer::File(path) => write!(f, "{}", path.display()),
+            ConfigLayer::Set(set) => write!(f, "--set {set}"),
+        }
+    }
+}
+
+impl ConfigLayer {
+    /// the toml content of this layer
+    fn to_toml(&self) -> Result<toml::Value> {
+        match self {
+            ConfigLayer::Preset(name) => {
+                let (_, content) = PRESETS
+                    .iter()
+                    .find(|(preset, _)| preset == name)
+                    .ok_or_else(|| {
+                        eyre::eyre!(
+                            "unknown preset: {name}, available: {}",
+                            PRESETS.iter().map(|(name, _)| name).join(", ")
+                        )
+                    })?;
+                toml::from_str(content).wrap_err(format!("fail to parse preset {name}"))
+            }
+            ConfigLayer::File(path) => {
+                let content = std::fs::read_to_string(path)
+                    .wrap_err(format!("fail to read config file {path:?}"))?;
+                toml::from_str(&content).wrap_err(format!("fail to parse config file {path:?}"))
+            }
+            ConfigLayer::Set(set) => {
+                let (key, value) = set
+                    .split_once('=')
+                    .ok_or_else(|| eyre::eyre!("--set should be key=value, got: {set}"))?;
+                let key = key.trim();
+                let value = value.trim();
+                // the value is a toml value, if it's not, treat it as a string
+                let value = toml::from_str::<toml::Value>(&format!("v = {value}"))
+                    .ok()
+                    .and_then(|v| v.get("v").cloned())
+                    .unwrap_or_else(|| toml::Value::String(value.to_string()));
+                let path = if key.contains('.') || TOP_LEVEL_KEYS.contains(&key) {
+                    key.split('.').collect_vec()
+                } else {
+                    vec!["mem_settings", key]
+                };
+                Ok(path.into_iter().rev().fold(value, |value, key| {
+                    toml::Value::Table(toml::value::Table::from_iter([(key.to_string(), value)]))
+                }))
+            }
+        }
+    }
+}
+
+/// flatten the toml tables into dotted keys
+fn flatten_toml(prefix: &str, value: &toml::Value, out: &mut Vec<String>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten_toml(&key, value, out);
+            }
+        }
+        _ => out.push(prefix.to_string()),
+    }
+}
+
+/// the final settings after merging all layers, and which layer set each value
+#[derive(Debug)]
+pub struct EffectiveSettings {
+    pub settings: Settings,
+    /// dotted key to the layer that set it, the keys not in it are defaults
+    pub provenance: BTreeMap<String, String>,
+}
+
+impl EffectiveSettings {
+    /// merge the layers in order
+    pub fn load(layers: &[ConfigLayer]) -> Result<Self> {
+        let mut builder = Config::builder();
+        let mut provenance = BTreeMap::new();
+        for layer in layers {
+            let value = layer.to_toml()?;
+            let mut keys = vec![];
+            flatten_toml("", &value, &mut keys);
+            for key in keys {
+                provenance.insert(key, layer.to_string());
+            }
+            builder =
+                builder.add_source(File::from_str(&toml::to_string(&value)?, FileFormat::Toml));
+        }
+        let settings: Settings = builder
+            .build()
+            .wrap_err("fail to build setting")?
+            .try_deserialize()
+            .wrap_err("fail to deserialize")?;
+
+        // the keys that are not a field are ignored by serde, warn about them
+        let mut known = vec![];
+        flatten_toml("", &toml::Value::try_from(&settings)?, &mut known);
+        provenance.retain(|key, layer| {
+            let is_known = known.contains(key);
+            if !is_known {
+                warn!("unknown setting {key} in {layer} is ignored");
+            }
+            is_known
+        });
+        Ok(Self {
+            settings,
+            provenance,
+        })
+    }
+
+    /// the effective settings as toml, each value is commented with the layer that set it.
+    /// the output can be loaded again as a config file.
+    pub fn to_toml(&self) -> Result<String> {
+        let value = toml::Value::try_from(&self.settings)?;
+        let mut output = String::new();
+        self.write_table("", value.as_table().unwrap(), &mut output);
+        Ok(output)
+    }
+
+    fn write_table(&self, prefix: &str, table: &toml::value::Table, output: &mut String) {
+        let full_key = |key: &str| {
+            if prefix.is_empty() {
+                key.to_string()
+            } else {
+                format!("{prefix}.{key}")
+            }
+        };
+        // the values must be written before the sub tables
+        for (key, value) in table.iter().filter(|(_, v)| !v.is_table()) {
+            let source = self
+                .provenance
+                .get(&full_key(key))
+                .map(String::as_str)
+                .unwrap_or("default");
+            output.push_str(&format!("{key} = {value} # {source}\n"));
+        }
+        for (key, value) in table.iter() {
+            if let toml::Value::Table(sub_table) = value {
+                output.push_str(&format!("\n[{}]\n", full_key(key)));
+                self.write_table(&full_key(key), sub_table, output);
+            }
+        }
+    }
+}
+
+/// one problem found by `MemSettings::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettingsProblem {
+    pub field: &'static str,
+    pub message: String,
+}
+
+/// all problems found by `MemSettings::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidSettings {
+    pub problems: Vec<SettingsProblem>,
+}
+
+impl Display for InvalidSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid mem_settings:")?;
+        for problem in &self.problems {
+            write!(f, "\n  {}: {}", problem.field, problem.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidSettings {}
+
+impl MemSettings {
+    /// check the settings before building anything, return all problems at once
+    pub fn validate(&self) -> Result<(), InvalidSettings> {
+        let mut problems = self.value_problems();
+        problems.extend(self.override_problems());
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidSettings { problems })
+        }
+    }
+
+    /// the problems of the overrides, and the problems of the instances they change
+    fn override_problems(&self) -> Vec<SettingsProblem> {
+        let mut problems = vec![];
+        for (index, instance) in self.overrides.iter().enumerate() {
+            let out_of_range = |selector: Option<usize>, count: usize| {
+                selector.map_or(false, |selector| selector >= count)
+            };
+            if out_of_range(instance.channel, self.channels)
+                || out_of_range(instance.chip, self.chips)
+                || out_of_range(instance.bank, self.banks)
+            {
+                problems.push(SettingsProblem {
+                    field: "overrides",
+                    message: format!(
+                        "the override {index} selects {:?}, there are {} channels of {} chips of {} banks",
+                        (instance.channel, instance.chip, instance.bank),
+                        self.channels,
+                        self.chips,
+                        self.banks
+                    ),
+                });
+            }
+            if (instance.chip.is_some() || instance.bank.is_some()) && instance.has_channel_fields()
+            {
+                problems.push(SettingsProblem {
+                    field: "overrides",
+                    message: format!(
+                        "the override {index} sets channel fields for a chip or a bank"
+                    ),
+                });
+            }
+            if instance.bank.is_some() && instance.has_chip_fields() {
+                problems.push(SettingsProblem {
+                    field: "overrides",
+                    message: format!("the override {index} sets chip fields for a bank"),
+                });
+            }
+        }
+        if self.overrides.is_empty() {
+            return problems;
+        }
+        // each problem is reported once, for the first instance that has it
+        let mut seen: BTreeSet<_> = self
+            .value_problems()
+            .into_iter()
+            .map(|problem| (problem.field, problem.message))
+            .collect();
+        let channels = (0..self.channels)
+            .map(|channel| (format!("channel {channel}"), self.for_channel(channel)));
+        let chips = (0..self.channels)
+            .cartesian_product(0..self.chips)
+            .map(|chip| (format!("chip {chip:?}"), self.for_chip(chip)));
+        let banks = (0..self.channels)
+            .cartesian_product(0..self.chips)
+            .cartesian_product(0..self.banks)
+            .map(|bank| (format!("bank {bank:?}"), self.for_bank(bank)));
+        for (name, settings) in channels.chain(chips).chain(banks) {
+            for problem in settings.value_problems() {
+                if seen.insert((problem.field, problem.message.clone())) {
+                    problems.push(SettingsProblem {
+                        field: problem.field,
+                        message: format!("{name}: {}", problem.message),
+                    });
+                }
+            }
+        }
+        problems
+    }
+
+    /// the problems of the values, without the overrides
+    fn value_problems(&self) -> Vec<SettingsProblem> {
+        let mut problems = vec![];
+        let mut check = |ok: bool, field: &'static str, message: String| {
+            if !ok {
+                problems.push(SettingsProblem { field, message });
+            }
+        };
+        for (field, value) in [
+            ("channels", self.channels),
+            ("chips", self.chips),
+            ("banks", self.banks),
+            ("row_size", self.row_size),
+            ("sender_store_size", self.sender_store_size),
+            ("bank_merger_count", self.bank_merger_count),
+            ("chip_merger_count", self.chip_merger_count),
+            ("channel_merger_count", self.channel_merger_count),
+            ("dimm_merger_count", self.dimm_merger_count),
+        ] {
+            check(
+                value > 0,
+                field,
+                format!("should be at least 1, got {value}"),
+            );
+        }
+        // a merger with one head never reduce the number of rows
+        for (field, value) in [
+            ("bank_merger_size", self.bank_merger_size),
+            ("chip_merger_size", self.chip_merger_size),
+            ("channel_merger_size", self.channel_merger_size),
+            ("dimm_merger_size", self.dimm_merger_size),
+        ] {
+            check(
+                value >= 2,
+                field,
+                format!("should be at least 2, got {value}"),
+            );
+        }
+        // see `BufferStatus::new`
+        for (field, value) in [
+            ("dimm_buffer_lines", self.dimm_buffer_lines),
+            ("channel_buffer_lines", self.channel_buffer_lines),
+            ("chip_buffer_lines", self.chip_buffer_lines),
+        ] {
+            check(
+                value >= 2,
+                field,
+                format!("should be at least 2, got {value}"),
+            );
+        }
+        // a reserve of all lines never admits a line before the oldest one, use InOrder instead
+        for (field, policy, lines) in [
+            (
+                "dimm_buffer_policy",
+                self.dimm_buffer_policy,
+                self.dimm_buffer_lines,
+            ),
+            (
+                "channel_buffer_policy",
+                self.channel_buffer_policy,
+                self.channel_buffer_lines,
+            ),
+            (
+                "chip_buffer_policy",
+                self.chip_buffer_policy,
+                self.chip_buffer_lines,
+            ),
+        ] {
+            if let BufferAdmission::Reserve(k) = policy {
+                check(
+                    k >= 1 && k < lines,
+                    field,
+                    format!(
+                        "should reserve 1 to {} lines, got {k}",
+                        lines.saturating_sub(1)
+                    ),
+                );
+            }
+        }
+        for (field, policy, count) in [
+            (
+                "dimm_merger_policy",
+                self.dimm_merger_policy,
+                self.dimm_merger_count,
+            ),
+            (
+                "channel_merger_policy",
+                self.channel_merger_policy,
+                self.channel_merger_count,
+            ),
+            (
+                "chip_merger_policy",
+                self.chip_merger_policy,
+                self.chip_merger_count,
+            ),
+        ] {
+            match policy {
+                MergerAllocation::SizeAware { wide, .. } => check(
+                    wide >= 1 && wide < count,
+                    field,
+                    format!(
+                        "should have 1 to {} wide mergers, got {wide}",
+                        count.saturating_sub(1)
+                    ),
+                ),
+                MergerAllocation::Reserve(k) => check(
+                    k >= 1 && k < count,
+                    field,
+                    format!(
+                        "should reserve 1 to {} mergers, got {k}",
+                        count.saturating_sub(1)
+                    ),
+                ),
+                _ => {}
+            }
+        }
+        for (field, value) in [
+            ("queue_sizes.signal", self.queue_sizes.signal),
+            ("queue_sizes.ready", self.queue_sizes.ready),
+            (
+                "queue_sizes.collector_to_dispatcher",
+                self.queue_sizes.collector_to_dispatcher,
+            ),
+            (
+                "queue_sizes.dispatcher_to_merger",
+                self.queue_sizes.dispatcher_to_merger,
+            ),
+            (
+                "queue_sizes.merger_to_sender",
+                self.queue_sizes.merger_to_sender,
+            ),
+        ] {
+            check(
+                value > 0,
+                field,
+                format!("should be at least 1, got {value}"),
+            );
+        }
+        for (name, link) in self.flow_control.links() {
+            check(
+                link.return_latency >= 0.,
+                "flow_control",
+                format!(
+                    "{name} should have a non-negative return_latency, got {}",
+                    link.return_latency
+                ),
+            );
+        }
+        if self.spill.is_enabled() {
+            check(
+                self.spill.nnz_bytes > 0,
+                "spill",
+                "should have nnz_bytes of at least 1".to_string(),
+            );
+            check(
+                self.spill.transfer_cycles >= 0.,
+                "spill",
+                format!(
+                    "should have non-negative transfer_cycles, got {}",
+                    self.spill.transfer_cycles
+                ),
+            );
+        }
+        match &self.row_mapping {
+            RowMapping::Interleaved | RowMapping::Xor => check(
+                self.interleaved_chunk > 0,
+                "interleaved_chunk",
+                format!(
+                    "should be at least 1 when row_mapping is {:?}",
+                    self.row_mapping
+                ),
+            ),
+            RowMapping::Explicit(path) => check(
+                path.is_file(),
+                "row_mapping",
+                format!("the mapping file {path:?} does not exist"),
+            ),
+            RowMapping::Chunk | RowMapping::NnzBalanced => {}
+        }
+        // the xor of the bank-sized digits can skip some banks when the count is not a power of two
+        let num_banks = self.channels * self.chips * self.banks;
+        if self.row_mapping.is_xor() {
+            check(
+                num_banks.is_power_of_two(),
+                "row_mapping",
+                format!("Xor needs a power of two banks, there are {num_banks}"),
+            );
+        }
+        if let Replication::TopK { replicas, .. } | Replication::Threshold { replicas, .. } =
+            self.replication
+        {
+            check(
+                replicas >= 2,
+                "replication",
+                format!("should have at least 2 replicas, got {replicas}"),
+            );
+        }
+        for &bank in &self.faults.failed_banks {
+            check(
+                bank < num_banks,
+                "faults",
+                format!("the failed bank {bank} is out of range, there are {num_banks} banks"),
+            );
+        }
+        for slow in &self.faults.slow_banks {
+            check(
+                slow.bank < num_banks && slow.slowdown >= 1.,
+                "faults",
+                format!(
+                    "the slow bank {} should be less than {num_banks} with a slowdown of at least 1, got {}",
+                    slow.bank, slow.slowdown
+                ),
+            );
+        }
+        let chips = self.faults.failed_chips.iter().cloned().chain(
+            self.faults
+                .slow_chips
+                .iter()
+                .map(|slow| (slow.channel, slow.chip)),
+        );
+        for (channel, chip) in chips {
+            check(
+                channel < self.channels && chip < self.chips,
+                "faults",
+                format!(
+                    "the chip ({channel}, {chip}) is out of range, there are {} channels of {} chips",
+                    self.channels, self.chips
+                ),
+            );
+        }
+        for slow in &self.faults.slow_chips {
+            check(
+                slow.slowdown >= 1.,
+                "faults",
+                format!(
+                    "the slow chip ({}, {}) should have a slowdown of at least 1, got {}",
+                    slow.channel, slow.chip, slow.slowdown
+                ),
+            );
+        }
+        if num_banks > 0 {
+            check(
+                FaultMap::new(self).failed_banks() < num_banks,
+                "faults",
+                "should keep at least one healthy bank".to_string(),
+            );
+        }
+        if let TaskSchedulerMode::ChunkShuffle = self.task_scheduler_mode {
+            check(
+                self.task_scheduler_chunk_size > 0,
+                "task_scheduler_chunk_size",
+                "should be at least 1 when task_scheduler_mode is ChunkShuffle".to_string(),
+            );
+        }
+        problems
+    }
+
+    /// validate the settings and the consistency with a matrix of `num_rows` rows
+    pub fn validate_with_rows(&self, num_rows: usize) -> Result<(), InvalidSettings> {
+        let mut problems = self
+            .validate()
+            .err()
+            .map(|e| e.problems)
+            .unwrap_or_default();
+        let total_banks = self.channels * self.chips * self.banks;
+        match &self.row_mapping {
+            RowMapping::Chunk if num_rows < total_banks => problems.push(SettingsProblem {
+                field: "row_mapping",
+                message: format!(
+                    "Chunk mapping needs at least one row per bank, the matrix has {num_rows} rows but there are {total_banks} banks"
+                ),
+            }),
+            // a missing file is already reported by `validate`
+            RowMapping::Explicit(path) if path.is_file() => {
+                if let Err(e) = read_explicit_mapping(path, num_rows, total_banks) {
+                    problems.push(SettingsProblem {
+                        field: "row_mapping",
+                        message: e.to_string(),
+                    });
+                }
+            }
+            _ => {}
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidSettings { problems })
+        }
+    }
+
+    /// validate the settings for `jobs` jobs, every job needs one buffer line and one merger of
+    /// each instance when the jobs do not share them
+    pub fn validate_with_jobs(&self, jobs: usize) -> Result<(), InvalidSettings> {
+        let mut problems = self
+            .validate()
+            .err()
+            .map(|e| e.problems)
+            .unwrap_or_default();
+        let mut check = |value: usize, field: &'static str, instance: String| {
+            if value < jobs {
+                problems.push(SettingsProblem {
+                    field,
+                    message: format!(
+                        "should be at least {jobs} for {instance} when job_sharing is Partitioned, got {value}"
+                    ),
+                });
+            }
+        };
+        if self.job_sharing.is_partitioned() && jobs > 1 {
+            check(
+                self.dimm_buffer_lines,
+                "dimm_buffer_lines",
+                "the dimm".to_string(),
+            );
+            check(
+                self.dimm_merger_count,
+                "dimm_merger_count",
+                "the dimm".to_string(),
+            );
+            for channel in 0..self.channels {
+                let channel_settings = self.for_channel(channel);
+                let instance = format!("the channel {channel}");
+                check(
+                    channel_settings.channel_buffer_lines,
+                    "channel_buffer_lines",
+                    instance.clone(),
+                );
+                check(
+                    channel_settings.channel_merger_count,
+                    "channel_merger_count",
+                    instance,
+                );
+                for chip in 0..self.chips {
+                    let chip_settings = self.for_chip((channel, chip));
+                    let instance = format!("the chip ({channel}, {chip})");
+                    check(
+                        chip_settings.chip_buffer_lines,
+                        "chip_buffer_lines",
+                        instance.clone(),
+                    );
+                    check(
+                        chip_settings.chip_merger_count,
+                        "chip_merger_count",
+                        instance,
+                    );
+                }
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidSettings { problems })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_is_valid() {
+        MemSettings::default().validate().unwrap();
+    }
+
+    #[test]
+    fn report_all_problems() {
+        let settings = MemSettings {
+            sender_store_size: 0,
+            chip_buffer_lines: 1,
+            dimm_merger_count: 0,
+            bank_merger_size: 1,
+            interleaved_chunk: 0,
+            ..Default::default()
+        };
+        let problems = settings.validate().unwrap_err().problems;
+        let fields = problems.iter().map(|p| p.field).collect_vec();
+        assert_eq!(
+            fields,
+            vec![
+                "sender_store_size",
+                "dimm_merger_count",
+                "bank_merger_size",
+                "chip_buffer_lines",
+                "interleaved_chunk"
+            ]
+        );
+    }
+
+    #[test]
+    fn reserve_less_than_lines() {
+        let settings = MemSettings {
+            chip_buffer_lines: 4,
+            chip_buffer_policy: BufferAdmission::Reserve(3),
+            channel_buffer_policy: BufferAdmission::Reserve(2),
+            dimm_buffer_policy: BufferAdmission::Reserve(0),
+            ..Default::default()
+        };
+        let problems = settings.validate().unwrap_err().problems;
+        let fields = problems.iter().map(|p| p.field).collect_vec();
+        assert_eq!(fields, vec!["dimm_buffer_policy", "channel_buffer_policy"]);
+    }
+
+    #[test]
+    fn queue_sizes_and_credits() {
+        let mut settings = MemSettings {
+            queue_sizes: QueueSizes {
+                ready: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        settings.flow_control.chip_to_bank.return_latency = -1.;
+        let problems = settings.validate().unwrap_err().problems;
+        let fields = problems.iter().map(|p| p.field).collect_vec();
+        assert_eq!(fields, vec!["queue_sizes.ready", "flow_control"]);
+
+        let effective = EffectiveSettings::load(&[
+            ConfigLayer::Set("mem_settings.flow_control.chip_to_bank.credits=2".to_string()),
+            ConfigLayer::Set("mem_settings.queue_sizes.signal=16".to_string()),
+        ])
+        .unwrap();
+        let mem_settings = &effective.settings.mem_settings;
+        assert_eq!(mem_settings.flow_control.chip_to_bank.credits, 2);
+        assert_eq!(mem_settings.flow_control.chip_to_bank.store_size(8), 2);
+        assert_eq!(mem_settings.flow_control.host_to_dimm.store_size(8), 8);
+        assert_eq!(mem_settings.queue_sizes.signal, 16);
+        assert_eq!(mem_settings.queue_sizes.ready, 128);
+    }
+
+    #[test]
+    fn faults_in_range() {
+        let mut settings = MemSettings::default();
+        settings.faults.failed_chips = vec![(0, 0), (0, 1), (1, 0), (1, 1)];
+        settings.faults.slow_banks = vec![crate::fault::SlowBank {
+            bank: 8,
+            slowdown: 2.,
+        }];
+        let problems = settings.validate().unwrap_err().problems;
+        let messages = problems.iter().map(|p| p.message.as_str()).collect_vec();
+        assert_eq!(problems.len(), 2);
+        assert!(messages[0].starts_with("the slow bank 8"));
+        assert_eq!(messages[1], "should keep at least one healthy bank");
+        settings.faults.failed_chips.pop();
+        settings.faults.slow_banks[0].bank = 7;
+        settings.validate().unwrap();
+    }
+
+    #[test]
+    fn instance_overrides() {
+        let settings: MemSettings = toml::from_str(
+            r#"
+            [[overrides]]
+            chip = 0
+            chip_merger_size = 8
+            [[overrides]]
+            channel = 1
+            bank = 1
+            bank_merger_count = 3
+            "#,
+        )
+        .unwrap();
+        settings.validate().unwrap();
+        assert_eq!(settings.for_chip((0, 0)).chip_merger_size, 8);
+        assert_eq!(settings.for_chip((1, 1)).chip_merger_size, 4);
+        // the chip fields of a channel are the ones of the chips without a selector
+        assert_eq!(settings.for_channel(0).chip_merger_size, 4);
+        assert_eq!(settings.for_bank(((1, 0), 1)).bank_merger_count, 3);
+        assert_eq!(settings.for_bank(((0, 0), 1)).bank_merger_count, 2);
+        assert_eq!(settings.for_bank(((1, 0), 1)).chip_merger_size, 8);
+
+        let settings = MemSettings {
+            overrides: vec![
+                InstanceOverride {
+                    chip: Some(2),
+                    ..Default::default()
+                },
+                InstanceOverride {
+                    bank: Some(0),
+                    chip_merger_count: Some(4),
+                    ..Default::default()
+                },
+                InstanceOverride {
+                    channel: Some(1),
+                    chip_merger_size: Some(1),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let messages = settings
+            .validate()
+            .unwrap_err()
+            .problems
+            .into_iter()
+            .map(|p| p.message)
+            .collect_vec();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].starts_with("the override 0 selects"));
+        assert_eq!(messages[1], "the override 1 sets chip fields for a bank");
+        // reported once, for the first instance that has it
+        assert_eq!(messages[2], "channel 1: should be at least 2, got 1");
+    }
+
+    #[test]
+    fn merged_values() {
+        let settings = MemSettings::default()
+            .merged(serde_json::json!({
+                "banks": 4,
+                "spill": { "region_lines": 8 },
+                "chip_merger_policy": { "Reserve": 1 },
+            }))
+            .unwrap();
+        assert_eq!(settings.banks, 4);
+        assert_eq!(
+            settings.spill,
+            Spill {
+                region_lines: 8,
+                ..Default::default()
+            }
+        );
+        assert_eq!(settings.chip_merger_policy, MergerAllocation::Reserve(1));
+        assert!(MemSettings::default()
+            .merged(serde_json::json!({ "banks": "four" }))
+            .is_err());
+        assert!(MemSettings::default()
+            .merged(serde_json::json!({ "bank": 4 }))
+            .is_err());
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let settings = MemSettings::default()
+            .merged(serde_json::json!({
+                "banks": 4,
+                "spill": { "region_lines": 8 },
+                "seed": 3,
+            }))
+            .unwrap();
+        let content = settings.to_toml().unwrap();
+        let parsed: MemSettings = toml::from_str(&content).unwrap();
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&settings).unwrap()
+        );
+    }
+
+    #[test]
+    fn partitioned_jobs() {
+        let settings = MemSettings {
+            job_sharing: JobSharing::Partitioned,
+            dimm_buffer_lines: 4,
+            dimm_merger_count: 4,
+            channel_buffer_lines: 4,
+            channel_merger_count: 4,
+            chip_buffer_lines: 4,
+            chip_merger_count: 4,
+            overrides: vec![InstanceOverride {
+                channel: Some(1),
+                chip: Some(0),
+                chip_merger_count: Some(2),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        settings.validate_with_jobs(2).unwrap();
+        let problems = settings.validate_with_jobs(3).unwrap_err().problems;
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].field, "chip_merger_count");
+        assert!(problems[0].message.contains("the chip (1, 0)"));
+        // the shared buffers and mergers need nothing more
+        MemSettings::default().validate_with_jobs(8).unwrap();
+    }
+
+    #[test]
+    fn layers_and_provenance() {
+        let effective = EffectiveSettings::load(&[
+            ConfigLayer::Preset("ddr4".to_string()),
+            ConfigLayer::File("configs/store_sizes/32.toml".into()),
+            ConfigLayer::Set("banks=4".to_string()),
+            ConfigLayer::Set("mem_settings.row_mapping=Chunk".to_string()),
+        ])
+        .unwrap();
+        let mem_settings = &effective.settings.mem_settings;
+        assert_eq!(mem_settings.banks, 4);
+        assert_eq!(mem_settings.chips, 8);
+        assert_eq!(mem_settings.sender_store_size, 32);
+        assert!(mem_settings.row_mapping.is_chunk());
+        assert_eq!(effective.provenance["mem_settings.chips"], "preset:ddr4");
+        assert_eq!(
+            effective.provenance["mem_settings.sender_store_size"],
+            "configs/store_sizes/32.toml"
+        );
+        assert_eq!(effective.provenance["mem_settings.banks"], "--set banks=4");
+        // the unknown key store_size in ddr4 is dropped
+        assert!(!effective.provenance.contains_key("mem_settings.store_size"));
+        // the missing fields are default
+        assert!(!effective.provenance.contains_key("mtx_files"));
+    }
+
+    #[test]
+    fn set_top_level_jobs() {
+        let effective = EffectiveSettings::load(&[ConfigLayer::Set(
+            r#"jobs=[{ mtx_file = "mtx/test.mtx", arrival = 10.0 }]"#.to_string(),
+        )])
+        .unwrap();
+        assert_eq!(effective.settings.jobs.len(), 1);
+        assert_eq!(effective.settings.jobs[0].arrival, 10.);
+    }
+
+    #[test]
+    fn set_top_level_stream() {
+        let effective = EffectiveSettings::load(&[ConfigLayer::Set(
+            r#"stream={ a_files = ["mtx/test.mtx"], batch_rows = 2 }"#.to_string(),
+        )])
+        .unwrap();
+        let stream = effective.settings.stream.unwrap();
+        assert_eq!(stream.batch_rows, 2);
+        assert_eq!(stream.a_files.len(), 1);
+    }
+
+    #[test]
+    fn dump_can_be_loaded() {
+        let effective = EffectiveSettings::load(&[
+            ConfigLayer::Preset("ddr4".to_string()),
+            ConfigLayer::Preset("large".to_string()),
+        ])
+        .unwrap();
+        let dumped = effective.to_toml().unwrap();
+        let path = std::env::temp_dir().join("spmm_pim_dump_can_be_loaded.toml");
+        std::fs::write(&path, &dumped).unwrap();
+        let reloaded = EffectiveSettings::load(&[ConfigLayer::File(path)]).unwrap();
+        assert_eq!(
+            reloaded.to_toml().unwrap().lines().count(),
+            dumped.lines().count()
+        );
+        assert_eq!(
+            toml::Value::try_from(&reloaded.settings).unwrap(),
+            toml::Value::try_from(&effective.settings).unwrap()
+        );
+    }
+
+    #[test]
+    fn chunk_mapping_rows() {
+        let settings = MemSettings {
+            row_mapping: RowMapping::Chunk,
+            ..Default::default()
+        };
+        settings.validate_with_rows(8).unwrap();
+        let problems = settings.validate_with_rows(7).unwrap_err().problems;
+        assert_eq!(problems[0].field, "row_mapping");
+    }
+
+    #[test]
+    fn row_mapping_with_value() {
+        let effective = EffectiveSettings::load(&[ConfigLayer::Set(
+            r#"row_mapping={ Explicit = "mapping.txt" }"#.to_string(),
+        )])
+        .unwrap();
+        let row_mapping = &effective.settings.mem_settings.row_mapping;
+        assert_eq!(row_mapping, &RowMapping::Explicit("mapping.txt".into()));
+        let problems = effective
+            .settings
+            .mem_settings
+            .validate()
+            .unwrap_err()
+            .problems;
+        assert_eq!(problems[0].field, "row_mapping");
+
+        let effective =
+            EffectiveSettings::load(&[ConfigLayer::Set("row_mapping=Xor".to_string())]).unwrap();
+        assert!(effective.settings.mem_settings.row_mapping.is_xor());
+        let settings = MemSettings {
+            banks: 3,
+            ..effective.settings.mem_settings
+        };
+        assert_eq!(
+            settings.validate().unwrap_err().problems[0].field,
+            "row_mapping"
+        );
+    }
+
+    #[test]
+    fn explicit_mapping_rows() {
+        let path = std::env::temp_dir().join(format!(
+            "spmm_pim_explicit_mapping_rows_{}.txt",
+            std::process::id()
+        ));
+        // 8 banks by default
+        std::fs::write(&path, "0\n7 # the last bank\n3\n").unwrap();
+        let settings = MemSettings {
+            row_mapping: RowMapping::Explicit(path.clone()),
+            ..Default::default()
+        };
+        settings.validate_with_rows(3).unwrap();
+        assert_eq!(
+            settings.validate_with_rows(4).unwrap_err().problems[0].field,
+            "row_mapping"
+        );
+        std::fs::write(&path, "0\n8\n3\n").unwrap();
+        assert!(settings.validate_with_rows(3).is_err());
+    }
+}
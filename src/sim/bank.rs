@@ -3,10 +3,13 @@ use std::{
     collections::{BTreeMap, VecDeque},
 };
 const BANK_ROW_SIZE: usize = 2048;
+/// the cycles to read one row of the bank array
+pub const ROW_CYCLES: f64 = 16.;
 use qsim::ResourceId;
 use tracing::debug;
 
 use super::{
+    bank_cache::{BankCache, CachePolicy},
     component::Component,
     queue_tracker::QueueTrackerId,
     sim_time::{EndTimeId, NamedTimeId},
@@ -15,11 +18,23 @@ use super::{
 };
 use crate::{
     pim::merge_rows_into_one,
-    sim::types::{BankTaskEnum, PushBankTaskType, PushPartialSumType, StateWithSharedStatus},
+    sim::types::{
+        BankTaskEnum, PushBankTaskType, PushPartialSumType, SpillTaskType, StateWithSharedStatus,
+    },
 };
 use genawaiter::rc::{Co, Gen};
 //849191287
 
+/// the array rows read to access the bytes `start..end`, one more row is opened if `current_row` is not the first one
+pub fn array_rows(start: usize, end: usize, current_row: usize) -> usize {
+    let (first, last) = (start / BANK_ROW_SIZE, end / BANK_ROW_SIZE);
+    if first == current_row {
+        last - first
+    } else {
+        last - first + 1
+    }
+}
+
 /// merger status
 /// - total_merger: number of total merger workers
 /// - current_working_merger: target id to merger id
@@ -104,6 +119,8 @@ pub struct BankPe {
 
     pub named_idle_time_id: NamedTimeId,
     pub end_time_id: EndTimeId,
+    /// the factor of the merge cycles, more than 1 for a slow bank
+    pub slowdown: f64,
 }
 
 impl BankPe {
@@ -128,8 +145,15 @@ impl BankPe {
             task_sender_input_id,
             named_idle_time_id,
             end_time_id,
+            slowdown: 1.,
         }
     }
+
+    /// multiply the merge cycles, for a slow bank
+    pub fn with_slowdown(mut self, slowdown: f64) -> Self {
+        self.slowdown = slowdown;
+        self
+    }
 }
 
 impl Component for BankPe {
@@ -176,15 +200,18 @@ impl Component for BankPe {
                         current_task_id = task_id;
                         current_task_target_row = to;
                     }
+                    BankTaskEnum::Spill(_) => {
+                        unreachable!("the spill tasks are served by BankTaskReorder")
+                    }
                     BankTaskEnum::EndThisTask => {
                         // end this task
                         // compute the task
                         if !tasks.is_empty() {
                             // process last tasks
                             let (add_cycle, merge_cycle, data) =
-                                merge_rows_into_one(tasks.clone(), self.merger_size);
+                                merge_rows_into_one(std::mem::take(&mut tasks), self.merger_size);
                             // todo: refine the add cycle according to the adder size
-                            let wait_time = cmp::max(add_cycle, merge_cycle) as f64;
+                            let wait_time = cmp::max(add_cycle, merge_cycle) as f64 * self.slowdown;
                             shared_status.shared_sim_time.add_bank_merge(wait_time);
                             shared_status.shared_named_time.add_idle_time(
                                 &self.named_idle_time_id,
@@ -257,6 +284,10 @@ pub struct BankTaskReorder {
     pub comp_id: NamedTimeId,
     pub end_time_id: EndTimeId,
     pub queue_tracker_id_recv: QueueTrackerId,
+    /// the cache of B rows in front of the array, None if disabled
+    pub cache: Option<BankCache>,
+    /// the factor of the array read cycles, more than 1 for a slow bank
+    pub slowdown: f64,
 }
 
 // TODO
@@ -269,6 +300,8 @@ impl Component for BankTaskReorder {
             let mut current_target_pe = 0;
             let mut current_row = 0;
             let mut current_time = 0.;
+            let mut cache = self.cache;
+            let row_cycles = ROW_CYCLES * self.slowdown;
             loop {
                 // first get the context
                 let context: SpmmContex = co
@@ -293,11 +326,27 @@ impl Component for BankTaskReorder {
                     "get_task_from_chip",
                     gap,
                 );
-                shared_status.queue_tracker.deq(&self.queue_tracker_id_recv);
+                let (_resouce_id, task) = status.into_push_bank_task().unwrap();
+                // the spill tasks come from a spill port, not through the tracked link of the chip
+                let from_chip = !matches!(task, BankTaskEnum::Spill(_));
+                if from_chip {
+                    shared_status.queue_tracker.deq(&self.queue_tracker_id_recv);
+                }
+                if let Some(credits) = shared_status
+                    .shared_credits
+                    .get(&self.queue_tracker_id_recv)
+                    .filter(|_| from_chip)
+                {
+                    // the task left the link, give the credit back to the chip
+                    co.yield_(
+                        original_status
+                            .clone_with_state(SpmmStatusEnum::PushCredit(credits.credit_return)),
+                    )
+                    .await;
+                }
                 shared_status
                     .shared_end_time
                     .set_end_time(self.end_time_id, time);
-                let (_resouce_id, task) = status.into_push_bank_task().unwrap();
 
                 match task {
                     BankTaskEnum::PushBankTask(PushBankTaskType {
@@ -317,13 +366,31 @@ impl Component for BankTaskReorder {
                         let inner_row_id_end = row_end / BANK_ROW_SIZE;
                         // read the bank
                         let mut total_waiting = 0.;
-                        if inner_row_id_start == current_row {
+                        let cache_hit = match &mut cache {
+                            Some(cache) => {
+                                let hit = cache.access(from, row_end - row_start);
+                                if hit {
+                                    // the same rows as a miss would read, the row buffer is not changed
+                                    let rows = array_rows(row_start, row_end, current_row);
+                                    shared_status
+                                        .shared_bank_cache
+                                        .add_hit(self.self_id, rows as f64 * row_cycles);
+                                } else {
+                                    shared_status.shared_bank_cache.add_miss(self.self_id);
+                                }
+                                hit
+                            }
+                            None => false,
+                        };
+                        if cache_hit {
+                            // the row is read from the cache, the array is not accessed
+                        } else if inner_row_id_start == current_row {
                             for _i in inner_row_id_start..inner_row_id_end {
-                                // TODO , read the setting
-                                total_waiting += 16.;
+                                total_waiting += row_cycles;
                                 let context = co
                                     .yield_(
-                                        original_status.clone_with_state(SpmmStatusEnum::Wait(16.)),
+                                        original_status
+                                            .clone_with_state(SpmmStatusEnum::Wait(row_cycles)),
                                     )
                                     .await;
                                 let (_time, _status) = context.into_inner();
@@ -334,10 +401,11 @@ impl Component for BankTaskReorder {
                             }
                         } else {
                             for _i in inner_row_id_start..=inner_row_id_end {
-                                total_waiting += 16.;
+                                total_waiting += row_cycles;
                                 let context = co
                                     .yield_(
-                                        original_status.clone_with_state(SpmmStatusEnum::Wait(16.)),
+                                        original_status
+                                            .clone_with_state(SpmmStatusEnum::Wait(row_cycles)),
                                     )
                                     .await;
                                 let (_time, _status) = context.into_inner();
@@ -354,7 +422,9 @@ impl Component for BankTaskReorder {
                             "read_row_buffer",
                             total_waiting,
                         );
-                        current_row = inner_row_id_end;
+                        if !cache_hit {
+                            current_row = inner_row_id_end;
+                        }
 
                         let context = co
                             .yield_(
@@ -384,6 +454,40 @@ impl Component for BankTaskReorder {
                             .shared_end_time
                             .set_end_time(self.end_time_id, current_time);
                     }
+                    BankTaskEnum::Spill(SpillTaskType { bytes, done }) => {
+                        // the spill region is never in the row buffer, its rows replace the open row of B
+                        let wait_time = array_rows(0, bytes, usize::MAX) as f64 * row_cycles;
+                        let context = co
+                            .yield_(
+                                original_status.clone_with_state(SpmmStatusEnum::Wait(wait_time)),
+                            )
+                            .await;
+                        let (_time, _status) = context.into_inner();
+                        current_time = _time;
+                        current_row = usize::MAX;
+                        shared_status.shared_named_time.add_idle_time(
+                            &self.comp_id,
+                            "spill_access",
+                            wait_time,
+                        );
+                        let context = co
+                            .yield_(
+                                original_status
+                                    .clone_with_state(SpmmStatusEnum::PushSpillDone(done)),
+                            )
+                            .await;
+                        let (_time, _status) = context.into_inner();
+                        let gap = _time - current_time;
+                        current_time = _time;
+                        shared_status.shared_named_time.add_idle_time(
+                            &self.comp_id,
+                            "push_spill_done",
+                            gap,
+                        );
+                        shared_status
+                            .shared_end_time
+                            .set_end_time(self.end_time_id, current_time);
+                    }
                     BankTaskEnum::EndThisTask => {
                         // end this task
                         // push this to current_taget_pe and switch to the next
@@ -438,8 +542,22 @@ impl BankTaskReorder {
             comp_id,
             end_time_id,
             queue_tracker_id_recv,
+            cache: None,
+            slowdown: 1.,
         }
     }
+
+    /// keep the recently read rows of B in a cache of `capacity` bytes
+    pub fn with_cache(mut self, capacity: usize, policy: CachePolicy) -> Self {
+        self.cache = Some(BankCache::new(capacity, policy));
+        self
+    }
+
+    /// multiply the array read cycles, for a slow bank
+    pub fn with_slowdown(mut self, slowdown: f64) -> Self {
+        self.slowdown = slowdown;
+        self
+    }
 }
 #[cfg(test)]
 mod test {
@@ -451,7 +569,7 @@ mod test {
     use crate::{
         csv_nodata::CsVecNodata,
         init_logger,
-        settings::RealRowMapping,
+        pim::ResolvedRowMapping,
         sim::{
             final_receiver::FinalReceiver,
             sim_time::{SharedEndTime, SharedNamedTime},
@@ -506,7 +624,7 @@ mod test {
             1,
             1,
             1,
-            RealRowMapping::Chunk,
+            ResolvedRowMapping::Chunk,
             queue_id_send,
             DefaultTaskScheduler::new(all_send_task),
         );
@@ -0,0 +1,179 @@
+//! a small SRAM in each bank that keeps the recently read rows of B, a hit does not read the bank array
+
+use std::{cell::RefCell, collections::BTreeMap};
+
+use enum_as_inner::EnumAsInner;
+use serde::{Deserialize, Serialize};
+
+use super::id_translation::BankID;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, EnumAsInner)]
+pub enum CachePolicy {
+    /// evict the least recently used row
+    #[default]
+    Lru,
+    /// evict the least frequently used row, the least recently used one of them
+    Lfu,
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    bytes: usize,
+    last_use: u64,
+    uses: u64,
+}
+
+/// the rows of B cached by one bank, the rows larger than the cache are never cached
+#[derive(Debug, Clone)]
+pub struct BankCache {
+    capacity: usize,
+    used: usize,
+    policy: CachePolicy,
+    /// row of B to its entry
+    entries: BTreeMap<usize, CacheEntry>,
+    clock: u64,
+}
+
+impl BankCache {
+    /// `capacity` is in bytes
+    pub fn new(capacity: usize, policy: CachePolicy) -> Self {
+        Self {
+            capacity,
+            used: 0,
+            policy,
+            entries: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// read `row` of `bytes`, return true if it's a hit, a missed row is put into the cache
+    pub fn access(&mut self, row: usize, bytes: usize) -> bool {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(&row) {
+            entry.last_use = self.clock;
+            entry.uses += 1;
+            return true;
+        }
+        if bytes > self.capacity {
+            return false;
+        }
+        while self.used + bytes > self.capacity {
+            let victim = match self.policy {
+                CachePolicy::Lru => self.entries.iter().min_by_key(|(_, e)| e.last_use),
+                CachePolicy::Lfu => self
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, e)| (e.uses, e.last_use)),
+            }
+            .map(|(&row, _)| row)
+            .unwrap();
+            self.used -= self.entries.remove(&victim).unwrap().bytes;
+        }
+        self.used += bytes;
+        self.entries.insert(
+            row,
+            CacheEntry {
+                bytes,
+                last_use: self.clock,
+                uses: 1,
+            },
+        );
+        false
+    }
+}
+
+/// the hits of one bank
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct BankCacheCounter {
+    pub hits: usize,
+    pub misses: usize,
+    /// the cycles of the array reads avoided by the hits
+    pub saved_read_time: f64,
+}
+
+/// the hits of all bank caches, written by `BankTaskReorder`
+#[derive(Debug, Default)]
+pub struct SharedBankCacheStats {
+    inner: RefCell<BTreeMap<BankID, BankCacheCounter>>,
+}
+
+impl SharedBankCacheStats {
+    pub fn add_hit(&self, bank_id: BankID, saved_read_time: f64) {
+        let mut inner = self.inner.borrow_mut();
+        let counter = inner.entry(bank_id).or_default();
+        counter.hits += 1;
+        counter.saved_read_time += saved_read_time;
+    }
+
+    pub fn add_miss(&self, bank_id: BankID) {
+        self.inner.borrow_mut().entry(bank_id).or_default().misses += 1;
+    }
+
+    pub fn get_stats(&self) -> BankCacheStats {
+        let inner = self.inner.borrow();
+        let hits = inner.values().map(|c| c.hits).sum::<usize>();
+        let misses = inner.values().map(|c| c.misses).sum::<usize>();
+        BankCacheStats {
+            hits,
+            misses,
+            hit_rate: if hits + misses == 0 {
+                0.
+            } else {
+                hits as f64 / (hits + misses) as f64
+            },
+            saved_read_time: inner.values().map(|c| c.saved_read_time).sum(),
+            banks: inner
+                .iter()
+                .map(|(bank_id, counter)| (format!("{bank_id:?}"), counter.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// the hit rate of the bank caches, all zero when the cache is disabled
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BankCacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub hit_rate: f64,
+    pub saved_read_time: f64,
+    pub banks: BTreeMap<String, BankCacheCounter>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lru_and_lfu() {
+        let mut lru = BankCache::new(300, CachePolicy::Lru);
+        let mut lfu = BankCache::new(300, CachePolicy::Lfu);
+        // the row 0 is used twice, then the row 2 evicts two rows
+        for cache in [&mut lru, &mut lfu] {
+            assert!(!cache.access(0, 100));
+            assert!(!cache.access(1, 100));
+            assert!(cache.access(0, 100));
+            assert!(!cache.access(3, 100));
+            assert!(!cache.access(2, 200));
+        }
+        // lru evicts 1 and 0, lfu evicts 1 and 3
+        assert!(!lru.access(0, 100));
+        assert!(lfu.access(0, 100));
+        // too large to be cached
+        assert!(!lru.access(4, 400));
+        assert!(!lru.access(4, 400));
+    }
+
+    #[test]
+    fn stats() {
+        let stats = SharedBankCacheStats::default();
+        stats.add_miss(((0, 0), 0));
+        stats.add_hit(((0, 0), 0), 16.);
+        stats.add_hit(((0, 0), 1), 32.);
+        stats.add_miss(((0, 0), 1));
+        let stats = stats.get_stats();
+        assert_eq!(stats.hit_rate, 0.5);
+        assert_eq!(stats.saved_read_time, 48.);
+        assert_eq!(stats.banks["((0, 0), 1)"].saved_read_time, 32.);
+    }
+}
@@ -4,16 +4,141 @@ use std::{
     cell::RefCell,
     collections::{BTreeMap, BTreeSet, VecDeque},
     fmt::Debug,
+    rc::Rc,
 };
 
+use enum_as_inner::EnumAsInner;
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use tracing::debug;
 
-/// the buffer status help to decide whether to receive a new line,
-/// # Policy:
+use super::job::JobTable;
+
+/// decide whether a buffer takes a new line, a line that is already in the buffer is always received
+pub trait BufferAdmissionPolicy: Debug {
+    /// `new_task` is waiting and not in the buffer
+    fn can_admit(&self, buffer: &BufferStatus, new_task: usize) -> bool;
+}
+
+/// the default policy:
 /// - if there are more than 2 slots, then receive a new line
 /// - if there are only one slot, if the incoming is a latest line, then receive it!
 /// - else, drop it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LastSlotForOldest;
+
+impl BufferAdmissionPolicy for LastSlotForOldest {
+    fn can_admit(&self, buffer: &BufferStatus, new_task: usize) -> bool {
+        match buffer.free_slots() {
+            0 => false,
+            1 => {
+                let oldest = buffer.waiting_sequence.front().unwrap();
+                // if the first line is already in the buffer, the next one can be received
+                oldest == &new_task || buffer.occupied_task_ids.contains(oldest)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// the lines get their slots in the order of the tasks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StrictInOrder;
+
+impl BufferAdmissionPolicy for StrictInOrder {
+    fn can_admit(&self, buffer: &BufferStatus, new_task: usize) -> bool {
+        buffer.free_slots() > 0 && buffer.waiting_without_slot().next() == Some(new_task)
+    }
+}
+
+/// the last `k` slots are kept for the `k` oldest tasks that do not have a slot
+#[derive(Debug, Clone, Copy)]
+pub struct ReserveSlots(pub usize);
+
+impl BufferAdmissionPolicy for ReserveSlots {
+    fn can_admit(&self, buffer: &BufferStatus, new_task: usize) -> bool {
+        let free = buffer.free_slots();
+        free > self.0
+            || buffer
+                .waiting_without_slot()
+                .take(free)
+                .any(|task| task == new_task)
+    }
+}
+
+/// the last slot goes to the task with the fewest sub ids left, the older one first
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FewestRemaining;
+
+impl BufferAdmissionPolicy for FewestRemaining {
+    fn can_admit(&self, buffer: &BufferStatus, new_task: usize) -> bool {
+        match buffer.free_slots() {
+            0 => false,
+            1 => {
+                buffer
+                    .waiting_without_slot()
+                    .min_by_key(|&task| buffer.remaining_sub_ids(task))
+                    == Some(new_task)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// the lines are split between the jobs, each part keeps its last line for the oldest task of its job.
+/// it replaces the policy of the level when the jobs do not share the buffers
+#[derive(Debug)]
+pub struct PartitionByJob {
+    pub jobs: Rc<JobTable>,
+}
+
+impl BufferAdmissionPolicy for PartitionByJob {
+    fn can_admit(&self, buffer: &BufferStatus, new_task: usize) -> bool {
+        let job = self.jobs.job_of(new_task);
+        let of_job = |task: &usize| self.jobs.job_of(*task) == job;
+        let lines = self.jobs.partition(job, buffer.total_tasks).len();
+        let occupied = buffer
+            .occupied_task_ids
+            .iter()
+            .filter(|t| of_job(t))
+            .count();
+        match lines.saturating_sub(occupied) {
+            0 => false,
+            1 => {
+                let oldest = buffer.waiting_sequence.iter().find(|t| of_job(t)).unwrap();
+                oldest == &new_task || buffer.occupied_task_ids.contains(oldest)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// the admission policy of the buffers of one level, in toml: `chip_buffer_policy = { Reserve = 2 }`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, EnumAsInner)]
+pub enum BufferAdmission {
+    /// see `LastSlotForOldest`
+    #[default]
+    LastSlotForOldest,
+    /// see `StrictInOrder`
+    InOrder,
+    /// see `ReserveSlots`
+    Reserve(usize),
+    /// see `FewestRemaining`
+    Priority,
+}
+
+impl BufferAdmission {
+    pub fn build(&self) -> Box<dyn BufferAdmissionPolicy> {
+        match *self {
+            BufferAdmission::LastSlotForOldest => Box::new(LastSlotForOldest),
+            BufferAdmission::InOrder => Box::new(StrictInOrder),
+            BufferAdmission::Reserve(k) => Box::new(ReserveSlots(k)),
+            BufferAdmission::Priority => Box::new(FewestRemaining),
+        }
+    }
+}
+
+/// the buffer status help to decide whether to receive a new line, see `BufferAdmissionPolicy`
 #[derive(Debug)]
 pub struct BufferStatus {
     /// self id
@@ -29,6 +154,7 @@ pub struct BufferStatus {
     waiting_sequence: VecDeque<usize>,
     /// for each waiting task_id, the lower id it waits.
     waiting_sub_ids: BTreeMap<usize, BTreeSet<usize>>,
+    policy: Box<dyn BufferAdmissionPolicy>,
 }
 
 impl BufferStatus {
@@ -44,8 +170,32 @@ impl BufferStatus {
             occupied_task_ids: BTreeSet::new(),
             waiting_sequence: VecDeque::new(),
             waiting_sub_ids: Default::default(),
+            policy: Box::new(LastSlotForOldest),
         }
     }
+
+    pub fn with_policy(mut self, policy: Box<dyn BufferAdmissionPolicy>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn free_slots(&self) -> usize {
+        self.total_tasks - self.occupied_task_ids.len()
+    }
+
+    /// the waiting tasks that are not in the buffer, the oldest first
+    pub fn waiting_without_slot(&self) -> impl Iterator<Item = usize> + '_ {
+        self.waiting_sequence
+            .iter()
+            .cloned()
+            .filter(|task| !self.occupied_task_ids.contains(task))
+    }
+
+    /// the number of lower pes that have not sent their line of `task_id`
+    pub fn remaining_sub_ids(&self, task_id: usize) -> usize {
+        self.waiting_sub_ids.get(&task_id).map_or(0, BTreeSet::len)
+    }
+
     /// add a new target row that will be received later. this should be called by task sender, the row id shoudl be sorted!
     pub fn add_waiting(&mut self, task_id: usize, sub_id: usize) {
         if !self.waiting_sub_ids.contains_key(&task_id) {
@@ -64,31 +214,11 @@ impl BufferStatus {
     }
     /// test if the buffer will be availiable to receive a new line
     pub fn can_receive(&self, new_task: usize) -> bool {
-        let remaining = self.total_tasks - self.occupied_task_ids.len();
         if self.occupied_task_ids.contains(&new_task) {
             return true;
         }
         // do not contains this one
-        match remaining {
-            0 => false,
-            1 => {
-                if self.waiting_sequence.front().unwrap() == &new_task {
-                    true
-                } else {
-                    if self
-                        .occupied_task_ids
-                        .contains(self.waiting_sequence.front().unwrap())
-                    {
-                        // the first line is already in the buffer, so the next we can receive!
-                        true
-                    } else {
-                        // the last line should be reserved for the latest one!
-                        false
-                    }
-                }
-            }
-            _ => true,
-        }
+        self.policy.can_admit(self, new_task)
     }
 
     /// - receive a new line, if this is the last task, the record will be removed
@@ -174,9 +304,17 @@ pub struct BufferStatusId {
 impl SharedBufferStatus {
     #[must_use]
     pub fn add_component(&self, buffer_rows: usize) -> BufferStatusId {
+        self.add_component_with_policy(buffer_rows, Box::new(LastSlotForOldest))
+    }
+    #[must_use]
+    pub fn add_component_with_policy(
+        &self,
+        buffer_rows: usize,
+        policy: Box<dyn BufferAdmissionPolicy>,
+    ) -> BufferStatusId {
         let mut inner = self.inner.borrow_mut();
         let id = inner.len();
-        inner.push(BufferStatus::new(buffer_rows, id));
+        inner.push(BufferStatus::new(buffer_rows, id).with_policy(policy));
         BufferStatusId { id }
     }
     /// # Safety:
@@ -226,11 +364,24 @@ impl SharedBufferStatus {
         let inner = self.inner.borrow();
         format!("{:?}", inner.get(comp_id.id).unwrap())
     }
+
+    /// all buffers that still have unfinished tasks, (buffer id, waiting_sequence)
+    pub fn pending_tasks(&self) -> Vec<(usize, Vec<usize>)> {
+        self.inner
+            .borrow()
+            .iter()
+            .filter(|buffer| !buffer.waiting_sequence.is_empty())
+            .map(|buffer| (buffer.id, buffer.waiting_sequence.iter().cloned().collect()))
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::SharedBufferStatus;
+    use std::rc::Rc;
+
+    use super::{BufferAdmission, PartitionByJob, SharedBufferStatus};
+    use crate::sim::job::JobTable;
 
     #[test]
     fn main_test() {
@@ -302,4 +453,100 @@ mod test {
         shared_buffer_status.remove(&id, 1);
         println!("after remove 1,2,3,4\n {:?}", shared_buffer_status);
     }
+
+    #[test]
+    fn in_order_test() {
+        let shared_buffer_status = SharedBufferStatus::default();
+        let id =
+            shared_buffer_status.add_component_with_policy(4, BufferAdmission::InOrder.build());
+        shared_buffer_status.add_waiting(&id, 0, 0);
+        shared_buffer_status.add_waiting(&id, 1, 0);
+        shared_buffer_status.add_waiting(&id, 2, 0);
+
+        // all lines are free, but 0 comes first
+        assert!(!shared_buffer_status.can_receive(&id, 1));
+        assert!(shared_buffer_status.can_receive(&id, 0));
+        let _finished = shared_buffer_status.receive(&id, 0, 0);
+        assert!(shared_buffer_status.can_receive(&id, 1));
+        assert!(!shared_buffer_status.can_receive(&id, 2));
+    }
+
+    #[test]
+    fn reserve_test() {
+        let shared_buffer_status = SharedBufferStatus::default();
+        let id =
+            shared_buffer_status.add_component_with_policy(4, BufferAdmission::Reserve(2).build());
+        shared_buffer_status.add_waiting(&id, 0, 0);
+        shared_buffer_status.add_waiting(&id, 0, 1);
+        for task in 1..6 {
+            shared_buffer_status.add_waiting(&id, task, 2);
+        }
+
+        // 2 lines are free besides the reserved ones
+        assert!(shared_buffer_status.can_receive(&id, 3));
+        let _finished = shared_buffer_status.receive(&id, 3, 2);
+        let _finished = shared_buffer_status.receive(&id, 4, 2);
+
+        // the last 2 lines are kept for 0 and 1
+        assert!(!shared_buffer_status.can_receive(&id, 5));
+        assert!(shared_buffer_status.can_receive(&id, 1));
+        let _finished = shared_buffer_status.receive(&id, 1, 2);
+        assert!(!shared_buffer_status.can_receive(&id, 2));
+        assert!(shared_buffer_status.can_receive(&id, 0));
+        let _finished = shared_buffer_status.receive(&id, 0, 0);
+
+        // the buffer is full, only the lines of 0 are received
+        assert!(!shared_buffer_status.can_receive(&id, 2));
+        assert!(shared_buffer_status.can_receive(&id, 0));
+        shared_buffer_status.remove(&id, 3);
+        assert!(shared_buffer_status.can_receive(&id, 2));
+    }
+
+    #[test]
+    fn priority_test() {
+        let shared_buffer_status = SharedBufferStatus::default();
+        let default_id = shared_buffer_status.add_component(2);
+        let priority_id =
+            shared_buffer_status.add_component_with_policy(2, BufferAdmission::Priority.build());
+        for id in [default_id, priority_id] {
+            for sub_id in 0..3 {
+                shared_buffer_status.add_waiting(&id, 0, sub_id);
+            }
+            shared_buffer_status.add_waiting(&id, 1, 0);
+            shared_buffer_status.add_waiting(&id, 1, 1);
+            shared_buffer_status.add_waiting(&id, 2, 0);
+            let _finished = shared_buffer_status.receive(&id, 1, 0);
+        }
+
+        // one line left, 0 waits 3 lines and 2 waits only one
+        assert!(shared_buffer_status.can_receive(&default_id, 0));
+        assert!(!shared_buffer_status.can_receive(&default_id, 2));
+        assert!(!shared_buffer_status.can_receive(&priority_id, 0));
+        assert!(shared_buffer_status.can_receive(&priority_id, 2));
+        let is_finished = shared_buffer_status.receive(&priority_id, 2, 0);
+        assert!(is_finished);
+        shared_buffer_status.remove(&priority_id, 2);
+        assert!(shared_buffer_status.can_receive(&priority_id, 0));
+    }
+
+    #[test]
+    fn partition_test() {
+        let jobs = Rc::new(JobTable::default());
+        jobs.set_jobs(2);
+        // the tasks 0, 2 and 4 are of the job 0, 1 and 3 of the job 1
+        for job in [0, 1, 0, 1, 0] {
+            jobs.next_task(job);
+        }
+        let shared_buffer_status = SharedBufferStatus::default();
+        let id =
+            shared_buffer_status.add_component_with_policy(4, Box::new(PartitionByJob { jobs }));
+        for task in 0..5 {
+            shared_buffer_status.add_waiting(&id, task, 0);
+        }
+        let _finished = shared_buffer_status.receive(&id, 2, 0);
+        // the last line of the job 0 is kept for 0, the lines of the job 1 are free
+        assert!(!shared_buffer_status.can_receive(&id, 4));
+        assert!(shared_buffer_status.can_receive(&id, 3));
+        assert!(shared_buffer_status.can_receive(&id, 0));
+    }
 }
@@ -1,11 +1,16 @@
 #![allow(dead_code)]
 
-use std::fmt::Debug;
+use std::{cell::RefCell, fmt::Debug, pin::Pin, rc::Rc};
 
-use qsim::{resources::Store, Simulation};
+use genawaiter::{Coroutine, GeneratorState};
+use qsim::{resources::Store, Effect, ResourceId, SimState, Simulation};
 use tracing::info;
 
-use super::{component::Component, SpmmStatus};
+use super::{
+    component::Component,
+    types::{SpmmContex, SpmmGenerator},
+    SpmmStatus,
+};
 
 #[derive(Default)]
 pub struct QueueIdCollector {
@@ -18,9 +23,52 @@ impl QueueIdCollector {
         id
     }
 }
+
+/// the last thing a process yielded, used to find out who is stuck when the simulation ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    /// the process is not waiting on any resource
+    Running,
+    /// waiting to pop from the resource
+    Pop(ResourceId),
+    /// waiting to push into the resource
+    Push(ResourceId),
+    /// the generator returned
+    Finished,
+}
+
+/// wrap the generator of a component, record the effect of every yield into the shared states.
+struct TrackedProcess {
+    id: usize,
+    inner: Box<SpmmGenerator>,
+    states: Rc<RefCell<Vec<ProcessState>>>,
+}
+
+impl Coroutine for TrackedProcess {
+    type Yield = SpmmStatus;
+    type Resume = SpmmContex;
+    type Return = ();
+
+    fn resume_with(mut self: Pin<&mut Self>, arg: SpmmContex) -> GeneratorState<SpmmStatus, ()> {
+        let this = &mut *self;
+        let result = Pin::new(&mut *this.inner).resume_with(arg);
+        let state = match &result {
+            GeneratorState::Yielded(status) => match status.get_effect() {
+                Effect::Pop(rid) => ProcessState::Pop(rid),
+                Effect::Push(rid) => ProcessState::Push(rid),
+                Effect::TimeOut(_) => ProcessState::Running,
+            },
+            GeneratorState::Complete(_) => ProcessState::Finished,
+        };
+        this.states.borrow_mut()[this.id] = state;
+        result
+    }
+}
+
 pub struct ProcessInfoCollector {
     should_collect: bool,
     all_process_infos: Vec<String>,
+    process_states: Rc<RefCell<Vec<ProcessState>>>,
 }
 
 impl ProcessInfoCollector {
@@ -28,6 +76,7 @@ impl ProcessInfoCollector {
         ProcessInfoCollector {
             should_collect,
             all_process_infos: Vec::new(),
+            process_states: Default::default(),
         }
     }
     pub fn create_process_and_schedule<T>(
@@ -39,7 +88,15 @@ impl ProcessInfoCollector {
         T: Debug + Component + 'static,
     {
         self.all_process_infos.push(format!("{:?}", process));
-        let id = sim.create_process(process.run(status.clone()));
+        let mut states = self.process_states.borrow_mut();
+        let tracked = TrackedProcess {
+            id: states.len(),
+            inner: process.run(status.clone()),
+            states: self.process_states.clone(),
+        };
+        states.push(ProcessState::Running);
+        drop(states);
+        let id = sim.create_process(Box::new(tracked));
         sim.schedule_event(
             0.0,
             id,
@@ -52,4 +109,15 @@ impl ProcessInfoCollector {
             info!("{}", process_info);
         }
     }
+
+    /// all processes that are still waiting on a resource, (process info, state)
+    /// - call it after the simulation is finished, a process that waits to push can never make progress.
+    pub fn blocked_processes(&self) -> Vec<(String, ProcessState)> {
+        self.all_process_infos
+            .iter()
+            .zip(self.process_states.borrow().iter())
+            .filter(|(_, state)| matches!(state, ProcessState::Pop(_) | ProcessState::Push(_)))
+            .map(|(info, state)| (info.clone(), *state))
+            .collect()
+    }
 }
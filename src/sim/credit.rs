@@ -0,0 +1,117 @@
+//! credit based flow control of the task links, the sender takes a credit before each task and the receiver returns it after the pop
+
+use std::{cell::RefCell, collections::BTreeMap};
+
+use genawaiter::rc::{Co, Gen};
+use qsim::ResourceId;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    component::Component,
+    queue_tracker::QueueTrackerId,
+    types::{SpmmContex, SpmmGenerator},
+    SpmmStatus, SpmmStatusEnum,
+};
+
+/// the credits of a task link, in toml: `chip_to_bank = { credits = 4, return_latency = 2.0 }`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(default)]
+pub struct CreditLink {
+    /// the tasks the sender can send before a credit comes back, 0 disables the credits
+    pub credits: usize,
+    /// the cycles from the pop of the receiver to the credit reaching the sender
+    pub return_latency: f64,
+}
+
+impl CreditLink {
+    pub fn is_enabled(&self) -> bool {
+        self.credits > 0
+    }
+
+    /// the capacity of the task store, the credits limit the link when they are enabled
+    pub fn store_size(&self, sender_store_size: usize) -> usize {
+        if self.is_enabled() {
+            self.credits
+        } else {
+            sender_store_size
+        }
+    }
+}
+
+/// the task links from the upper level, the links without credits are limited by `sender_store_size` only
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(default)]
+pub struct FlowControl {
+    pub host_to_dimm: CreditLink,
+    pub dimm_to_channel: CreditLink,
+    pub channel_to_chip: CreditLink,
+    pub chip_to_bank: CreditLink,
+}
+
+impl FlowControl {
+    pub fn links(&self) -> [(&'static str, CreditLink); 4] {
+        [
+            ("host_to_dimm", self.host_to_dimm),
+            ("dimm_to_channel", self.dimm_to_channel),
+            ("channel_to_chip", self.channel_to_chip),
+            ("chip_to_bank", self.chip_to_bank),
+        ]
+    }
+}
+
+/// the credits waiting at the sender and the credits sent back by the receiver
+#[derive(Debug, Clone, Copy)]
+pub struct CreditQueues {
+    pub credit_in: ResourceId,
+    pub credit_return: ResourceId,
+}
+
+/// the credit queues of each link, by the queue tracker id of the link
+#[derive(Debug, Default)]
+pub struct SharedCredits {
+    inner: RefCell<BTreeMap<usize, CreditQueues>>,
+}
+
+impl SharedCredits {
+    pub fn add_link(&self, id: &QueueTrackerId, queues: CreditQueues) {
+        self.inner.borrow_mut().insert(id.id, queues);
+    }
+
+    /// None if the link has no credits
+    pub fn get(&self, id: &QueueTrackerId) -> Option<CreditQueues> {
+        self.inner.borrow().get(&id.id).cloned()
+    }
+}
+
+/// one credit of a link, each credit has its own process so the returns overlap
+#[derive(Debug)]
+pub struct CreditReturner {
+    pub queues: CreditQueues,
+    pub return_latency: f64,
+}
+
+impl Component for CreditReturner {
+    fn run(self, original_status: SpmmStatus) -> Box<SpmmGenerator> {
+        let function = |co: Co<SpmmStatus, SpmmContex>| async move {
+            loop {
+                // give the credit to the sender
+                co.yield_(
+                    original_status
+                        .clone_with_state(SpmmStatusEnum::PushCredit(self.queues.credit_in)),
+                )
+                .await;
+                // wait until the receiver pops the task, then the credit travels back
+                co.yield_(
+                    original_status
+                        .clone_with_state(SpmmStatusEnum::Pop(self.queues.credit_return)),
+                )
+                .await;
+                co.yield_(
+                    original_status.clone_with_state(SpmmStatusEnum::Wait(self.return_latency)),
+                )
+                .await;
+            }
+        };
+        Box::new(Gen::new(function))
+    }
+}
@@ -0,0 +1,171 @@
+//! find out why a simulation stopped before all rows are delivered
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::Serialize;
+
+use super::comp_collector::ProcessState;
+
+/// a process that is still waiting on a resource when the simulation ends
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockedProcess {
+    /// the debug info of the component
+    pub process: String,
+    /// true when the process is waiting to push, false when waiting to pop
+    pub is_push: bool,
+    pub resource_id: usize,
+    /// the name in `sender_id_to_name_mapping`, if any
+    pub resource_name: Option<String>,
+}
+
+/// the report of a simulation that stopped before all rows are delivered,
+/// returned as the error of `Simulator::run`
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadlockReport {
+    /// the time when the simulation stopped
+    pub time: f64,
+    pub blocked_processes: Vec<BlockedProcess>,
+    /// (buffer id, the waiting_sequence of the buffer)
+    pub pending_buffers: Vec<(usize, Vec<usize>)>,
+    /// (merger group id, the tasks waiting for a merger)
+    pub pending_mergers: Vec<(usize, Vec<usize>)>,
+    /// the rows that never reached the final receiver
+    pub undelivered_rows: Vec<usize>,
+}
+
+impl DeadlockReport {
+    /// build the report, return None if all rows are delivered.
+    pub fn detect(
+        time: f64,
+        blocked_processes: Vec<(String, ProcessState)>,
+        resource_names: &BTreeMap<usize, String>,
+        pending_buffers: Vec<(usize, Vec<usize>)>,
+        pending_mergers: Vec<(usize, Vec<usize>)>,
+        total_rows: usize,
+        received_rows: &[usize],
+    ) -> Option<Self> {
+        let mut delivered = vec![false; total_rows];
+        for &row in received_rows {
+            if let Some(d) = delivered.get_mut(row) {
+                *d = true;
+            }
+        }
+        let undelivered_rows = delivered
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| !**d)
+            .map(|(row, _)| row)
+            .collect::<Vec<_>>();
+        let blocked_processes = blocked_processes
+            .into_iter()
+            .filter_map(|(process, state)| {
+                let (is_push, resource_id) = match state {
+                    ProcessState::Push(rid) => (true, rid),
+                    ProcessState::Pop(rid) => (false, rid),
+                    _ => return None,
+                };
+                Some(BlockedProcess {
+                    process,
+                    is_push,
+                    resource_id,
+                    resource_name: resource_names.get(&resource_id).cloned(),
+                })
+            })
+            .collect::<Vec<_>>();
+        if undelivered_rows.is_empty() {
+            return None;
+        }
+        Some(Self {
+            time,
+            blocked_processes,
+            pending_buffers,
+            pending_mergers,
+            undelivered_rows,
+        })
+    }
+}
+
+impl Display for DeadlockReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "simulation deadlocked at {}: {} rows undelivered",
+            self.time,
+            self.undelivered_rows.len()
+        )?;
+        // the idle components are always waiting to pop, only show the count of them
+        let (push_blocked, pop_blocked): (Vec<_>, Vec<_>) =
+            self.blocked_processes.iter().partition(|p| p.is_push);
+        for p in push_blocked {
+            writeln!(
+                f,
+                "  blocked on push to {}({}): {}",
+                p.resource_name.as_deref().unwrap_or("unnamed"),
+                p.resource_id,
+                p.process
+            )?;
+        }
+        writeln!(f, "  {} processes waiting to pop", pop_blocked.len())?;
+        for (id, tasks) in &self.pending_buffers {
+            writeln!(f, "  buffer {} waiting for tasks: {:?}", id, tasks)?;
+        }
+        for (id, tasks) in &self.pending_mergers {
+            writeln!(f, "  merger group {} waiting for tasks: {:?}", id, tasks)?;
+        }
+        write!(
+            f,
+            "  undelivered rows: {:?}",
+            self.undelivered_rows.iter().take(32).collect::<Vec<_>>()
+        )
+    }
+}
+
+impl std::error::Error for DeadlockReport {}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn no_deadlock_test() {
+        let report = DeadlockReport::detect(
+            10.,
+            vec![("receiver".to_string(), ProcessState::Pop(0))],
+            &BTreeMap::new(),
+            vec![],
+            vec![],
+            3,
+            &[2, 0, 1],
+        );
+        assert!(report.is_none());
+    }
+
+    #[test]
+    fn deadlock_test() {
+        let names = BTreeMap::from([(1, "channel_0".to_string())]);
+        let report = DeadlockReport::detect(
+            10.,
+            vec![
+                ("receiver".to_string(), ProcessState::Pop(0)),
+                ("sender".to_string(), ProcessState::Push(1)),
+            ],
+            &names,
+            vec![(0, vec![1, 2])],
+            vec![],
+            3,
+            &[0],
+        )
+        .unwrap();
+        assert_eq!(report.undelivered_rows, vec![1, 2]);
+        assert_eq!(report.blocked_processes.len(), 2);
+        assert_eq!(
+            report.blocked_processes[1].resource_name.as_deref(),
+            Some("channel_0")
+        );
+        assert!(report
+            .to_string()
+            .contains("blocked on push to channel_0(1): sender"));
+    }
+}
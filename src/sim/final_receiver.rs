@@ -8,6 +8,8 @@ use crate::{csv_nodata::CsVecNodata, sim::types::StateWithSharedStatus, two_matr
 
 use super::{
     component::Component,
+    sim_time::EndTimeId,
+    stream::BatchEndTimes,
     types::{SpmmContex, SpmmGenerator},
     SpmmStatus, SpmmStatusEnum,
 };
@@ -17,6 +19,10 @@ pub struct FinalReceiver {
     pub collect_result: bool,
     pub result_matrix: Vec<CsVecNodata<usize>>,
     pub all_received: Rc<RefCell<Vec<usize>>>,
+    /// the time of the last received row, None if not recorded
+    pub end_time_id: Option<EndTimeId>,
+    /// the time of the last received row of each batch of a stream
+    pub batch_end_times: Option<BatchEndTimes>,
 }
 
 impl FinalReceiver {
@@ -39,8 +45,22 @@ impl FinalReceiver {
             collect_result,
             result_matrix: vec![],
             all_received,
+            end_time_id: None,
+            batch_end_times: None,
         }
     }
+
+    /// record the time of the last received row
+    pub fn with_end_time(mut self, end_time_id: EndTimeId) -> Self {
+        self.end_time_id = Some(end_time_id);
+        self
+    }
+
+    /// record the time of the last received row of each batch
+    pub fn with_batches(mut self, batch_end_times: BatchEndTimes) -> Self {
+        self.batch_end_times = Some(batch_end_times);
+        self
+    }
 }
 
 impl Component for FinalReceiver {
@@ -52,12 +72,22 @@ impl Component for FinalReceiver {
                     .yield_(original_status.clone_with_state(SpmmStatusEnum::Pop(self.receiver)))
                     .await;
                 debug!("FINIAL_RECIEVER: received final result: {:?}", ret);
-                let (_time, pop_status) = ret.into_inner();
+                let (time, pop_status) = ret.into_inner();
                 let StateWithSharedStatus {
                     status,
-                    shared_status: _,
+                    shared_status,
                 } = pop_status.into_inner();
+                if let Some(end_time_id) = self.end_time_id {
+                    shared_status
+                        .shared_end_time
+                        .set_end_time(end_time_id, time);
+                }
                 let (_resouce_id, partial_result) = status.into_push_partial_task().unwrap();
+                if let Some(batch_end_times) = &self.batch_end_times {
+                    shared_status
+                        .shared_end_time
+                        .set_end_time(batch_end_times.end_time_id(partial_result.target_row), time);
+                }
 
                 debug!(
                     "FINIAL_RECIEVER: {}:{}:{:?}",
@@ -0,0 +1,290 @@
+//! several jobs on the same dimm, the arbiter gives the host link to one task of one job at a time
+
+use std::{
+    cell::{Cell, RefCell},
+    ops::Range,
+    path::PathBuf,
+};
+
+use enum_as_inner::EnumAsInner;
+use genawaiter::rc::{Co, Gen};
+use itertools::Itertools;
+use qsim::ResourceId;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    component::Component,
+    types::{SpmmContex, SpmmGenerator, StateWithSharedStatus},
+    SpmmStatus, SpmmStatusEnum,
+};
+use crate::two_matrix::TwoMatrix;
+
+/// which waiting job sends its next task, in toml: `job_arbitration = "RoundRobin"`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, EnumAsInner)]
+pub enum JobArbitration {
+    /// the job that asked first
+    #[default]
+    Fifo,
+    /// the first waiting job after the job of the last task
+    RoundRobin,
+    /// the waiting job listed first
+    Priority,
+}
+
+impl JobArbitration {
+    /// remove and return one of the `waiting` jobs, `last` is the job of the last task
+    pub fn select(
+        &self,
+        waiting: &mut Vec<usize>,
+        last: Option<usize>,
+        jobs: usize,
+    ) -> Option<usize> {
+        let index = match self {
+            JobArbitration::Fifo => (!waiting.is_empty()).then_some(0),
+            JobArbitration::RoundRobin => {
+                let start = last.map_or(0, |last| last + 1) % jobs;
+                waiting
+                    .iter()
+                    .position_min_by_key(|&&job| (job + jobs - start) % jobs)
+            }
+            JobArbitration::Priority => waiting.iter().position_min(),
+        }?;
+        Some(waiting.remove(index))
+    }
+}
+
+/// how the jobs use the buffers and the mergers of each level, in toml: `job_sharing = "Partitioned"`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, EnumAsInner)]
+pub enum JobSharing {
+    #[default]
+    Shared,
+    /// each job gets an equal part of the lines of each buffer and of the mergers of each group
+    Partitioned,
+}
+
+/// a job of the config, in toml: `jobs = [{ mtx_file = "mtx/a.mtx" }, { mtx_file = "mtx/b.mtx", arrival = 1000.0 }]`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct JobSpec {
+    pub mtx_file: PathBuf,
+    /// the cycle the job starts to send its tasks
+    pub arrival: f64,
+}
+
+/// one job of `Simulator::run_jobs`, A x A^T of its matrix like the other runs
+pub struct Job {
+    pub name: String,
+    pub matrix: TwoMatrix<i32, i32>,
+    pub arrival: f64,
+}
+
+/// the job of each task id, the ids are given in the order the tasks are sent to the dimm
+#[derive(Debug, Default)]
+pub struct JobTable {
+    jobs: Cell<usize>,
+    task_jobs: RefCell<Vec<usize>>,
+}
+
+impl JobTable {
+    pub fn set_jobs(&self, jobs: usize) {
+        self.jobs.set(jobs);
+    }
+
+    /// 0 when the simulation has no jobs
+    pub fn jobs(&self) -> usize {
+        self.jobs.get()
+    }
+
+    /// true if the buffers and mergers are split between the jobs
+    pub fn is_partitioned(&self, sharing: JobSharing) -> bool {
+        sharing.is_partitioned() && self.jobs() > 1
+    }
+
+    /// the id of the next task, a task of `job`
+    pub fn next_task(&self, job: usize) -> usize {
+        let mut task_jobs = self.task_jobs.borrow_mut();
+        task_jobs.push(job);
+        task_jobs.len() - 1
+    }
+
+    pub fn job_of(&self, task_id: usize) -> usize {
+        self.task_jobs.borrow()[task_id]
+    }
+
+    /// the part of `total` lines or mergers used by `job`
+    pub fn partition(&self, job: usize, total: usize) -> Range<usize> {
+        let jobs = self.jobs();
+        job * total / jobs..(job + 1) * total / jobs
+    }
+}
+
+/// the signals from the task senders to the arbiter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobSignal {
+    /// the job has a task to send
+    Request(usize),
+    /// the job has sent the end of its task
+    Done(usize),
+}
+
+/// the link of the task sender of a job to the arbiter
+#[derive(Debug, Clone, Copy)]
+pub struct JobPort {
+    pub job: usize,
+    pub queue_id_arbiter: ResourceId,
+    /// the arbiter pushes a credit when the job can send one task
+    pub queue_id_grant: ResourceId,
+    pub arrival: f64,
+    /// the rows and nonzeros of B of the jobs before this one, the matrices B are stored one after another
+    pub row_offset: usize,
+    pub nnz_offset: usize,
+}
+
+/// gives the host link to one waiting job until it sends the end of its task
+#[derive(Debug)]
+pub struct JobArbiter {
+    pub queue_id_in: ResourceId,
+    pub queue_id_grants: Vec<ResourceId>,
+    pub policy: JobArbitration,
+}
+
+impl Component for JobArbiter {
+    fn run(self, original_status: SpmmStatus) -> Box<SpmmGenerator> {
+        let function = |co: Co<SpmmStatus, SpmmContex>| async move {
+            let jobs = self.queue_id_grants.len();
+            let mut waiting = vec![];
+            let mut last = None;
+            let mut busy = false;
+            loop {
+                let context = co
+                    .yield_(original_status.clone_with_state(SpmmStatusEnum::Pop(self.queue_id_in)))
+                    .await;
+                let (_time, status) = context.into_inner();
+                let (_, signal) = status.into_inner().status.into_push_job_signal().unwrap();
+                match signal {
+                    JobSignal::Request(job) => waiting.push(job),
+                    JobSignal::Done(_) => busy = false,
+                }
+                if busy {
+                    continue;
+                }
+                if let Some(job) = self.policy.select(&mut waiting, last, jobs) {
+                    busy = true;
+                    last = Some(job);
+                    co.yield_(
+                        original_status.clone_with_state(SpmmStatusEnum::PushCredit(
+                            self.queue_id_grants[job],
+                        )),
+                    )
+                    .await;
+                }
+            }
+        };
+        Box::new(Gen::new(function))
+    }
+}
+
+/// sends each final row to the final receiver of its job
+#[derive(Debug)]
+pub struct FinalRouter {
+    pub queue_id_in: ResourceId,
+    pub queue_id_jobs: Vec<ResourceId>,
+}
+
+impl Component for FinalRouter {
+    fn run(self, original_status: SpmmStatus) -> Box<SpmmGenerator> {
+        let function = |co: Co<SpmmStatus, SpmmContex>| async move {
+            loop {
+                let context = co
+                    .yield_(original_status.clone_with_state(SpmmStatusEnum::Pop(self.queue_id_in)))
+                    .await;
+                let (_time, status) = context.into_inner();
+                let StateWithSharedStatus {
+                    status,
+                    shared_status,
+                } = status.into_inner();
+                let (_, partial_sum) = status.into_push_partial_task().unwrap();
+                let job = shared_status.shared_jobs.job_of(partial_sum.task_id);
+                co.yield_(
+                    original_status.clone_with_state(SpmmStatusEnum::PushPartialTask(
+                        self.queue_id_jobs[job],
+                        partial_sum,
+                    )),
+                )
+                .await;
+            }
+        };
+        Box::new(Gen::new(function))
+    }
+}
+
+/// the time of one job with the others and alone
+#[derive(Debug, Clone, Serialize)]
+pub struct JobReport {
+    pub name: String,
+    /// the rows of A
+    pub tasks: usize,
+    pub arrival: f64,
+    /// the cycle the last row of the job is received
+    pub finish: f64,
+    /// `finish - arrival`
+    pub completion_cycles: f64,
+    pub alone_cycles: f64,
+    /// `completion_cycles / alone_cycles`
+    pub slowdown: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobsReport {
+    /// the cycles until all jobs are finished
+    pub cycles: f64,
+    pub arbitration: JobArbitration,
+    pub sharing: JobSharing,
+    pub jobs: Vec<JobReport>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arbitration_test() {
+        let select_all = |policy: JobArbitration, mut waiting: Vec<usize>, last: Option<usize>| {
+            let mut order = vec![];
+            let mut last = last;
+            while let Some(job) = policy.select(&mut waiting, last, 4) {
+                order.push(job);
+                last = Some(job);
+            }
+            order
+        };
+        assert_eq!(
+            select_all(JobArbitration::Fifo, vec![2, 0, 3], None),
+            vec![2, 0, 3]
+        );
+        assert_eq!(
+            select_all(JobArbitration::RoundRobin, vec![0, 1, 3], Some(1)),
+            vec![3, 0, 1]
+        );
+        assert_eq!(
+            select_all(JobArbitration::Priority, vec![2, 0, 3], None),
+            vec![0, 2, 3]
+        );
+    }
+
+    #[test]
+    fn job_table_test() {
+        let table = JobTable::default();
+        table.set_jobs(3);
+        assert!(table.is_partitioned(JobSharing::Partitioned));
+        assert!(!table.is_partitioned(JobSharing::Shared));
+        assert_eq!(table.next_task(1), 0);
+        assert_eq!(table.next_task(0), 1);
+        assert_eq!(table.job_of(0), 1);
+        assert_eq!(table.job_of(1), 0);
+        // 8 lines for 3 jobs
+        assert_eq!(table.partition(0, 8), 0..2);
+        assert_eq!(table.partition(1, 8), 2..5);
+        assert_eq!(table.partition(2, 8), 5..8);
+    }
+}
@@ -1,15 +1,179 @@
-use std::{cell::RefCell, collections::VecDeque};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, VecDeque},
+    fmt::Debug,
+    rc::Rc,
+};
 
+use enum_as_inner::EnumAsInner;
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use tracing::debug;
 
+use super::job::JobTable;
+
+/// choose an idle merger for a task
+pub trait MergerAllocationPolicy: Debug {
+    /// - `idle` is the idle mergers sorted by id, not empty
+    /// - `is_head` is false if an older task is still waiting for a merger, it should leave some mergers for the older one
+    /// - `rows` is the number of partial rows of the task
+    fn select(&mut self, idle: &[usize], is_head: bool, rows: usize) -> Option<usize>;
+}
+
+/// the tasks behind the head can only take a merger when more than `reserved` are idle
+fn can_allocate(idle: &[usize], is_head: bool, reserved: usize) -> bool {
+    is_head || idle.len() > reserved
+}
+
+/// the first idle merger, the last one is kept for the head
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FirstIdle;
+
+impl MergerAllocationPolicy for FirstIdle {
+    fn select(&mut self, idle: &[usize], is_head: bool, _rows: usize) -> Option<usize> {
+        can_allocate(idle, is_head, 1).then(|| idle[0])
+    }
+}
+
+/// the first idle merger after the last allocated one
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl MergerAllocationPolicy for RoundRobin {
+    fn select(&mut self, idle: &[usize], is_head: bool, _rows: usize) -> Option<usize> {
+        if !can_allocate(idle, is_head, 1) {
+            return None;
+        }
+        let selected = idle
+            .iter()
+            .cloned()
+            .find(|&id| id >= self.next)
+            .unwrap_or(idle[0]);
+        self.next = selected + 1;
+        Some(selected)
+    }
+}
+
+/// the idle merger that is allocated the longest time ago
+#[derive(Debug, Clone, Default)]
+pub struct LeastRecentlyUsed {
+    last_used: Vec<u64>,
+    clock: u64,
+}
+
+impl MergerAllocationPolicy for LeastRecentlyUsed {
+    fn select(&mut self, idle: &[usize], is_head: bool, _rows: usize) -> Option<usize> {
+        if !can_allocate(idle, is_head, 1) {
+            return None;
+        }
+        let selected = *idle
+            .iter()
+            .min_by_key(|&&id| self.last_used.get(id).cloned().unwrap_or(0))
+            .unwrap();
+        if self.last_used.len() <= selected {
+            self.last_used.resize(selected + 1, 0);
+        }
+        self.clock += 1;
+        self.last_used[selected] = self.clock;
+        Some(selected)
+    }
+}
+
+/// the mergers from `first_wide` are wider, the tasks of more than `threshold` rows go to them first,
+/// the smaller ones go to the narrow mergers first
+#[derive(Debug, Clone, Copy)]
+pub struct SizeAware {
+    pub first_wide: usize,
+    pub threshold: usize,
+}
+
+impl MergerAllocationPolicy for SizeAware {
+    fn select(&mut self, idle: &[usize], is_head: bool, rows: usize) -> Option<usize> {
+        if !can_allocate(idle, is_head, 1) {
+            return None;
+        }
+        let wants_wide = rows > self.threshold;
+        idle.iter()
+            .cloned()
+            .find(|&id| (id >= self.first_wide) == wants_wide)
+            .or(Some(idle[0]))
+    }
+}
+
+/// the first idle merger, the last `k` are kept for the head
+#[derive(Debug, Clone, Copy)]
+pub struct ReserveMergers(pub usize);
+
+impl MergerAllocationPolicy for ReserveMergers {
+    fn select(&mut self, idle: &[usize], is_head: bool, _rows: usize) -> Option<usize> {
+        can_allocate(idle, is_head, self.0).then(|| idle[0])
+    }
+}
+
+/// the allocation policy of the mergers of one level, in toml: `chip_merger_policy = { SizeAware = { wide = 1, threshold = 4 } }`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, EnumAsInner)]
+pub enum MergerAllocation {
+    /// see `FirstIdle`
+    #[default]
+    FirstIdle,
+    /// see `RoundRobin`
+    RoundRobin,
+    /// see `LeastRecentlyUsed`
+    LeastRecentlyUsed,
+    /// the last `wide` mergers have twice the width, see `SizeAware`
+    SizeAware { wide: usize, threshold: usize },
+    /// see `ReserveMergers`
+    Reserve(usize),
+}
+
+impl MergerAllocation {
+    pub fn build(&self, num_mergers: usize) -> Box<dyn MergerAllocationPolicy> {
+        match *self {
+            MergerAllocation::FirstIdle => Box::new(FirstIdle),
+            MergerAllocation::RoundRobin => Box::new(RoundRobin::default()),
+            MergerAllocation::LeastRecentlyUsed => Box::new(LeastRecentlyUsed::default()),
+            MergerAllocation::SizeAware { wide, threshold } => Box::new(SizeAware {
+                first_wide: num_mergers.saturating_sub(wide),
+                threshold,
+            }),
+            MergerAllocation::Reserve(k) => Box::new(ReserveMergers(k)),
+        }
+    }
+
+    /// the width of the merger `id` of `num_mergers` mergers of `merger_size`
+    pub fn merger_width(&self, merger_size: usize, id: usize, num_mergers: usize) -> usize {
+        match *self {
+            MergerAllocation::SizeAware { wide, .. } if id + wide >= num_mergers => merger_size * 2,
+            _ => merger_size,
+        }
+    }
+}
+
 /// the merger status,
 /// each merger status represent a set of mergers for a certain Chip.. or channel.
 ///
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct MergerStatus {
     current_merger_working: Vec<bool>,
     current_waiting_task_id: VecDeque<usize>,
+    policy: Box<dyn MergerAllocationPolicy>,
+    name: String,
+    policy_name: String,
+    /// the time each working merger is allocated
+    allocated_at: Vec<f64>,
+    allocations: Vec<usize>,
+    busy_time: Vec<f64>,
+    refused: usize,
+    /// when the jobs do not share the mergers, each job only uses its part of the group
+    partition: Option<Rc<JobTable>>,
+}
+
+impl Default for MergerStatus {
+    fn default() -> Self {
+        Self::new(0)
+    }
 }
 
 impl MergerStatus {
@@ -20,62 +184,75 @@ impl MergerStatus {
         Self {
             current_merger_working: vec![false; num_mergers],
             current_waiting_task_id: VecDeque::new(),
+            policy: Box::new(FirstIdle),
+            name: String::new(),
+            policy_name: format!("{:?}", MergerAllocation::FirstIdle),
+            allocated_at: vec![0.; num_mergers],
+            allocations: vec![0; num_mergers],
+            busy_time: vec![0.; num_mergers],
+            refused: 0,
+            partition: None,
         }
     }
 
-    /// select a new merger and push a task into it.
-    /// - when it's standalone mode, it will send the lastest one to the first merger.
+    pub fn with_policy(mut self, name: String, policy: MergerAllocation) -> Self {
+        self.policy = policy.build(self.current_merger_working.len());
+        self.name = name;
+        self.policy_name = format!("{policy:?}");
+        self
+    }
+
+    /// select a new merger and push a task of `rows` partial rows into it at `time`.
+    /// - when it's standalone mode, the policy keeps some mergers for the head of the waiting tasks.
     /// - it will also delete the waiting task
-    pub fn get_next_merger(&mut self, task_id: usize, is_binding: bool) -> Option<usize> {
-        // find a merger with least on_going_task_num
-        if is_binding {
-            debug!("current_ongoing: {:?}", &self.current_merger_working);
-            let avaliable = self.current_merger_working.iter().position(|&x| x == false);
-            if let Some(id) = avaliable {
-                self.current_merger_working[id] = true;
-                self.current_waiting_task_id.remove(
-                    self.current_waiting_task_id
-                        .binary_search(&task_id)
-                        .unwrap(),
-                );
-                return Some(id);
-            } else {
-                return None;
-            }
-        } else {
-            // standalone mode
-            if self.current_waiting_task_id.front().unwrap() == &task_id {
-                let avaliable = self.current_merger_working.iter().position(|&x| x == false);
-                if let Some(position) = avaliable {
-                    self.current_merger_working[position] = true;
-                    self.current_waiting_task_id.pop_front();
-                    return Some(position);
-                } else {
-                    return None;
-                }
-            } else {
-                let all_positions = self
-                    .current_merger_working
+    pub fn get_next_merger(
+        &mut self,
+        task_id: usize,
+        is_binding: bool,
+        rows: usize,
+        time: f64,
+    ) -> Option<usize> {
+        debug!("current_ongoing: {:?}", &self.current_merger_working);
+        let mut idle = self
+            .current_merger_working
+            .iter()
+            .positions(|&x| !x)
+            .collect_vec();
+        let mut is_head = is_binding || self.current_waiting_task_id.front() == Some(&task_id);
+        if let Some(jobs) = &self.partition {
+            // the head is the oldest waiting task of the same job
+            let job = jobs.job_of(task_id);
+            let part = jobs.partition(job, self.current_merger_working.len());
+            idle.retain(|id| part.contains(id));
+            is_head = is_binding
+                || self
+                    .current_waiting_task_id
                     .iter()
-                    .enumerate()
-                    .filter(|&(_, x)| x == &false)
-                    .map(|(i, _)| i)
-                    .collect::<Vec<_>>();
-                match all_positions.len() {
-                    0..=1 => None,
-                    _ => {
-                        let avaliable = all_positions[0];
-                        self.current_waiting_task_id.remove(
-                            self.current_waiting_task_id
-                                .binary_search(&task_id)
-                                .unwrap(),
-                        );
-                        self.current_merger_working[avaliable] = true;
-                        Some(avaliable)
-                    }
-                }
-            }
+                    .find(|&&task| jobs.job_of(task) == job)
+                    == Some(&task_id);
         }
+        let selected = if idle.is_empty() {
+            None
+        } else {
+            self.policy.select(&idle, is_head, rows)
+        };
+        let id = match selected {
+            Some(id) => id,
+            None => {
+                self.refused += 1;
+                return None;
+            }
+        };
+        debug_assert!(!self.current_merger_working[id]);
+        self.current_merger_working[id] = true;
+        self.allocated_at[id] = time;
+        self.allocations[id] += 1;
+        self.current_waiting_task_id.remove(
+            self.current_waiting_task_id
+                .binary_search(&task_id)
+                .unwrap(),
+        );
+        Some(id)
     }
     /// add to the waiting only when it's standalone mode
 
@@ -97,14 +274,41 @@ impl MergerStatus {
         }
     }
 
-    /// means the merger is done.
+    /// means the merger is done at `time`.
     /// _is_binding
-    pub fn release_merger(&mut self, merger_id: usize, _task_id: usize, _is_binding: bool) {
+    pub fn release_merger(
+        &mut self,
+        merger_id: usize,
+        _task_id: usize,
+        _is_binding: bool,
+        time: f64,
+    ) {
         assert!(self.current_merger_working[merger_id]);
         self.current_merger_working[merger_id] = false;
+        self.busy_time[merger_id] += time - self.allocated_at[merger_id];
     }
 }
 
+/// the usage of the mergers of one group
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MergerGroupStats {
+    pub policy: String,
+    /// the tasks sent to each merger
+    pub allocations: Vec<usize>,
+    pub busy_time: Vec<f64>,
+    /// the busy time of all mergers over the total time of all mergers
+    pub utilization: f64,
+    /// the allocations that found no merger and waited
+    pub refused: usize,
+}
+
+/// the merger usage of each group and the mean utilization of the groups of each policy
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MergerStats {
+    pub groups: BTreeMap<String, MergerGroupStats>,
+    pub policies: BTreeMap<String, f64>,
+}
+
 /// This is the merger status, it is used to store the merger status.
 #[derive(Debug, Default)]
 pub struct SharedMergerStatus {
@@ -124,26 +328,45 @@ impl SharedMergerStatus {
         }
     }
     pub fn add_component(&self, total_merger: usize) -> MergerStatusId {
+        self.add_component_with_policy(String::new(), total_merger, MergerAllocation::FirstIdle)
+    }
+    /// `name` is the name of the group in `MergerStats`
+    pub fn add_component_with_policy(
+        &self,
+        name: String,
+        total_merger: usize,
+        policy: MergerAllocation,
+    ) -> MergerStatusId {
         let mut inner = self.inner.borrow_mut();
-        inner.push(MergerStatus::new(total_merger));
-        MergerStatusId {
-            id: inner.len() - 1,
-        }
+        let id = inner.len();
+        let name = if name.is_empty() {
+            format!("merger-{id}")
+        } else {
+            name
+        };
+        inner.push(MergerStatus::new(total_merger).with_policy(name, policy));
+        MergerStatusId { id }
+    }
+    /// split the mergers of the group between the jobs of `jobs`
+    pub fn partition_by_job(&self, id: &MergerStatusId, jobs: Rc<JobTable>) {
+        self.inner.borrow_mut()[id.id].partition = Some(jobs);
     }
     // this target row will need to go to the merger. only standalone mode will take effect on this.
     pub fn add_waiting(&self, id: &MergerStatusId, task_id: usize) {
         let mut inner = self.inner.borrow_mut();
         inner[id.id].add_waiting(task_id, self.is_binding);
     }
-    // fetch the next merger for task_id.
+    // fetch the next merger for task_id of `rows` partial rows.
     pub fn get_next_merger(
         &self,
         id: MergerStatusId,
         task_id: usize,
         is_binding: bool,
+        rows: usize,
+        time: f64,
     ) -> Option<usize> {
         let mut inner = self.inner.borrow_mut();
-        inner[id.id].get_next_merger(task_id, is_binding)
+        inner[id.id].get_next_merger(task_id, is_binding, rows, time)
     }
     // release the merger for task_id.
     pub fn release_merger(
@@ -152,8 +375,159 @@ impl SharedMergerStatus {
         merger_id: usize,
         task_id: usize,
         is_binding: bool,
+        time: f64,
     ) {
         let mut inner = self.inner.borrow_mut();
-        inner[id.id].release_merger(merger_id, task_id, is_binding);
+        inner[id.id].release_merger(merger_id, task_id, is_binding, time);
+    }
+    /// all merger groups that still have tasks waiting for a merger, (group id, waiting task ids)
+    pub fn pending_tasks(&self) -> Vec<(usize, Vec<usize>)> {
+        self.inner
+            .borrow()
+            .iter()
+            .enumerate()
+            .filter(|(_, status)| !status.current_waiting_task_id.is_empty())
+            .map(|(id, status)| (id, status.current_waiting_task_id.iter().cloned().collect()))
+            .collect()
+    }
+
+    /// the usage of all groups, `total_time` is the end time of the simulation
+    pub fn get_stats(&self, total_time: f64) -> MergerStats {
+        let inner = self.inner.borrow();
+        let groups: BTreeMap<_, _> = inner
+            .iter()
+            .map(|status| {
+                let capacity = total_time * status.busy_time.len() as f64;
+                let busy = status.busy_time.iter().sum::<f64>();
+                let stats = MergerGroupStats {
+                    policy: status.policy_name.clone(),
+                    allocations: status.allocations.clone(),
+                    busy_time: status.busy_time.clone(),
+                    utilization: if capacity > 0. { busy / capacity } else { 0. },
+                    refused: status.refused,
+                };
+                (status.name.clone(), stats)
+            })
+            .collect();
+        let policies = groups
+            .values()
+            .into_group_map_by(|group| group.policy.clone())
+            .into_iter()
+            .map(|(policy, groups)| {
+                let mean = groups.iter().map(|g| g.utilization).sum::<f64>() / groups.len() as f64;
+                (policy, mean)
+            })
+            .collect();
+        MergerStats { groups, policies }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// a standalone merger status with the tasks `0..tasks` waiting
+    fn status(num_mergers: usize, policy: MergerAllocation, tasks: usize) -> MergerStatus {
+        let mut status = MergerStatus::new(num_mergers).with_policy("test".to_string(), policy);
+        for task_id in 0..tasks {
+            status.add_waiting(task_id, false);
+        }
+        status
+    }
+
+    #[test]
+    fn first_idle_test() {
+        let mut status = status(2, MergerAllocation::FirstIdle, 3);
+        // 1 is not the head, but 2 mergers are idle
+        assert_eq!(status.get_next_merger(1, false, 1, 0.), Some(0));
+        // the last merger is kept for 0
+        assert_eq!(status.get_next_merger(2, false, 1, 1.), None);
+        assert_eq!(status.get_next_merger(0, false, 1, 2.), Some(1));
+        status.release_merger(0, 1, false, 5.);
+        status.release_merger(1, 0, false, 6.);
+        assert_eq!(status.busy_time, vec![5., 4.]);
+        assert_eq!(status.refused, 1);
+    }
+
+    #[test]
+    fn round_robin_and_lru_test() {
+        let mut round_robin = status(3, MergerAllocation::RoundRobin, 5);
+        let mut lru = status(3, MergerAllocation::LeastRecentlyUsed, 5);
+        for status in [&mut round_robin, &mut lru] {
+            assert_eq!(status.get_next_merger(0, false, 1, 0.), Some(0));
+            assert_eq!(status.get_next_merger(1, false, 1, 0.), Some(1));
+            status.release_merger(1, 1, false, 1.);
+            assert_eq!(status.get_next_merger(2, false, 1, 1.), Some(2));
+            status.release_merger(2, 2, false, 2.);
+            // 0 is still working
+            assert_eq!(status.get_next_merger(3, false, 1, 2.), Some(1));
+            status.release_merger(0, 0, false, 3.);
+            status.release_merger(1, 3, false, 3.);
+        }
+        // round robin goes on after 1, lru takes the merger used the longest time ago
+        assert_eq!(round_robin.get_next_merger(4, false, 1, 3.), Some(2));
+        assert_eq!(lru.get_next_merger(4, false, 1, 3.), Some(0));
+    }
+
+    #[test]
+    fn size_aware_test() {
+        let policy = MergerAllocation::SizeAware {
+            wide: 1,
+            threshold: 4,
+        };
+        assert_eq!(policy.merger_width(4, 2, 3), 8);
+        assert_eq!(policy.merger_width(4, 1, 3), 4);
+        let mut status = status(3, policy, 3);
+        assert_eq!(status.get_next_merger(0, false, 8, 0.), Some(2));
+        assert_eq!(status.get_next_merger(1, false, 1, 0.), Some(0));
+        // the wide merger is working, the large task takes a narrow one
+        assert_eq!(status.get_next_merger(2, false, 8, 0.), Some(1));
+    }
+
+    #[test]
+    fn reserve_test() {
+        let mut status = status(3, MergerAllocation::Reserve(2), 3);
+        assert_eq!(status.get_next_merger(1, false, 1, 0.), Some(0));
+        assert_eq!(status.get_next_merger(2, false, 1, 0.), None);
+        assert_eq!(status.get_next_merger(0, false, 1, 0.), Some(1));
+        assert_eq!(status.get_next_merger(2, false, 1, 0.), Some(2));
+    }
+
+    #[test]
+    fn partition_test() {
+        let jobs = Rc::new(JobTable::default());
+        jobs.set_jobs(2);
+        // the tasks 0, 2 and 4 are of the job 0, 1 and 3 of the job 1
+        for job in [0, 1, 0, 1, 0] {
+            jobs.next_task(job);
+        }
+        let shared = SharedMergerStatus::new(false);
+        let id = shared.add_component(4);
+        shared.partition_by_job(&id, jobs);
+        (0..5).for_each(|task_id| shared.add_waiting(&id, task_id));
+        // the job 1 takes the mergers 2 and 3, 3 is the head of the job 1 after 1
+        assert_eq!(shared.get_next_merger(id, 1, false, 1, 0.), Some(2));
+        assert_eq!(shared.get_next_merger(id, 3, false, 1, 0.), Some(3));
+        assert_eq!(shared.get_next_merger(id, 2, false, 1, 0.), Some(0));
+        // the last merger of the job 0 is kept for 0
+        assert_eq!(shared.get_next_merger(id, 4, false, 1, 0.), None);
+        assert_eq!(shared.get_next_merger(id, 0, false, 1, 0.), Some(1));
+    }
+
+    #[test]
+    fn stats_test() {
+        let shared = SharedMergerStatus::new(false);
+        let first =
+            shared.add_component_with_policy("a".to_string(), 2, MergerAllocation::FirstIdle);
+        let _second =
+            shared.add_component_with_policy("b".to_string(), 2, MergerAllocation::RoundRobin);
+        shared.add_waiting(&first, 0);
+        assert_eq!(shared.get_next_merger(first, 0, false, 1, 2.), Some(0));
+        shared.release_merger(first, 0, 0, false, 7.);
+        let stats = shared.get_stats(10.);
+        assert_eq!(stats.groups["a"].allocations, vec![1, 0]);
+        assert_eq!(stats.groups["a"].utilization, 0.25);
+        assert_eq!(stats.policies["FirstIdle"], 0.25);
+        assert_eq!(stats.policies["RoundRobin"], 0.);
     }
 }
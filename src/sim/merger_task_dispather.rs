@@ -1,6 +1,6 @@
 use std::{cmp::Reverse, collections::BinaryHeap};
 
-use crate::{csv_nodata::CsVecNodata, sim::types::PushFullSumType};
+use crate::sim::types::{PushFullSumType, SharedRow};
 
 use super::{
     component::Component,
@@ -28,7 +28,7 @@ pub struct MergerWorkerDispatcher {
 struct TempFullResult {
     pub task_id: usize,
     pub target_row: usize,
-    pub target_result: Vec<CsVecNodata<usize>>,
+    pub target_result: Vec<SharedRow>,
 }
 
 impl Ord for TempFullResult {
@@ -51,7 +51,7 @@ impl Component for MergerWorkerDispatcher {
                 let task = co
                     .yield_(original_status.clone_with_state(SpmmStatusEnum::Pop(self.full_sum_in)))
                     .await;
-                let (_, ret_status) = task.into_inner();
+                let (time, ret_status) = task.into_inner();
                 let StateWithSharedStatus {
                     status,
                     shared_status,
@@ -71,6 +71,8 @@ impl Component for MergerWorkerDispatcher {
                             self.merger_status_id,
                             task_id,
                             self.is_binding,
+                            target_result.len(),
+                            time,
                         ) {
                             // find a empty merger!
                             // push the partial result back
@@ -121,9 +123,14 @@ impl Component for MergerWorkerDispatcher {
                                 "MergerWorkerDispatcher-{:?}: start to test target_id: {target_row}",
                                 self.level_id,
                             );
-                            if let Some(target_pe) = shared_status
-                                .shared_merger_status
-                                .get_next_merger(self.merger_status_id, task_id, self.is_binding)
+                            if let Some(target_pe) =
+                                shared_status.shared_merger_status.get_next_merger(
+                                    self.merger_status_id,
+                                    task_id,
+                                    self.is_binding,
+                                    target_result.len(),
+                                    time,
+                                )
                             {
                                 // push to that merger
                                 // find a empty merger!
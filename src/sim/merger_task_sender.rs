@@ -80,6 +80,17 @@ where
                 shared_status
                     .queue_tracker
                     .deq(self.get_queue_tracker_id_recv());
+                if let Some(credits) = shared_status
+                    .shared_credits
+                    .get(self.get_queue_tracker_id_recv())
+                {
+                    // the task left the link, give the credit back to the upper pe
+                    co.yield_(
+                        original_status
+                            .clone_with_state(SpmmStatusEnum::PushCredit(credits.credit_return)),
+                    )
+                    .await;
+                }
                 if gap > 10. {
                     tracing::debug!(target : "spmm_pim::sim::merger_task_sender::timeerror",
                     "error! gap is too large: {}", gap);
@@ -118,6 +129,25 @@ where
                             .shared_merger_status
                             .add_waiting(self.get_merger_status_id(), task_id);
 
+                        if let Some(credits) = shared_status
+                            .shared_credits
+                            .get(&self.get_queue_tracker_id_send()[lower_index])
+                        {
+                            let context = co
+                                .yield_(
+                                    original_status
+                                        .clone_with_state(SpmmStatusEnum::Pop(credits.credit_in)),
+                                )
+                                .await;
+                            let (_time, _status) = context.into_inner();
+                            let gap = _time - current_time;
+                            current_time = _time;
+                            shared_status.shared_named_time.add_idle_time(
+                                self.get_time_id(),
+                                "wait_credit",
+                                gap,
+                            );
+                        }
                         let context = co
                             .yield_(
                                 original_status.clone_with_state(SpmmStatusEnum::PushBankTask(
@@ -151,6 +181,9 @@ where
                             gap,
                         );
                     }
+                    BankTaskEnum::Spill(_) => {
+                        unreachable!("the spill tasks are pushed to the banks directly")
+                    }
                     BankTaskEnum::EndThisTask => {
                         // push this to every lower pe
                         for (lower_pe_id, lower_queue_tracker_id) in self
@@ -158,6 +191,25 @@ where
                             .iter()
                             .zip(self.get_queue_tracker_id_send())
                         {
+                            if let Some(credits) =
+                                shared_status.shared_credits.get(lower_queue_tracker_id)
+                            {
+                                let context = co
+                                    .yield_(
+                                        original_status.clone_with_state(SpmmStatusEnum::Pop(
+                                            credits.credit_in,
+                                        )),
+                                    )
+                                    .await;
+                                let (_time, _status) = context.into_inner();
+                                let gap = _time - current_time;
+                                current_time = _time;
+                                shared_status.shared_named_time.add_idle_time(
+                                    self.get_time_id(),
+                                    "wait_credit",
+                                    gap,
+                                );
+                            }
                             let context = co
                                 .yield_(original_status.clone_with_state(
                                     SpmmStatusEnum::PushBankTask(
@@ -1,13 +1,17 @@
 pub mod bank;
+pub mod bank_cache;
 pub mod buffer_status;
 pub mod channel_merger;
 pub mod chip_merger;
 pub mod comp_collector;
 pub mod component;
+pub mod credit;
+pub mod deadlock;
 pub mod dimm_merger;
 pub mod final_receiver;
 pub mod full_result_merger_worker;
 pub mod id_translation;
+pub mod job;
 pub mod merger_status;
 pub mod merger_task_dispather;
 pub mod merger_task_sender;
@@ -18,6 +22,8 @@ pub mod partial_sum_sender_dimm;
 pub mod partial_sum_signal_collector;
 pub mod queue_tracker;
 pub mod sim_time;
+pub mod spill;
+pub mod stream;
 mod task_balance;
 pub mod task_reorderer;
 pub mod task_router;
@@ -29,15 +35,20 @@ use itertools::Itertools;
 use tracing::{debug, error, info};
 
 use qsim::{prelude::*, resources::Store};
+use serde::Serialize;
+use sprs::CsMat;
 
 use self::{
     bank::{BankPe, BankTaskReorder},
-    buffer_status::SharedBufferStatus,
+    bank_cache::BankCacheStats,
+    buffer_status::{BufferAdmission, BufferAdmissionPolicy, PartitionByJob, SharedBufferStatus},
     channel_merger::ChannelMerger,
     chip_merger::ChipMerger,
+    component::Component,
     dimm_merger::DimmMerger,
     final_receiver::FinalReceiver,
     full_result_merger_worker::FullResultMergerWorker,
+    job::{FinalRouter, Job, JobArbiter, JobPort, JobReport, JobsReport},
     merger_task_dispather::MergerWorkerDispatcher,
     partial_sum_collector::PartialSumCollector,
     partial_sum_sender::PartialSumSender,
@@ -45,26 +56,143 @@ use self::{
     partial_sum_sender_dimm::PartialSumSenderDimm,
     partial_sum_signal_collector::PartialSumSignalCollector,
     queue_tracker::QueueTrackerId,
-    sim_time::{
-        DetailedTimeStats, LevelTime, LevelTimeId, SharedNamedTime, SharedSimTime, TimeStats,
-    },
+    sim_time::{LevelTime, LevelTimeId, SharedNamedTime, SharedSimTime},
     task_sender::TaskSender,
     types::SpmmStatus,
 };
 use crate::{
     csv_nodata::CsVecNodata,
+    fault::FaultMap,
+    pim::ResolvedRowMapping,
+    replication::ReplicationPlan,
     settings::MemSettings,
     sim::{
         comp_collector::ProcessInfoCollector,
-        merger_status::SharedMergerStatus,
+        credit::{CreditLink, CreditQueues, CreditReturner},
+        deadlock::DeadlockReport,
+        merger_status::{MergerStats, MergerStatusId, SharedMergerStatus},
         queue_tracker::QueueTracker,
         sim_time::SharedEndTime,
+        spill::{SpillPort, SpillRegion, SpillStats},
+        stream::{Batch, BatchArrival, BatchEndTimes, BatchReport, StreamReport},
         task_balance::{BatchShuffleScheduler, DefaultTaskScheduler, RandomTaskScheduler},
         types::{SharedStatus, SpmmStatusEnum},
     },
     two_matrix::TwoMatrix,
 };
-use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+use eyre::WrapErr;
+use std::{cell::RefCell, collections::BTreeMap, fmt::Debug, rc::Rc};
+
+/// the credits of the task link tracked by `id`, nothing if the link has no credits
+fn create_credit_link(
+    sim: &mut Simulation<SpmmStatus>,
+    status: &SpmmStatus,
+    p_collector: &mut ProcessInfoCollector,
+    sender_id_to_name_mapping: &mut BTreeMap<usize, String>,
+    link: &CreditLink,
+    id: &QueueTrackerId,
+    name: &str,
+) {
+    if !link.is_enabled() {
+        return;
+    }
+    let queues = CreditQueues {
+        credit_in: sim.create_resource(Box::new(Store::new(link.credits)), "credit_in"),
+        credit_return: sim.create_resource(Box::new(Store::new(link.credits)), "credit_return"),
+    };
+    sender_id_to_name_mapping.insert(queues.credit_in, format!("credit_in_{name}"));
+    sender_id_to_name_mapping.insert(queues.credit_return, format!("credit_return_{name}"));
+    status.shared_status.shared_credits.add_link(id, queues);
+    for _ in 0..link.credits {
+        let returner = CreditReturner {
+            queues,
+            return_latency: link.return_latency,
+        };
+        p_collector.create_process_and_schedule(sim, returner, status);
+    }
+}
+
+/// the spill region of a chip or channel buffer, None if the spill is disabled
+fn create_spill_region(
+    sim: &mut Simulation<SpmmStatus>,
+    mem_settings: &MemSettings,
+    name: &str,
+) -> Option<SpillRegion> {
+    mem_settings.spill.is_enabled().then(|| SpillRegion {
+        spill: mem_settings.spill,
+        queue_id_write: sim.create_resource(Box::new(Store::new(1)), "spill_write"),
+        // at most one write and one read of each line are waiting
+        queue_id_access: sim.create_resource(
+            Box::new(Store::new(2 * mem_settings.spill.region_lines)),
+            "spill_access",
+        ),
+        queue_id_read_back: sim
+            .create_resource(Box::new(Store::new(mem_settings.spill.region_lines)), name),
+    })
+}
+
+/// the port of a spill region, the region is spread over the banks below the buffer that did not fail
+fn create_spill_port(
+    sim: &mut Simulation<SpmmStatus>,
+    status: &SpmmStatus,
+    p_collector: &mut ProcessInfoCollector,
+    mem_settings: &MemSettings,
+    level_id: LevelId,
+    region: SpillRegion,
+    banks: &[(BankID, usize)],
+) {
+    let fault_map = FaultMap::new(mem_settings);
+    let healthy = banks
+        .iter()
+        .filter(|(bank, _)| !fault_map.is_failed(*bank))
+        .map(|(_, queue)| *queue)
+        .collect_vec();
+    let bank_task_in = if healthy.is_empty() {
+        banks.iter().map(|(_, queue)| *queue).collect()
+    } else {
+        healthy
+    };
+    let named_sim_time = status
+        .shared_status
+        .shared_named_time
+        .add_component_with_name(format!("spill_port-{level_id:?}"), vec!["spill_port"]);
+    let port = SpillPort {
+        level_id,
+        region,
+        bank_task_in,
+        queue_id_done: sim.create_resource(Box::new(Store::new(1)), "spill_done"),
+        named_sim_time,
+    };
+    p_collector.create_process_and_schedule(sim, port, status);
+}
+
+/// the admission policy of a buffer, the lines are split between the jobs when they do not share them
+fn build_buffer_policy(
+    shared_status: &SharedStatus,
+    mem_settings: &MemSettings,
+    policy: BufferAdmission,
+) -> Box<dyn BufferAdmissionPolicy> {
+    let jobs = &shared_status.shared_jobs;
+    if jobs.is_partitioned(mem_settings.job_sharing) {
+        Box::new(PartitionByJob { jobs: jobs.clone() })
+    } else {
+        policy.build()
+    }
+}
+
+/// split the mergers of a group between the jobs when they do not share them
+fn partition_mergers(
+    shared_status: &SharedStatus,
+    mem_settings: &MemSettings,
+    merger_status_id: &MergerStatusId,
+) {
+    let jobs = &shared_status.shared_jobs;
+    if jobs.is_partitioned(mem_settings.job_sharing) {
+        shared_status
+            .shared_merger_status
+            .partition_by_job(merger_status_id, jobs.clone());
+    }
+}
 
 fn build_dimm(
     mem_settings: &MemSettings,
@@ -87,7 +215,12 @@ fn build_dimm(
     let channel_stores = (0..num_channels)
         .map(|_i| {
             sim.create_resource(
-                Box::new(Store::new(mem_settings.sender_store_size)),
+                Box::new(Store::new(
+                    mem_settings
+                        .flow_control
+                        .dimm_to_channel
+                        .store_size(mem_settings.sender_store_size),
+                )),
                 "dimm_to_channel",
             )
         })
@@ -97,17 +230,35 @@ fn build_dimm(
     }
     let merger_status_id = shared_status
         .shared_merger_status
-        .add_component(mem_settings.dimm_merger_count);
+        .add_component_with_policy(
+            "dimm".to_string(),
+            mem_settings.dimm_merger_count,
+            mem_settings.dimm_merger_policy,
+        );
+    partition_mergers(&shared_status, mem_settings, &merger_status_id);
 
     let sim_time_id = shared_status
         .shared_named_time
         .add_component_with_name("DIMMSENDER_GETID", vec!["dimm"]);
     let buffer_status_id = shared_status
         .shared_buffer_status
-        .add_component(mem_settings.dimm_buffer_lines);
+        .add_component_with_policy(
+            mem_settings.dimm_buffer_lines,
+            build_buffer_policy(
+                &shared_status,
+                mem_settings,
+                mem_settings.dimm_buffer_policy,
+            ),
+        );
 
-    let signal_in = sim.create_resource(Box::new(Store::new(128)), "signal_dimm");
-    let ready_id_queue = sim.create_resource(Box::new(Store::new(128)), "ready_dimm");
+    let signal_in = sim.create_resource(
+        Box::new(Store::new(mem_settings.queue_sizes.signal)),
+        "signal_dimm",
+    );
+    let ready_id_queue = sim.create_resource(
+        Box::new(Store::new(mem_settings.queue_sizes.ready)),
+        "ready_dimm",
+    );
     let named_sim_time = shared_status.shared_named_time.add_component_with_name(
         "dimm_signal_collector",
         vec!["signal_collector", "dimm_signal_collector"],
@@ -118,10 +269,13 @@ fn build_dimm(
         buffer_status_id,
         level_id: LevelId::Dimm,
         named_sim_time,
+        spill_region: None,
     };
     p_collector.create_process_and_schedule(sim, dimm_signal_collector, &status);
-    let collector_to_dispatcher =
-        sim.create_resource(Box::new(Store::new(1)), "collector_to_dispatcher_dimm");
+    let collector_to_dispatcher = sim.create_resource(
+        Box::new(Store::new(mem_settings.queue_sizes.collector_to_dispatcher)),
+        "collector_to_dispatcher_dimm",
+    );
     let named_sim_time = shared_status.shared_named_time.add_component_with_name(
         "DIMM_DATA_COLLECTOR",
         vec!["dimm_data_collector", "data_collector"],
@@ -145,6 +299,17 @@ fn build_dimm(
                 .add_component_with_name(format!("dimm_sender-{i}"))
         })
         .collect_vec();
+    for (i, id) in queue_tracker_id_send.iter().enumerate() {
+        create_credit_link(
+            sim,
+            &status,
+            p_collector,
+            sender_id_to_name_mapping,
+            &mem_settings.flow_control.dimm_to_channel,
+            id,
+            &format!("channel_{i}"),
+        );
+    }
 
     let dimm = DimmMerger::new(
         LevelId::Dimm,
@@ -161,8 +326,10 @@ fn build_dimm(
     // create the merger_task_worker
     let mut task_receiver = vec![];
     for i in 0..mem_settings.dimm_merger_count {
-        let merger_to_sender_queue =
-            sim.create_resource(Box::new(Store::new(1)), "merger_to_sender_dimm");
+        let merger_to_sender_queue = sim.create_resource(
+            Box::new(Store::new(mem_settings.queue_sizes.merger_to_sender)),
+            "merger_to_sender_dimm",
+        );
         let named_sim_time = shared_status.shared_named_time.add_component_with_name(
             "dimm_sum_sender",
             vec!["partial_sum_sender", "dimm_sum_sender"],
@@ -179,8 +346,10 @@ fn build_dimm(
         };
         p_collector.create_process_and_schedule(sim, dimm_signal_sender, &status);
 
-        let full_partial_sum_in =
-            sim.create_resource(Box::new(Store::new(1)), "dispatcher_to_merger_dimm");
+        let full_partial_sum_in = sim.create_resource(
+            Box::new(Store::new(mem_settings.queue_sizes.dispatcher_to_merger)),
+            "dispatcher_to_merger_dimm",
+        );
         let named_sim_time = shared_status.shared_named_time.add_component_with_name(
             "DIMM_MERGER_TASK_WORKER",
             vec!["dimm_merger_task_worker", "merger_task_worker"],
@@ -193,7 +362,11 @@ fn build_dimm(
             queue_id_partial_sum_in: full_partial_sum_in,
             self_sender_id: task_send_store,
             merger_status_id,
-            merger_width: mem_settings.dimm_merger_size,
+            merger_width: mem_settings.dimm_merger_policy.merger_width(
+                mem_settings.dimm_merger_size,
+                i,
+                mem_settings.dimm_merger_count,
+            ),
             named_sim_time,
             is_bind: mem_settings.buffer_mode.is_bind_merger(),
             queue_id_finished_signal_out: collector_to_dispatcher,
@@ -250,13 +423,19 @@ fn build_channel(
         .enumerate()
     {
         // create the channel!
+        let mem_settings = &mem_settings.for_channel(channel_id);
         let num_chips = mem_settings.chips;
 
         // the channel that send task to the chip from this channel
         let chip_stores = (0..num_chips)
             .map(|_i| {
                 sim.create_resource(
-                    Box::new(Store::new(mem_settings.sender_store_size)),
+                    Box::new(Store::new(
+                        mem_settings
+                            .flow_control
+                            .channel_to_chip
+                            .store_size(mem_settings.sender_store_size),
+                    )),
                     "channel_to_chip",
                 )
             })
@@ -266,33 +445,55 @@ fn build_channel(
         }
         let merger_status_id = shared_status
             .shared_merger_status
-            .add_component(mem_settings.channel_merger_count);
+            .add_component_with_policy(
+                format!("channel-{channel_id}"),
+                mem_settings.channel_merger_count,
+                mem_settings.channel_merger_policy,
+            );
+        partition_mergers(&shared_status, mem_settings, &merger_status_id);
 
         let sim_time = shared_status
             .shared_named_time
             .add_component_with_name("channel_sender", vec!["channel_task_sender", "task_sender"]);
         let buffer_status_id = shared_status
             .shared_buffer_status
-            .add_component(mem_settings.channel_buffer_lines);
+            .add_component_with_policy(
+                mem_settings.channel_buffer_lines,
+                build_buffer_policy(
+                    &shared_status,
+                    mem_settings,
+                    mem_settings.channel_buffer_policy,
+                ),
+            );
 
-        let signal_in = sim.create_resource(Box::new(Store::new(128)), "signal_channel");
-        let ready_queueid = sim.create_resource(Box::new(Store::new(128)), "ready_channel");
+        let signal_in = sim.create_resource(
+            Box::new(Store::new(mem_settings.queue_sizes.signal)),
+            "signal_channel",
+        );
+        let ready_queueid = sim.create_resource(
+            Box::new(Store::new(mem_settings.queue_sizes.ready)),
+            "ready_channel",
+        );
         let named_sim_time = shared_status.shared_named_time.add_component_with_name(
             "channel_signal_collector",
             vec!["signal_collector", "channel_signal_collector"],
         );
+        let spill_region = create_spill_region(sim, mem_settings, "spill_read_back_channel");
         let channel_signal_collector = PartialSumSignalCollector {
             queue_id_signal_in: signal_in,
             queue_id_ready_out: ready_queueid,
             buffer_status_id,
             level_id: LevelId::Channel(channel_id),
             named_sim_time,
+            spill_region,
         };
 
         p_collector.create_process_and_schedule(sim, channel_signal_collector, &status);
 
-        let collector_to_dispatcher =
-            sim.create_resource(Box::new(Store::new(1)), "collector_to_dispatcher_channel");
+        let collector_to_dispatcher = sim.create_resource(
+            Box::new(Store::new(mem_settings.queue_sizes.collector_to_dispatcher)),
+            "collector_to_dispatcher_channel",
+        );
 
         let named_sim_time = shared_status.shared_named_time.add_component_with_name(
             "CHANNEL_DATA_COLLECTOR",
@@ -316,6 +517,17 @@ fn build_channel(
                     .add_component_with_name(format!("channel_sender-{i}"))
             })
             .collect_vec();
+        for (i, id) in queue_tracker_id_send.iter().enumerate() {
+            create_credit_link(
+                sim,
+                &status,
+                p_collector,
+                sender_id_to_name_mapping,
+                &mem_settings.flow_control.channel_to_chip,
+                id,
+                &format!("chip_{channel_id}.{i}"),
+            );
+        }
         let channel = ChannelMerger::new(
             LevelId::Channel(channel_id),
             dimm_to_channel_task_sender,
@@ -333,8 +545,10 @@ fn build_channel(
         // create the merger_task_worker
         let mut task_receiver = vec![];
         for i in 0..mem_settings.channel_merger_count {
-            let merger_to_sender_queue =
-                sim.create_resource(Box::new(Store::new(1)), "merger_to_sender_channel");
+            let merger_to_sender_queue = sim.create_resource(
+                Box::new(Store::new(mem_settings.queue_sizes.merger_to_sender)),
+                "merger_to_sender_channel",
+            );
             let named_sim_time = shared_status.shared_named_time.add_component_with_name(
                 "channel_sum_sender",
                 vec!["partial_sum_sender", "channel_sum_sender"],
@@ -353,8 +567,10 @@ fn build_channel(
             };
             p_collector.create_process_and_schedule(sim, channel_signal_sender, &status);
 
-            let resouce =
-                sim.create_resource(Box::new(Store::new(1)), "dispatcher_to_merger_channel");
+            let resouce = sim.create_resource(
+                Box::new(Store::new(mem_settings.queue_sizes.dispatcher_to_merger)),
+                "dispatcher_to_merger_channel",
+            );
             let named_sim_time = shared_status.shared_named_time.add_component_with_name(
                 "channel_merger_task_worker",
                 vec!["merger_task_worker", "channel_merger_task_worker"],
@@ -368,7 +584,11 @@ fn build_channel(
                 queue_id_partial_sum_in: resouce,
                 self_sender_id: dimm_to_channel_task_sender,
                 merger_status_id,
-                merger_width: mem_settings.channel_merger_size,
+                merger_width: mem_settings.channel_merger_policy.merger_width(
+                    mem_settings.channel_merger_size,
+                    i,
+                    mem_settings.channel_merger_count,
+                ),
                 named_sim_time,
                 is_bind: mem_settings.buffer_mode.is_bind_merger(),
                 queue_id_finished_signal_out: collector_to_dispatcher,
@@ -385,7 +605,7 @@ fn build_channel(
             is_binding: mem_settings.buffer_mode.is_bind_merger(),
         };
         p_collector.create_process_and_schedule(sim, merger_task_dispatcher, &status);
-        build_chip(
+        let banks = build_chip(
             mem_settings,
             sim,
             status.clone(),
@@ -398,6 +618,17 @@ fn build_channel(
             sender_id_to_name_mapping,
             queue_tracker_id_send,
         )?;
+        if let Some(region) = spill_region {
+            create_spill_port(
+                sim,
+                &status,
+                p_collector,
+                mem_settings,
+                LevelId::Channel(channel_id),
+                region,
+                &banks,
+            );
+        }
     }
     Ok(())
 }
@@ -413,8 +644,10 @@ fn build_chip(
     p_collector: &mut ProcessInfoCollector,
     sender_id_to_name_mapping: &mut BTreeMap<usize, String>,
     queue_tracker_id_recv: Vec<QueueTrackerId>,
-) -> eyre::Result<()> {
+) -> eyre::Result<Vec<(BankID, usize)>> {
     let shared_status = status.shared_status.clone();
+    // the task queue of each bank of the channel
+    let mut banks = vec![];
     // 4. add the chip
     for (chip_id, (store_id, queue_tracker_id_recv)) in chip_stores
         .into_iter()
@@ -423,44 +656,84 @@ fn build_chip(
     {
         // create the chip!
         let chip_id = (channel_id, chip_id);
+        let mem_settings = &mem_settings.for_chip(chip_id);
         let num_banks = mem_settings.banks;
         let bank_stores = (0..num_banks)
             .map(|_i| {
                 sim.create_resource(
-                    Box::new(Store::new(mem_settings.sender_store_size)),
+                    Box::new(Store::new(
+                        mem_settings
+                            .flow_control
+                            .chip_to_bank
+                            .store_size(mem_settings.sender_store_size),
+                    )),
                     "chip_to_bank",
                 )
             })
             .collect_vec();
         for (index, id) in bank_stores.iter().enumerate() {
             sender_id_to_name_mapping.insert(*id, format!("bank_{:?}.{}", &chip_id, index));
+            banks.push(((chip_id, index), *id));
         }
         let merger_status_id = shared_status
             .shared_merger_status
-            .add_component(mem_settings.chip_merger_count);
+            .add_component_with_policy(
+                format!("chip-{chip_id:?}"),
+                mem_settings.chip_merger_count,
+                mem_settings.chip_merger_policy,
+            );
+        partition_mergers(&shared_status, mem_settings, &merger_status_id);
         let sim_time_id = shared_status
             .shared_named_time
             .add_component_with_name("chip_sender", vec!["chip_task_sender", "task_sender"]);
         let buffer_status_id = shared_status
             .shared_buffer_status
-            .add_component(mem_settings.chip_buffer_lines);
+            .add_component_with_policy(
+                mem_settings.chip_buffer_lines,
+                build_buffer_policy(
+                    &shared_status,
+                    mem_settings,
+                    mem_settings.chip_buffer_policy,
+                ),
+            );
 
-        let signal_in = sim.create_resource(Box::new(Store::new(128)), "signal_chip");
-        let ready_queueid = sim.create_resource(Box::new(Store::new(128)), "ready_chip");
+        let signal_in = sim.create_resource(
+            Box::new(Store::new(mem_settings.queue_sizes.signal)),
+            "signal_chip",
+        );
+        let ready_queueid = sim.create_resource(
+            Box::new(Store::new(mem_settings.queue_sizes.ready)),
+            "ready_chip",
+        );
         let named_sim_time = shared_status.shared_named_time.add_component_with_name(
             "chip_singal_collector",
             vec!["chip_singal_collector", "signal_collector"],
         );
+        let spill_region = create_spill_region(sim, mem_settings, "spill_read_back_chip");
         let chip_signal_collector = PartialSumSignalCollector {
             queue_id_signal_in: signal_in,
             queue_id_ready_out: ready_queueid,
             buffer_status_id,
             level_id: LevelId::Chip(chip_id),
             named_sim_time,
+            spill_region,
         };
         p_collector.create_process_and_schedule(sim, chip_signal_collector, &status);
-        let collector_to_dispatcher =
-            sim.create_resource(Box::new(Store::new(1)), "collector_to_dispatcher_chip");
+        if let Some(region) = spill_region {
+            create_spill_port(
+                sim,
+                &status,
+                p_collector,
+                mem_settings,
+                LevelId::Chip(chip_id),
+                region,
+                &banks[banks.len() - num_banks..],
+            );
+        }
+        let collector_to_dispatcher = sim.create_resource(
+            Box::new(Store::new(mem_settings.queue_sizes.collector_to_dispatcher)),
+            "collector_to_dispatcher_chip",
+        );
 
         let named_sim_time = shared_status.shared_named_time.add_component_with_name(
             "CHIP_DATA_COLLECTOR",
@@ -484,6 +757,17 @@ fn build_chip(
                     .add_component_with_name(format!("chip_sender-{i}"))
             })
             .collect_vec();
+        for (i, id) in queue_tracker_id_send.iter().enumerate() {
+            create_credit_link(
+                sim,
+                &status,
+                p_collector,
+                sender_id_to_name_mapping,
+                &mem_settings.flow_control.chip_to_bank,
+                id,
+                &format!("bank_{chip_id:?}.{i}"),
+            );
+        }
         let chip = ChipMerger::new(
             LevelId::Chip(chip_id),
             store_id,
@@ -502,8 +786,10 @@ fn build_chip(
         let mut task_receiver = vec![];
         for i in 0..mem_settings.chip_merger_count {
             // build partial sum sender, signal collector and data collector
-            let merger_to_sender_queue =
-                sim.create_resource(Box::new(Store::new(1)), "merger_to_sender_chip");
+            let merger_to_sender_queue = sim.create_resource(
+                Box::new(Store::new(mem_settings.queue_sizes.merger_to_sender)),
+                "merger_to_sender_chip",
+            );
 
             let named_sim_time = shared_status.shared_named_time.add_component_with_name(
                 "chip_partial_sum_sender",
@@ -523,7 +809,10 @@ fn build_chip(
             };
             p_collector.create_process_and_schedule(sim, chip_signal_sender, &status);
 
-            let resouce = sim.create_resource(Box::new(Store::new(1)), "dispatcher_to_merger_chip");
+            let resouce = sim.create_resource(
+                Box::new(Store::new(mem_settings.queue_sizes.dispatcher_to_merger)),
+                "dispatcher_to_merger_chip",
+            );
             let named_sim_time = shared_status.shared_named_time.add_component_with_name(
                 "chip_merger_task_worker",
                 vec!["merger_task_worker", "chip_merger_task_worker"],
@@ -537,7 +826,11 @@ fn build_chip(
                 queue_id_partial_sum_in: resouce,
                 self_sender_id: store_id,
                 merger_status_id,
-                merger_width: mem_settings.chip_merger_size,
+                merger_width: mem_settings.chip_merger_policy.merger_width(
+                    mem_settings.chip_merger_size,
+                    i,
+                    mem_settings.chip_merger_count,
+                ),
                 named_sim_time,
                 is_bind: mem_settings.buffer_mode.is_bind_merger(),
                 queue_id_finished_signal_out: collector_to_dispatcher,
@@ -570,7 +863,7 @@ fn build_chip(
     }
     // start
 
-    Ok(())
+    Ok(banks)
     // what we should to output?
 }
 
@@ -587,6 +880,7 @@ fn build_bank(
     queue_tracker_id_recv: Vec<QueueTrackerId>,
 ) -> eyre::Result<()> {
     let shared_status = status.shared_status.clone();
+    let fault_map = FaultMap::new(mem_settings);
     // 5. add the bank
     for (bank_id, (store_id, queue_tracker_id_recv)) in bank_stores
         .into_iter()
@@ -595,6 +889,7 @@ fn build_bank(
     {
         // create the bank!
         let bank_id = (chip_id, bank_id);
+        let mem_settings = &mem_settings.for_bank(bank_id);
 
         let bank_pe_stores = (0..mem_settings.bank_merger_count)
             .map(|_i| {
@@ -623,14 +918,24 @@ fn build_bank(
             end_time_id,
             queue_tracker_id_recv,
         );
+        let bank = if mem_settings.bank_cache_size > 0 {
+            bank.with_cache(mem_settings.bank_cache_size, mem_settings.bank_cache_policy)
+        } else {
+            bank
+        };
+        // a failed bank is still built, it holds no rows and only receives the end of each task
+        let slowdown = fault_map.slowdown(bank_id);
+        let bank = bank.with_slowdown(slowdown);
 
         // create the process
         p_collector.create_process_and_schedule(sim, bank, &status);
 
         for (bank_pe_id, bank_pe_store_id) in bank_pe_stores.into_iter().enumerate() {
             // create the partial sum sender, signal collector and data collector
-            let merger_to_sender =
-                sim.create_resource(Box::new(Store::new(1)), "merger_to_sender_bank");
+            let merger_to_sender = sim.create_resource(
+                Box::new(Store::new(mem_settings.queue_sizes.merger_to_sender)),
+                "merger_to_sender_bank",
+            );
 
             let named_sim_time = shared_status.shared_named_time.add_component_with_name(
                 "bank_partial_sum_sender",
@@ -663,7 +968,8 @@ fn build_bank(
                 store_id,
                 comp_id,
                 end_time_id,
-            );
+            )
+            .with_slowdown(slowdown);
             p_collector.create_process_and_schedule(sim, bank_pe, &status);
         }
     }
@@ -671,13 +977,237 @@ fn build_bank(
     Ok(())
 }
 
+/// the shared status of one simulation, no jobs until `JobTable::set_jobs`
+fn create_shared_status(mem_settings: &MemSettings) -> SharedStatus {
+    SharedStatus {
+        shared_bankpe_status: Rc::new(RefCell::new(BTreeMap::new())),
+        shared_sim_time: Rc::new(SharedSimTime::new()),
+        shared_level_time: Rc::new(LevelTime::new()),
+        shared_named_time: Rc::new(SharedNamedTime::new()),
+        shared_buffer_status: Rc::new(SharedBufferStatus::default()),
+        shared_merger_status: Rc::new(SharedMergerStatus::new(
+            mem_settings.buffer_mode.is_bind_merger(),
+        )),
+        shared_end_time: Rc::new(SharedEndTime::new()),
+        queue_tracker: Rc::new(QueueTracker::new()),
+        shared_bank_cache: Default::default(),
+        shared_spill: Default::default(),
+        shared_credits: Default::default(),
+        shared_jobs: Default::default(),
+    }
+}
+
+/// the store that connects the task senders and the dimm, and its credits
+fn create_host_link(
+    mem_settings: &MemSettings,
+    sim: &mut Simulation<SpmmStatus>,
+    status: &SpmmStatus,
+    p_collector: &mut ProcessInfoCollector,
+    sender_id_to_name_mapping: &mut BTreeMap<usize, String>,
+) -> (usize, QueueTrackerId) {
+    let task_send_store = sim.create_resource(
+        Box::new(Store::new(
+            mem_settings
+                .flow_control
+                .host_to_dimm
+                .store_size(mem_settings.sender_store_size),
+        )),
+        "task_send_store",
+    );
+    sender_id_to_name_mapping.insert(task_send_store, "dimm".to_string());
+    let queue_tracker_id_send = status
+        .shared_status
+        .queue_tracker
+        .add_component_with_name("channel_sender");
+    create_credit_link(
+        sim,
+        status,
+        p_collector,
+        sender_id_to_name_mapping,
+        &mem_settings.flow_control.host_to_dimm,
+        &queue_tracker_id_send,
+        "dimm",
+    );
+    (task_send_store, queue_tracker_id_send)
+}
+
+fn schedule_task_sender<T>(
+    sim: &mut Simulation<SpmmStatus>,
+    status: &SpmmStatus,
+    p_collector: &mut ProcessInfoCollector,
+    task_sender: TaskSender<T>,
+    job: Option<JobPort>,
+) where
+    TaskSender<T>: Debug + Component + 'static,
+{
+    match job {
+        Some(port) => {
+            p_collector.create_process_and_schedule(sim, task_sender.with_job(port), status)
+        }
+        None => p_collector.create_process_and_schedule(sim, task_sender, status),
+    }
+}
+
+/// the task sender of the rows of A, `job` links it to the arbiter when several jobs share the dimm
+fn create_task_sender(
+    mem_settings: &MemSettings,
+    sim: &mut Simulation<SpmmStatus>,
+    status: &SpmmStatus,
+    p_collector: &mut ProcessInfoCollector,
+    input_matrix: TwoMatrix<i32, i32>,
+    task_send_store: usize,
+    queue_tracker_id_send: QueueTrackerId,
+    job: Option<JobPort>,
+) -> eyre::Result<()> {
+    let real_row_mapping = ResolvedRowMapping::new(mem_settings, &input_matrix.b)?;
+    let replication = ReplicationPlan::new(mem_settings, &input_matrix.a, &real_row_mapping);
+    let all_send_task = input_matrix
+        .a
+        .outer_iterator()
+        .map(|x| CsVecNodata::from(x.to_owned()))
+        .collect_vec();
+    match mem_settings.task_scheduler_mode {
+        crate::settings::TaskSchedulerMode::Sequence => {
+            let task_sender = TaskSender::new(
+                input_matrix.a,
+                input_matrix.b,
+                task_send_store,
+                mem_settings.channels,
+                mem_settings.chips,
+                mem_settings.banks,
+                real_row_mapping,
+                queue_tracker_id_send,
+                DefaultTaskScheduler::new(all_send_task),
+            )
+            .with_replication(replication);
+            schedule_task_sender(sim, status, p_collector, task_sender, job);
+        }
+        crate::settings::TaskSchedulerMode::Shuffle => {
+            let task_sender = TaskSender::<RandomTaskScheduler>::new(
+                input_matrix.a,
+                input_matrix.b,
+                task_send_store,
+                mem_settings.channels,
+                mem_settings.chips,
+                mem_settings.banks,
+                real_row_mapping,
+                queue_tracker_id_send,
+                RandomTaskScheduler::new(all_send_task, mem_settings.seed),
+            )
+            .with_replication(replication);
+            schedule_task_sender(sim, status, p_collector, task_sender, job);
+        }
+        crate::settings::TaskSchedulerMode::ChunkShuffle => {
+            let task_sender = TaskSender::new(
+                input_matrix.a,
+                input_matrix.b,
+                task_send_store,
+                mem_settings.channels,
+                mem_settings.chips,
+                mem_settings.banks,
+                real_row_mapping,
+                queue_tracker_id_send,
+                BatchShuffleScheduler::new(
+                    mem_settings.task_scheduler_chunk_size,
+                    all_send_task,
+                    mem_settings.seed,
+                ),
+            )
+            .with_replication(replication);
+            schedule_task_sender(sim, status, p_collector, task_sender, job);
+        }
+    }
+    Ok(())
+}
+
+/// the rows of A in the order of the task scheduler
+fn scheduled_tasks(
+    mem_settings: &MemSettings,
+    matrix_a: &CsMat<i32>,
+) -> Vec<(usize, CsVecNodata<usize>)> {
+    let all_send_task = matrix_a
+        .outer_iterator()
+        .map(|x| CsVecNodata::from(x.to_owned()))
+        .collect_vec();
+    match mem_settings.task_scheduler_mode {
+        crate::settings::TaskSchedulerMode::Sequence => DefaultTaskScheduler::new(all_send_task)
+            .into_iter()
+            .collect(),
+        crate::settings::TaskSchedulerMode::Shuffle => {
+            RandomTaskScheduler::new(all_send_task, mem_settings.seed)
+                .into_iter()
+                .collect()
+        }
+        crate::settings::TaskSchedulerMode::ChunkShuffle => BatchShuffleScheduler::new(
+            mem_settings.task_scheduler_chunk_size,
+            all_send_task,
+            mem_settings.seed,
+        )
+        .into_iter()
+        .collect(),
+    }
+}
+
+/// the dimm and all levels below it
+fn build_levels(
+    mem_settings: &MemSettings,
+    sim: &mut Simulation<SpmmStatus>,
+    status: &SpmmStatus,
+    task_send_store: usize,
+    final_receiver: usize,
+    p_collector: &mut ProcessInfoCollector,
+    sender_id_to_name_mapping: &mut BTreeMap<usize, String>,
+    queue_tracker_id_send: QueueTrackerId,
+) -> eyre::Result<()> {
+    let shared_level_time = &status.shared_status.shared_level_time;
+    let dimm_level_id = shared_level_time.add_level();
+    let channel_level_id = shared_level_time.add_level();
+    let chip_level_id = shared_level_time.add_level();
+    let bank_level_id = shared_level_time.add_level();
+    build_dimm(
+        mem_settings,
+        sim,
+        task_send_store,
+        status.clone(),
+        final_receiver,
+        dimm_level_id,
+        channel_level_id,
+        chip_level_id,
+        bank_level_id,
+        p_collector,
+        sender_id_to_name_mapping,
+        queue_tracker_id_send,
+    )
+}
+
+/// the results of `Simulator::run`, the idle times are rates of the total time
+#[derive(Debug, Serialize)]
+pub struct SimReport {
+    pub cycles: f64,
+    pub time_stats: Vec<(String, f64)>,
+    pub detailed_time_stats: Vec<(String, Vec<(String, f64)>)>,
+    pub end_time_stats: Vec<(String, f64)>,
+    pub bank_cache: BankCacheStats,
+    pub merger_stats: MergerStats,
+    pub spill_stats: SpillStats,
+}
+
 pub struct Simulator {}
 impl Simulator {
     /// run the simulator
     pub fn run(
         mem_settings: &MemSettings,
         input_matrix: TwoMatrix<i32, i32>,
-    ) -> Result<(f64, TimeStats, DetailedTimeStats, Vec<(String, f64)>), eyre::Report> {
+    ) -> eyre::Result<SimReport> {
+        mem_settings.validate_with_rows(input_matrix.b.rows())?;
+        Self::run_unchecked(mem_settings, input_matrix)
+    }
+
+    /// `run` without checking the settings, the settings rejected by `validate` can block the components forever
+    fn run_unchecked(
+        mem_settings: &MemSettings,
+        input_matrix: TwoMatrix<i32, i32>,
+    ) -> eyre::Result<SimReport> {
         let mut sender_id_to_name_mapping = BTreeMap::<usize, String>::new();
 
         let total_rows = input_matrix.a.rows();
@@ -688,35 +1218,9 @@ impl Simulator {
         // 1.---- the basic data
         debug!("start to run");
         let mut sim = Simulation::new();
-        // let merger_status = Rc::new(RefCell::new(FullMergerStatus::new()));
-        let bankpe_status = Rc::new(RefCell::new(BTreeMap::new()));
-        let shared_level_time = Rc::new(LevelTime::new());
-
-        let dimm_level_id = shared_level_time.add_level();
-        let channel_level_id = shared_level_time.add_level();
-        let chip_level_id = shared_level_time.add_level();
-        let bank_level_id = shared_level_time.add_level();
-
-        let shared_named_time = Rc::new(SharedNamedTime::new());
-        let shared_buffer_status = Rc::new(SharedBufferStatus::default());
-        let sim_time = Rc::new(SharedSimTime::new());
-        let shared_merger_status = Rc::new(SharedMergerStatus::new(
-            mem_settings.buffer_mode.is_bind_merger(),
-        ));
-        let shared_end_time = Rc::new(SharedEndTime::new());
-        let queue_tracker = Rc::new(QueueTracker::new());
-        let shared_status = SharedStatus {
-            shared_bankpe_status: bankpe_status,
-            shared_sim_time: sim_time,
-            shared_level_time,
-            shared_named_time,
-            shared_buffer_status,
-            shared_merger_status,
-            shared_end_time,
-            queue_tracker,
-        };
+        let shared_status = create_shared_status(mem_settings);
 
-        let status = SpmmStatus::new(SpmmStatusEnum::Continue, shared_status.clone());
+        let status = SpmmStatus::new(SpmmStatusEnum::Continue, shared_status);
 
         let final_receiver_resouce = sim.create_resource(Box::new(Store::new(1)), "final_receiver");
         let all_received = Rc::new(RefCell::new(Vec::new()));
@@ -729,80 +1233,30 @@ impl Simulator {
 
         p_collector.create_process_and_schedule(&mut sim, final_rev, &status);
         // this store connect the task sender and the Dimm
-        let task_send_store = sim.create_resource(
-            Box::new(Store::new(mem_settings.sender_store_size)),
-            "task_send_store",
+        let (task_send_store, queue_tracker_id_send) = create_host_link(
+            mem_settings,
+            &mut sim,
+            &status,
+            &mut p_collector,
+            &mut sender_id_to_name_mapping,
         );
-        sender_id_to_name_mapping.insert(task_send_store, "dimm".to_string());
-        let queue_tracker_id_send = shared_status
-            .queue_tracker
-            .add_component_with_name("channel_sender");
-        let real_row_mapping = mem_settings
-            .row_mapping
-            .to_real_row_mapping(mem_settings.interleaved_chunk);
-        let all_send_task = input_matrix
-            .a
-            .outer_iterator()
-            .map(|x| CsVecNodata::from(x.to_owned()))
-            .collect_vec();
-        match mem_settings.task_scheduler_mode {
-            crate::settings::TaskSchedulerMode::Sequence => {
-                let task_sender = TaskSender::new(
-                    input_matrix.a,
-                    input_matrix.b,
-                    task_send_store,
-                    mem_settings.channels,
-                    mem_settings.chips,
-                    mem_settings.banks,
-                    real_row_mapping,
-                    queue_tracker_id_send,
-                    DefaultTaskScheduler::new(all_send_task),
-                );
-                p_collector.create_process_and_schedule(&mut sim, task_sender, &status);
-            }
-            crate::settings::TaskSchedulerMode::Shuffle => {
-                let task_sender = TaskSender::<RandomTaskScheduler>::new(
-                    input_matrix.a,
-                    input_matrix.b,
-                    task_send_store,
-                    mem_settings.channels,
-                    mem_settings.chips,
-                    mem_settings.banks,
-                    real_row_mapping,
-                    queue_tracker_id_send,
-                    RandomTaskScheduler::new(all_send_task),
-                );
-                p_collector.create_process_and_schedule(&mut sim, task_sender, &status);
-            }
-            crate::settings::TaskSchedulerMode::ChunkShuffle => {
-                let task_sender = TaskSender::new(
-                    input_matrix.a,
-                    input_matrix.b,
-                    task_send_store,
-                    mem_settings.channels,
-                    mem_settings.chips,
-                    mem_settings.banks,
-                    real_row_mapping,
-                    queue_tracker_id_send,
-                    BatchShuffleScheduler::new(
-                        mem_settings.task_scheduler_chunk_size,
-                        all_send_task,
-                    ),
-                );
-                p_collector.create_process_and_schedule(&mut sim, task_sender, &status);
-            }
-        }
+        create_task_sender(
+            mem_settings,
+            &mut sim,
+            &status,
+            &mut p_collector,
+            input_matrix,
+            task_send_store,
+            queue_tracker_id_send,
+            None,
+        )?;
 
-        build_dimm(
+        build_levels(
             mem_settings,
             &mut sim,
+            &status,
             task_send_store,
-            status.clone(),
             final_receiver_resouce,
-            dimm_level_id,
-            channel_level_id,
-            chip_level_id,
-            bank_level_id,
             &mut p_collector,
             &mut sender_id_to_name_mapping,
             queue_tracker_id_send,
@@ -823,12 +1277,22 @@ impl Simulator {
             all_received.borrow().iter().max(),
         );
         info!("original_matrix: {}", total_rows);
-        if all_received.borrow().len() != total_rows {
+        if let Some(report) = DeadlockReport::detect(
+            time,
+            p_collector.blocked_processes(),
+            &sender_id_to_name_mapping,
+            status.shared_status.shared_buffer_status.pending_tasks(),
+            status.shared_status.shared_merger_status.pending_tasks(),
+            total_rows,
+            &all_received.borrow(),
+        ) {
             error!(
-                "the received data is not correct,received: {},should be:{}",
+                "the received data is not correct,received: {},should be:{}\n{}",
                 all_received.borrow().len(),
-                total_rows
+                total_rows,
+                report
             );
+            return Err(report.into());
         }
         // output the mapping of sender id:
         info!(
@@ -840,18 +1304,326 @@ impl Simulator {
             .shared_named_time
             .get_detailed_stats(time);
         let end_time_stats = status.shared_status.shared_end_time.get_stats(time);
-        Ok((time, time_stats, detailed_time_stats, end_time_stats))
+        let bank_cache_stats = status.shared_status.shared_bank_cache.get_stats();
+        let merger_stats = status.shared_status.shared_merger_status.get_stats(time);
+        let spill_stats = status.shared_status.shared_spill.get_stats();
+        Ok(SimReport {
+            cycles: time,
+            time_stats: time_stats.to_rate(),
+            detailed_time_stats: detailed_time_stats.to_rate(),
+            end_time_stats,
+            bank_cache: bank_cache_stats,
+            merger_stats,
+            spill_stats,
+        })
+    }
+
+    /// run a stream of batches of A against `matrix_b`.
+    /// - B is placed in the banks once before the first batch, the placement takes no cycles
+    /// - each batch waits for its arrival and the batches before it, the rows of a batch are ordered by the task scheduler
+    pub fn run_stream(
+        mem_settings: &MemSettings,
+        matrix_b: CsMat<i32>,
+        batches: Vec<Batch>,
+    ) -> eyre::Result<StreamReport> {
+        mem_settings.validate_with_rows(matrix_b.rows())?;
+        for (index, batch) in batches.iter().enumerate() {
+            if batch.a.rows() == 0 || batch.a.cols() != matrix_b.rows() {
+                eyre::bail!(
+                    "the batch {index} has the shape {:?}, it should have rows and {} columns",
+                    batch.a.shape(),
+                    matrix_b.rows()
+                );
+            }
+        }
+        let matrix_a = sprs::vstack(&batches.iter().map(|batch| batch.a.view()).collect_vec());
+        let input_matrix = TwoMatrix::new(matrix_a, matrix_b);
+        let total_rows = input_matrix.a.rows();
+        let mut sender_id_to_name_mapping = BTreeMap::<usize, String>::new();
+        let mut p_collector = ProcessInfoCollector::new(true);
+        debug!("start to run {} batches", batches.len());
+        let mut sim = Simulation::new();
+        let status = SpmmStatus::new(SpmmStatusEnum::Continue, create_shared_status(mem_settings));
+        let shared_end_time = status.shared_status.shared_end_time.clone();
+
+        let final_receiver_resouce = sim.create_resource(Box::new(Store::new(1)), "final_receiver");
+        let all_received = Rc::new(RefCell::new(Vec::new()));
+        let row_starts = batches
+            .iter()
+            .scan(0, |start, batch| {
+                let this = *start;
+                *start += batch.a.rows();
+                Some(this)
+            })
+            .collect_vec();
+        let end_time_ids = (0..batches.len())
+            .map(|index| shared_end_time.add_component_with_name(format!("batch_{index}")))
+            .collect_vec();
+        let final_rev = FinalReceiver::new(
+            final_receiver_resouce,
+            true,
+            &input_matrix,
+            all_received.clone(),
+        )
+        .with_batches(BatchEndTimes {
+            row_starts: row_starts.clone(),
+            end_time_ids: end_time_ids.clone(),
+        });
+        p_collector.create_process_and_schedule(&mut sim, final_rev, &status);
+
+        let (task_send_store, queue_tracker_id_send) = create_host_link(
+            mem_settings,
+            &mut sim,
+            &status,
+            &mut p_collector,
+            &mut sender_id_to_name_mapping,
+        );
+        // the rows of each batch keep their place in the stacked A
+        let mut tasks = vec![];
+        let mut arrivals = vec![];
+        for ((index, batch), row_start) in batches.iter().enumerate().zip(&row_starts) {
+            tasks.extend(
+                scheduled_tasks(mem_settings, &batch.a)
+                    .into_iter()
+                    .map(|(row, vector)| (row + row_start, vector)),
+            );
+            arrivals.push(BatchArrival {
+                tasks: batch.a.rows(),
+                arrival: batch.arrival,
+                start_time_id: shared_end_time
+                    .add_component_with_name(format!("batch_{index}_start")),
+            });
+        }
+        let real_row_mapping = ResolvedRowMapping::new(mem_settings, &input_matrix.b)?;
+        let replication = ReplicationPlan::new(mem_settings, &input_matrix.a, &real_row_mapping);
+        let task_sender = TaskSender::new(
+            input_matrix.a,
+            input_matrix.b,
+            task_send_store,
+            mem_settings.channels,
+            mem_settings.chips,
+            mem_settings.banks,
+            real_row_mapping,
+            queue_tracker_id_send,
+            tasks,
+        )
+        .with_replication(replication)
+        .with_batches(arrivals.clone());
+        p_collector.create_process_and_schedule(&mut sim, task_sender, &status);
+
+        build_levels(
+            mem_settings,
+            &mut sim,
+            &status,
+            task_send_store,
+            final_receiver_resouce,
+            &mut p_collector,
+            &mut sender_id_to_name_mapping,
+            queue_tracker_id_send,
+        )?;
+
+        let sim = sim.run(EndCondition::NoEvents);
+        let time = sim.time();
+        if let Some(report) = DeadlockReport::detect(
+            time,
+            p_collector.blocked_processes(),
+            &sender_id_to_name_mapping,
+            status.shared_status.shared_buffer_status.pending_tasks(),
+            status.shared_status.shared_merger_status.pending_tasks(),
+            total_rows,
+            &all_received.borrow(),
+        ) {
+            error!(
+                "the received data is not correct,received: {},should be:{}\n{}",
+                all_received.borrow().len(),
+                total_rows,
+                report
+            );
+            return Err(report.into());
+        }
+        let reports = batches
+            .iter()
+            .zip(arrivals)
+            .zip(end_time_ids)
+            .map(|((batch, arrival), end_time_id)| {
+                let finish = shared_end_time.get_end_time(end_time_id);
+                BatchReport {
+                    rows: batch.a.rows(),
+                    nnz: batch.a.nnz(),
+                    arrival: batch.arrival,
+                    start: shared_end_time.get_end_time(arrival.start_time_id),
+                    finish,
+                    latency: finish - batch.arrival,
+                }
+            })
+            .collect();
+        Ok(StreamReport::new(time, reports))
+    }
+
+    /// run several jobs that share the dimm, then each job alone to get its slowdown.
+    /// - the matrices B of the jobs are stored one after another, each one with its own row mapping
+    /// - the arbiter gives the host link to one task at a time, see `JobArbitration`
+    pub fn run_jobs(mem_settings: &MemSettings, jobs: Vec<Job>) -> eyre::Result<JobsReport> {
+        mem_settings.validate_with_jobs(jobs.len())?;
+        for job in &jobs {
+            mem_settings
+                .validate_with_rows(job.matrix.b.rows())
+                .wrap_err_with(|| format!("job {}", job.name))?;
+        }
+        let mut sender_id_to_name_mapping = BTreeMap::<usize, String>::new();
+        let mut p_collector = ProcessInfoCollector::new(true);
+        debug!("start to run {} jobs", jobs.len());
+        let mut sim = Simulation::new();
+        let shared_status = create_shared_status(mem_settings);
+        shared_status.shared_jobs.set_jobs(jobs.len());
+        let status = SpmmStatus::new(SpmmStatusEnum::Continue, shared_status);
+
+        // every job has its own final receiver behind the router
+        let final_receiver_resouce = sim.create_resource(Box::new(Store::new(1)), "final_receiver");
+        let mut job_receivers = vec![];
+        let mut job_queues = vec![];
+        for job in &jobs {
+            let queue = sim.create_resource(Box::new(Store::new(1)), "final_receiver_job");
+            sender_id_to_name_mapping.insert(queue, format!("final_receiver_{}", job.name));
+            let all_received = Rc::new(RefCell::new(Vec::new()));
+            let end_time_id = status
+                .shared_status
+                .shared_end_time
+                .add_component_with_name(format!("job_{}", job.name));
+            let final_rev = FinalReceiver::new(queue, true, &job.matrix, all_received.clone())
+                .with_end_time(end_time_id);
+            p_collector.create_process_and_schedule(&mut sim, final_rev, &status);
+            job_queues.push(queue);
+            job_receivers.push((all_received, end_time_id));
+        }
+        let router = FinalRouter {
+            queue_id_in: final_receiver_resouce,
+            queue_id_jobs: job_queues,
+        };
+        p_collector.create_process_and_schedule(&mut sim, router, &status);
+
+        let (task_send_store, queue_tracker_id_send) = create_host_link(
+            mem_settings,
+            &mut sim,
+            &status,
+            &mut p_collector,
+            &mut sender_id_to_name_mapping,
+        );
+        let queue_id_arbiter =
+            sim.create_resource(Box::new(Store::new(2 * jobs.len())), "job_arbiter");
+        sender_id_to_name_mapping.insert(queue_id_arbiter, "job_arbiter".to_string());
+        let queue_id_grants = jobs
+            .iter()
+            .map(|job| {
+                let grant = sim.create_resource(Box::new(Store::new(1)), "job_grant");
+                sender_id_to_name_mapping.insert(grant, format!("job_grant_{}", job.name));
+                grant
+            })
+            .collect_vec();
+        let arbiter = JobArbiter {
+            queue_id_in: queue_id_arbiter,
+            queue_id_grants: queue_id_grants.clone(),
+            policy: mem_settings.job_arbitration,
+        };
+        p_collector.create_process_and_schedule(&mut sim, arbiter, &status);
+        let (mut row_offset, mut nnz_offset) = (0, 0);
+        for (index, job) in jobs.iter().enumerate() {
+            let port = JobPort {
+                job: index,
+                queue_id_arbiter,
+                queue_id_grant: queue_id_grants[index],
+                arrival: job.arrival,
+                row_offset,
+                nnz_offset,
+            };
+            row_offset += job.matrix.b.rows();
+            nnz_offset += job.matrix.b.nnz();
+            create_task_sender(
+                mem_settings,
+                &mut sim,
+                &status,
+                &mut p_collector,
+                TwoMatrix::new(job.matrix.a.clone(), job.matrix.b.clone()),
+                task_send_store,
+                queue_tracker_id_send,
+                Some(port),
+            )?;
+        }
+
+        build_levels(
+            mem_settings,
+            &mut sim,
+            &status,
+            task_send_store,
+            final_receiver_resouce,
+            &mut p_collector,
+            &mut sender_id_to_name_mapping,
+            queue_tracker_id_send,
+        )?;
+
+        let sim = sim.run(EndCondition::NoEvents);
+        let time = sim.time();
+        for (job, (all_received, _)) in jobs.iter().zip(&job_receivers) {
+            if let Some(report) = DeadlockReport::detect(
+                time,
+                p_collector.blocked_processes(),
+                &sender_id_to_name_mapping,
+                status.shared_status.shared_buffer_status.pending_tasks(),
+                status.shared_status.shared_merger_status.pending_tasks(),
+                job.matrix.a.rows(),
+                &all_received.borrow(),
+            ) {
+                error!("the job {} is not finished\n{}", job.name, report);
+                return Err(eyre::Report::from(report).wrap_err(format!("job {}", job.name)));
+            }
+        }
+
+        let mut reports = vec![];
+        for (job, (_, end_time_id)) in jobs.into_iter().zip(job_receivers) {
+            let finish = status
+                .shared_status
+                .shared_end_time
+                .get_end_time(end_time_id);
+            let tasks = job.matrix.a.rows();
+            let alone_cycles = Self::run(mem_settings, job.matrix)
+                .wrap_err_with(|| format!("job {} alone", job.name))?
+                .cycles;
+            let completion_cycles = finish - job.arrival;
+            reports.push(JobReport {
+                name: job.name,
+                tasks,
+                arrival: job.arrival,
+                finish,
+                completion_cycles,
+                alone_cycles,
+                slowdown: completion_cycles / alone_cycles,
+            });
+        }
+        Ok(JobsReport {
+            cycles: time,
+            arbitration: mem_settings.job_arbitration,
+            sharing: mem_settings.job_sharing,
+            jobs: reports,
+        })
     }
 }
 
 #[cfg(test)]
 mod test {
 
-    use sprs::CsMat;
-
     use crate::{
+        fault::{Faults, SlowChip},
         init_logger,
-        settings::{BufferMode, RowMapping, TaskSchedulerMode},
+        settings::{BufferMode, InstanceOverride, RowMapping, TaskSchedulerMode},
+        sim::{
+            bank_cache::CachePolicy,
+            credit::{CreditLink, FlowControl},
+            job::{JobArbitration, JobSharing},
+            merger_status::MergerAllocation,
+            spill::Spill,
+            stream::split_rows,
+        },
+        utils::fixtures::{a_times_at, bfwa62, simulate},
     };
 
     use super::*;
@@ -889,10 +1661,317 @@ mod test {
             dimm_buffer_lines: 2,
             channel_buffer_lines: 2,
             chip_buffer_lines: 2,
+            dimm_buffer_policy: Default::default(),
+            channel_buffer_policy: Default::default(),
+            chip_buffer_policy: Default::default(),
+            dimm_merger_policy: Default::default(),
+            channel_merger_policy: Default::default(),
+            chip_merger_policy: Default::default(),
+            spill: Default::default(),
+            flow_control: Default::default(),
+            queue_sizes: Default::default(),
             buffer_mode: BufferMode::Standalone,
             task_scheduler_mode: TaskSchedulerMode::Shuffle,
             task_scheduler_chunk_size: 32,
+            seed: 0,
+            replication: Default::default(),
+            faults: Default::default(),
+            overrides: vec![],
+            job_arbitration: Default::default(),
+            job_sharing: Default::default(),
+            bank_cache_size: 0,
+            bank_cache_policy: Default::default(),
         };
         Simulator::run(&mem_settings, two_matrix).unwrap();
     }
+
+    #[test]
+    fn deadlock_test() {
+        let csr: CsMat<i32> = sprs::io::read_matrix_market("mtx/test.mtx")
+            .unwrap()
+            .to_csr();
+        let rows = csr.rows();
+        let two_matrix = TwoMatrix::new(csr.clone(), csr.transpose_view().to_csr());
+        // the host link can not hold any task, the task sender blocks on its first push
+        let mem_settings = MemSettings {
+            sender_store_size: 0,
+            ..Default::default()
+        };
+        assert!(mem_settings.validate().is_err());
+        let error = Simulator::run_unchecked(&mem_settings, two_matrix).unwrap_err();
+        let report = error.downcast_ref::<DeadlockReport>().unwrap();
+        assert_eq!(report.undelivered_rows, (0..rows).collect_vec());
+        assert!(report
+            .blocked_processes
+            .iter()
+            .any(|process| process.is_push));
+    }
+
+    #[test]
+    fn bank_cache_test() {
+        let run = |bank_cache_size| {
+            simulate(&MemSettings {
+                row_mapping: RowMapping::Chunk,
+                bank_cache_size,
+                bank_cache_policy: CachePolicy::Lru,
+                ..Default::default()
+            })
+            .bank_cache
+        };
+        let disabled = run(0);
+        assert_eq!(disabled.hits + disabled.misses, 0);
+        // every task reads one row of B, and each row of B is used by several rows of A
+        let enabled = run(4096);
+        assert_eq!(enabled.hits + enabled.misses, bfwa62().nnz());
+        assert!(enabled.hits > 0);
+        assert!(enabled.saved_read_time > 0.);
+    }
+
+    #[test]
+    fn merger_policy_test() {
+        for policy in [
+            MergerAllocation::FirstIdle,
+            MergerAllocation::RoundRobin,
+            MergerAllocation::LeastRecentlyUsed,
+            MergerAllocation::SizeAware {
+                wide: 1,
+                threshold: 2,
+            },
+            MergerAllocation::Reserve(2),
+        ] {
+            let stats = simulate(&MemSettings {
+                dimm_merger_count: 4,
+                channel_merger_count: 4,
+                chip_merger_count: 4,
+                dimm_merger_policy: policy,
+                channel_merger_policy: policy,
+                chip_merger_policy: policy,
+                ..Default::default()
+            })
+            .merger_stats;
+            let name = format!("{policy:?}");
+            assert!(stats.groups.values().all(|group| group.policy == name));
+            let utilization = stats.policies[&name];
+            assert!(utilization > 0. && utilization <= 1.);
+            if policy.is_round_robin() {
+                assert!(stats.groups["dimm"].allocations.iter().all(|&n| n > 0));
+            }
+        }
+    }
+    #[test]
+    fn override_test() {
+        let stats = simulate(&MemSettings {
+            overrides: vec![
+                // chip 0 of every channel, then the channel 1 and its chips
+                InstanceOverride {
+                    chip: Some(0),
+                    chip_merger_count: Some(4),
+                    chip_merger_size: Some(8),
+                    chip_merger_policy: Some(MergerAllocation::RoundRobin),
+                    ..Default::default()
+                },
+                InstanceOverride {
+                    channel: Some(1),
+                    channel_merger_count: Some(3),
+                    chip_merger_count: Some(1),
+                    bank_merger_count: Some(1),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        })
+        .merger_stats;
+        let mergers = |group: &str| stats.groups[group].allocations.len();
+        assert_eq!(mergers("chip-(0, 0)"), 4);
+        assert_eq!(mergers("chip-(0, 1)"), 2);
+        // the later override wins
+        assert_eq!(mergers("chip-(1, 0)"), 1);
+        assert_eq!(mergers("chip-(1, 1)"), 1);
+        assert_eq!(mergers("channel-0"), 2);
+        assert_eq!(mergers("channel-1"), 3);
+        assert_eq!(stats.groups["chip-(0, 0)"].policy, "RoundRobin");
+        assert_eq!(stats.groups["chip-(0, 1)"].policy, "FirstIdle");
+    }
+
+    #[test]
+    fn spill_test() {
+        let run = |region_lines| {
+            simulate(&MemSettings {
+                spill: Spill {
+                    region_lines,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .spill_stats
+        };
+        assert_eq!(run(0).spills, 0);
+        // the buffers have only 2 lines, the tasks from different banks are refused
+        let stats = run(4);
+        assert!(stats.spills > 0);
+        assert!(stats.buffers.values().all(|c| c.max_lines <= 4));
+        // every spilled partial sum is written and read back, one array row each at least
+        assert!(stats.extra_cycles >= stats.spills as f64 * 2. * 16.);
+    }
+
+    #[test]
+    fn spill_contention_test() {
+        let run = |region_lines| {
+            simulate(&MemSettings {
+                spill: Spill {
+                    region_lines,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+        };
+        // the cycles of all banks in a named time, from the rate of each bank
+        let bank_cycles = |report: &SimReport, name: &str| -> f64 {
+            report
+                .detailed_time_stats
+                .iter()
+                .find(|(stat, _)| stat == name)
+                .map_or(0., |(_, banks)| {
+                    banks.iter().map(|(_, rate)| rate * report.cycles).sum()
+                })
+        };
+        let (disabled, enabled) = (run(0), run(4));
+        assert_eq!(bank_cycles(&disabled, "bank_reorder:spill_access"), 0.);
+        // the writes and reads are served by the banks, one array row each at least
+        let spills = enabled.spill_stats.spills as f64;
+        assert!(bank_cycles(&enabled, "bank_reorder:spill_access") > spills * 2. * 16. - 1.);
+        // a spill access closes the row of B, so the next read of B opens it again
+        assert!(
+            bank_cycles(&enabled, "bank_reorder:read_row_buffer")
+                > bank_cycles(&disabled, "bank_reorder:read_row_buffer")
+        );
+    }
+
+    #[test]
+    fn fault_test() {
+        let run = |faults| {
+            simulate(&MemSettings {
+                faults,
+                ..Default::default()
+            })
+            .cycles
+        };
+        let healthy = run(Faults::default());
+        // half of the banks hold all the rows
+        let failed = run(Faults {
+            failed_chips: vec![(0, 0), (1, 1)],
+            ..Default::default()
+        });
+        let slow = run(Faults {
+            slow_chips: vec![SlowChip {
+                channel: 0,
+                chip: 0,
+                slowdown: 4.,
+            }],
+            ..Default::default()
+        });
+        assert!(failed > healthy);
+        assert!(slow > healthy);
+    }
+
+    #[test]
+    fn credit_test() {
+        let run = |return_latency| {
+            let link = CreditLink {
+                credits: 1,
+                return_latency,
+            };
+            simulate(&MemSettings {
+                flow_control: FlowControl {
+                    host_to_dimm: link,
+                    dimm_to_channel: link,
+                    channel_to_chip: link,
+                    chip_to_bank: link,
+                },
+                ..Default::default()
+            })
+            .cycles
+        };
+        // with one credit, a bank gets at most one task every 100 cycles
+        let fast = run(0.);
+        let slow = run(100.);
+        assert!(slow > fast);
+        assert!(slow >= 100. * (bfwa62().nnz() / 8) as f64);
+    }
+
+    #[test]
+    fn jobs_test() {
+        let csr = bfwa62();
+        let job = |name: &str, arrival| Job {
+            name: name.to_string(),
+            matrix: a_times_at(&csr),
+            arrival,
+        };
+        for (job_arbitration, job_sharing) in [
+            (JobArbitration::Fifo, JobSharing::Shared),
+            (JobArbitration::RoundRobin, JobSharing::Partitioned),
+            (JobArbitration::Priority, JobSharing::Shared),
+        ] {
+            let mem_settings = MemSettings {
+                dimm_buffer_lines: 4,
+                channel_buffer_lines: 4,
+                chip_buffer_lines: 4,
+                job_arbitration,
+                job_sharing,
+                ..Default::default()
+            };
+            let report =
+                Simulator::run_jobs(&mem_settings, vec![job("a", 0.), job("b", 100.)]).unwrap();
+            assert_eq!(report.jobs.len(), 2);
+            for job in &report.jobs {
+                assert_eq!(job.tasks, csr.rows());
+                assert!(job.finish > job.arrival && job.finish <= report.cycles);
+            }
+            // the jobs wait for each other at the host link
+            assert!(report.jobs.iter().any(|job| job.slowdown > 1.));
+            // the same jobs alone take the same time
+            assert_eq!(report.jobs[0].alone_cycles, report.jobs[1].alone_cycles);
+        }
+        // the mergers can not be split between more jobs than mergers
+        let mem_settings = MemSettings {
+            job_sharing: JobSharing::Partitioned,
+            ..Default::default()
+        };
+        let jobs = (0..3).map(|i| job(&i.to_string(), 0.)).collect();
+        assert!(Simulator::run_jobs(&mem_settings, jobs).is_err());
+    }
+
+    #[test]
+    fn stream_test() {
+        let csr = bfwa62();
+        let run = |interval: f64| {
+            let batches = split_rows(&csr, 16)
+                .into_iter()
+                .enumerate()
+                .map(|(index, a)| Batch {
+                    a,
+                    arrival: index as f64 * interval,
+                })
+                .collect();
+            Simulator::run_stream(
+                &MemSettings::default(),
+                csr.transpose_view().to_csr(),
+                batches,
+            )
+            .unwrap()
+        };
+        // all batches arrive at once, the later ones wait for the earlier ones
+        let burst = run(0.);
+        assert_eq!(burst.batches.len(), 4);
+        assert_eq!(burst.rows, csr.rows());
+        assert!(burst.batches.iter().all(|batch| batch.finish > batch.start));
+        assert!(burst.batches[3].latency > burst.batches[0].latency);
+        // one batch at a time, none of them waits
+        let spaced = run(burst.cycles);
+        for batch in &spaced.batches {
+            assert_eq!(batch.start, batch.arrival);
+            assert!(batch.latency < burst.batches[3].latency);
+        }
+        assert!(spaced.throughput < burst.throughput);
+    }
 }
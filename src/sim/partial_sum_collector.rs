@@ -4,9 +4,8 @@ use std::collections::BTreeMap;
 
 use tracing::debug;
 
-use crate::{
-    csv_nodata::CsVecNodata,
-    sim::types::{PushFullSumType, PushPartialSumType, ReadyQueueIdType, StateWithSharedStatus},
+use crate::sim::types::{
+    PushFullSumType, PushPartialSumType, ReadyQueueIdType, SharedRow, StateWithSharedStatus,
 };
 
 use super::{
@@ -41,7 +40,7 @@ impl Component for PartialSumCollector {
         // because in bind mode, the merger worker will delete the buffer
         let function = |co: Co<SpmmStatus, SpmmContex>| async move {
             // need a struct to store current partial sum
-            let mut current_partial_sum = BTreeMap::<usize, Vec<CsVecNodata<usize>>>::new();
+            let mut current_partial_sum = BTreeMap::<usize, Vec<SharedRow>>::new();
 
             let mut current_time = 0.;
             loop {
@@ -74,6 +73,7 @@ impl Component for PartialSumCollector {
                     target_row,
                     queue_id,
                     is_finished,
+                    spill_to,
                 } = status.into_push_ready_queue_id().unwrap().1;
                 debug!(
                     "PartialSumCollector-{:?}: receive ready queue id target: {target_row} queue_id: {:?}",
@@ -99,12 +99,30 @@ impl Component for PartialSumCollector {
                     "get_data",
                     _gap,
                 );
+                let partial_sum = status.into_push_partial_task().unwrap().1;
+                if let Some(spill_to) = spill_to {
+                    // the buffer is full, hand the partial sum back to the signal collector to spill it
+                    let context = co
+                        .yield_(original_status.clone_with_state(
+                            super::SpmmStatusEnum::PushPartialTask(spill_to, partial_sum),
+                        ))
+                        .await;
+                    let (time, _status) = context.into_inner();
+                    let gap = time - current_time;
+                    current_time = time;
+                    shared_status.shared_named_time.add_idle_time(
+                        &self.named_sim_time,
+                        "push_spill_data",
+                        gap,
+                    );
+                    continue;
+                }
                 let PushPartialSumType {
                     task_id,
                     target_row,
                     sender_id: _,
                     target_result,
-                } = status.into_push_partial_task().unwrap().1;
+                } = partial_sum;
                 assert_eq!(target_row, target_row,"the signal queue target id is not equal to the data id the queue_id is:{queue_id}, check is the queue is poped by other first??");
                 debug!(
                     "PartialSumCollector-{:?}: receive partial :{target_row} last:{is_finished} from sum id: {:?}",
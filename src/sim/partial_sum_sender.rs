@@ -146,14 +146,14 @@ impl Component for PartialSumSender {
                     "PartialSumSender-{:?}-{}: target_id: {}, send data to id: {:?} and release the merger",
                     self.level_id, self.id, target_row, self.queue_id_partial_sum_out
                 );
+                let (time, _status) = context.into_inner();
                 shared_status.shared_merger_status.release_merger(
                     self.merger_status_id,
                     self.id,
                     task_id,
                     self.is_binding,
+                    time,
                 );
-                //
-                let (time, _status) = context.into_inner();
                 let _gap = time - current_time;
                 current_time = time;
                 shared_status.shared_named_time.add_idle_time(
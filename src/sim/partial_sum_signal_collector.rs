@@ -2,14 +2,18 @@
 //!
 //!
 
-use std::{cmp::Reverse, collections::BinaryHeap};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+};
 
-use crate::sim::types::{ReadyQueueIdType, StateWithSharedStatus};
+use crate::sim::types::{PushPartialSumType, ReadyQueueIdType, StateWithSharedStatus};
 
 use super::{
     buffer_status::BufferStatusId,
     component::Component,
     sim_time::NamedTimeId,
+    spill::{SpillAccess, SpillRegion},
     types::{PartialSignalType, SpmmContex, SpmmGenerator},
     LevelId, SpmmStatus, SpmmStatusEnum,
 };
@@ -25,6 +29,8 @@ pub struct PartialSumSignalCollector {
     pub buffer_status_id: BufferStatusId,
 
     pub named_sim_time: NamedTimeId,
+    /// when the buffer is full, the partial sums are written into this region instead of blocking the lower pe
+    pub spill_region: Option<SpillRegion>,
 }
 
 impl Component for PartialSumSignalCollector {
@@ -33,6 +39,10 @@ impl Component for PartialSumSignalCollector {
             let mut current_time = 0.;
             // currently cannot receive the data, store the signal
             let mut temp_signal_queue: BinaryHeap<Reverse<PartialSignalType>> = Default::default();
+            // the signals whose partial sums are in the spill region, by (task_id, sender_id)
+            let mut spilled: BTreeMap<(usize, usize), (PartialSignalType, PushPartialSumType)> =
+                Default::default();
+            let buffer_name = format!("{:?}", self.level_id);
 
             loop {
                 // first get the signal
@@ -97,6 +107,7 @@ impl Component for PartialSumSignalCollector {
                                             target_row,
                                             queue_id,
                                             is_finished,
+                                            spill_to: None,
                                         },
                                     ),
                                 ))
@@ -114,6 +125,97 @@ impl Component for PartialSumSignalCollector {
                                 "send_ready_queue_id",
                                 gap,
                             );
+                        } else if let Some(region) = self
+                            .spill_region
+                            .filter(|region| spilled.len() < region.spill.region_lines)
+                        {
+                            // cannot receive now, write the partial sum into the spill region so the lower pe can go on.
+                            // the queue of the sender may still hold the partial sums of the signals sent before,
+                            // so the data collector pops it in order and moves this one to the spill queue
+                            debug!(
+                                "PartialSumSignalCollector-{:?}: receive PushSignal:{:?} but cannot send now, spill it",
+                                self.level_id, target_row
+                            );
+                            let context = co
+                                .yield_(original_status.clone_with_state(
+                                    SpmmStatusEnum::PushReadyQueueId(
+                                        self.queue_id_ready_out,
+                                        ReadyQueueIdType {
+                                            task_id,
+                                            target_row,
+                                            queue_id,
+                                            is_finished: false,
+                                            spill_to: Some(region.queue_id_write),
+                                        },
+                                    ),
+                                ))
+                                .await;
+                            let (time, _status) = context.into_inner();
+                            let gap = time - current_time;
+                            current_time = time;
+                            shared_status.shared_named_time.add_idle_time(
+                                &self.named_sim_time,
+                                "send_spill_queue_id",
+                                gap,
+                            );
+                            let context = co
+                                .yield_(
+                                    original_status.clone_with_state(SpmmStatusEnum::Pop(
+                                        region.queue_id_write,
+                                    )),
+                                )
+                                .await;
+                            let (time, status) = context.into_inner();
+                            let gap = time - current_time;
+                            current_time = time;
+                            let StateWithSharedStatus {
+                                status,
+                                shared_status,
+                            } = status.into_inner();
+                            shared_status.shared_named_time.add_idle_time(
+                                &self.named_sim_time,
+                                "get_spill_data",
+                                gap,
+                            );
+                            let data = status.into_push_partial_task().unwrap().1;
+                            assert_eq!(
+                                (data.task_id, data.sender_id),
+                                (task_id, sender_id),
+                                "PartialSumSignalCollector-{:?}: the spilled data is not the one of the signal",
+                                self.level_id
+                            );
+                            // the spill port writes it into the banks, the line is taken from now on
+                            let context = co
+                                .yield_(original_status.clone_with_state(
+                                    SpmmStatusEnum::PushSpillAccess(
+                                        region.queue_id_access,
+                                        SpillAccess::Write(data.clone()),
+                                    ),
+                                ))
+                                .await;
+                            let (time, _status) = context.into_inner();
+                            let gap = time - current_time;
+                            current_time = time;
+                            shared_status.shared_named_time.add_idle_time(
+                                &self.named_sim_time,
+                                "push_spill_write",
+                                gap,
+                            );
+                            spilled.insert(
+                                (task_id, sender_id),
+                                (
+                                    PartialSignalType {
+                                        task_id,
+                                        target_row,
+                                        sender_id,
+                                        queue_id,
+                                    },
+                                    data,
+                                ),
+                            );
+                            shared_status
+                                .shared_spill
+                                .add_spill(&buffer_name, spilled.len());
                         } else {
                             // cannot receive now, store it and resume it later
                             debug!("PartialSumSignalCollector-{:?}: receive PushSignal:{:?} but cannot send now",self.level_id, target_row);
@@ -136,15 +238,33 @@ impl Component for PartialSumSignalCollector {
                             "PartialSumSignalCollector-{:?}: receive PushBufferPopSignal,current_queue:{:?},start to test",
                             self.level_id,temp_signal_queue
                         );
-                        // a buffer entry is popped, resume the signal
+                        // a buffer entry is popped, resume the signal, the oldest task first
 
-                        while let Some(Reverse(PartialSignalType {
-                            task_id,
-                            target_row,
-                            sender_id,
-                            queue_id,
-                        })) = temp_signal_queue.pop()
-                        {
+                        loop {
+                            let oldest_spilled = spilled.keys().next().cloned();
+                            let from_spill = match (temp_signal_queue.peek(), oldest_spilled) {
+                                (Some(Reverse(waiting)), Some((task_id, _))) => {
+                                    task_id < waiting.task_id
+                                }
+                                (None, Some(_)) => true,
+                                (_, None) => false,
+                            };
+                            let (signal, data) = if from_spill {
+                                let (signal, data) =
+                                    spilled.remove(&oldest_spilled.unwrap()).unwrap();
+                                (signal, Some(data))
+                            } else {
+                                match temp_signal_queue.pop() {
+                                    Some(Reverse(signal)) => (signal, None),
+                                    None => break,
+                                }
+                            };
+                            let PartialSignalType {
+                                task_id,
+                                target_row,
+                                sender_id,
+                                queue_id,
+                            } = signal;
                             if shared_status
                                 .shared_buffer_status
                                 .can_receive(&self.buffer_status_id, task_id)
@@ -154,6 +274,11 @@ impl Component for PartialSumSignalCollector {
                                     task_id,
                                     sender_id,
                                 );
+                                // a spilled partial sum is read back by the spill port into the read back queue
+                                let queue_id = match (&data, self.spill_region) {
+                                    (Some(_), Some(region)) => region.queue_id_read_back,
+                                    _ => queue_id,
+                                };
                                 debug!(
                                     "PartialSumSignalCollector-{:?}:try to invoke PushSignal:{:?} queue_id:{}",
                                     self.level_id, task_id,self.queue_id_ready_out
@@ -167,6 +292,7 @@ impl Component for PartialSumSignalCollector {
                                                 target_row,
                                                 queue_id,
                                                 is_finished: finished,
+                                                spill_to: None,
                                             },
                                         ),
                                     ))
@@ -188,6 +314,24 @@ impl Component for PartialSumSignalCollector {
                                     "PartialSumSignalCollector-{:?}: send PushReadyQueueId:{:?}",
                                     self.level_id, target_row
                                 );
+                                if let (Some(data), Some(region)) = (data, self.spill_region) {
+                                    let context = co
+                                        .yield_(original_status.clone_with_state(
+                                            SpmmStatusEnum::PushSpillAccess(
+                                                region.queue_id_access,
+                                                SpillAccess::Read(data),
+                                            ),
+                                        ))
+                                        .await;
+                                    let (time, _status) = context.into_inner();
+                                    let gap = time - current_time;
+                                    current_time = time;
+                                    shared_status.shared_named_time.add_idle_time(
+                                        &self.named_sim_time,
+                                        "push_spill_read",
+                                        gap,
+                                    );
+                                }
                             } else {
                                 // cannot receive now, store it and resume it later
                                 debug!(
@@ -196,12 +340,18 @@ impl Component for PartialSumSignalCollector {
                                         .shared_buffer_status
                                         .get_current_status(&self.buffer_status_id)
                                 );
-                                temp_signal_queue.push(Reverse(PartialSignalType {
+                                let signal = PartialSignalType {
                                     task_id,
                                     target_row,
                                     sender_id,
                                     queue_id,
-                                }));
+                                };
+                                match data {
+                                    Some(data) => {
+                                        spilled.insert((task_id, sender_id), (signal, data));
+                                    }
+                                    None => temp_signal_queue.push(Reverse(signal)),
+                                }
                                 break;
                             }
                         }
@@ -81,11 +81,12 @@ pub struct TimeStats {
 
 #[derive(Serialize)]
 pub struct DetailedTimeStats {
-    /// real time, total time
-    pub status: BTreeMap<String, Vec<(f64, f64)>>,
+    /// component name, real time, total time
+    pub status: BTreeMap<String, Vec<(String, f64, f64)>>,
 }
 impl DetailedTimeStats {
-    pub fn to_rate(self) -> Vec<(String, Vec<f64>)> {
+    /// the rate of each component, by the component name
+    pub fn to_rate(self) -> Vec<(String, Vec<(String, f64)>)> {
         self.status
             .into_iter()
             .map(|(name, stats)| {
@@ -93,7 +94,7 @@ impl DetailedTimeStats {
                     name,
                     stats
                         .into_iter()
-                        .map(|(real, total)| real / total)
+                        .map(|(component, real, total)| (component, real / total))
                         .collect(),
                 )
             })
@@ -193,12 +194,12 @@ impl SharedNamedTime {
             status: BTreeMap::new(),
         };
         let data = self.data.borrow();
-        for (_name, tags, time) in data.iter() {
+        for (name, tags, time) in data.iter() {
             for tag_name in tags {
                 for (inner_name, inner_time) in &time.data {
                     let full_name = format!("{}:{}", tag_name, inner_name);
                     let entry = stats.status.entry(full_name).or_insert(vec![]);
-                    entry.push((*inner_time, sim_time));
+                    entry.push((name.clone(), *inner_time, sim_time));
                 }
             }
         }
@@ -0,0 +1,268 @@
+//! the partial sums refused by a full chip or channel buffer can be written into a dram region and read back later.
+//! the region is spread over the banks below the buffer, each write and read is a task in the queue of a bank
+
+use std::{cell::RefCell, collections::BTreeMap};
+
+use genawaiter::rc::{Co, Gen};
+use qsim::ResourceId;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use super::{
+    component::Component,
+    sim_time::NamedTimeId,
+    types::{
+        BankTaskEnum, PushPartialSumType, SpillTaskType, SpmmContex, SpmmGenerator,
+        StateWithSharedStatus,
+    },
+    LevelId, SpmmStatus, SpmmStatusEnum,
+};
+
+/// the spill region of each chip and channel buffer. in toml: `spill = { region_lines = 16 }`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Spill {
+    /// the partial sums each buffer can keep in its region, 0 disables the spill
+    pub region_lines: usize,
+    /// the bytes of one nonzero of a spilled partial sum, the index and the value
+    pub nnz_bytes: usize,
+    /// the cycles to move a partial sum between the buffer and the banks, added to each write and read
+    pub transfer_cycles: f64,
+}
+
+impl Default for Spill {
+    fn default() -> Self {
+        Self {
+            region_lines: 0,
+            nnz_bytes: 8,
+            transfer_cycles: 0.,
+        }
+    }
+}
+
+impl Spill {
+    pub fn is_enabled(&self) -> bool {
+        self.region_lines > 0
+    }
+
+    /// the bytes of a spilled partial sum of `nnz` nonzeros
+    pub fn bytes(&self, nnz: usize) -> usize {
+        nnz * self.nnz_bytes
+    }
+}
+
+/// the spill region of one buffer and the queues its partial sums are written and read back through
+#[derive(Debug, Clone, Copy)]
+pub struct SpillRegion {
+    pub spill: Spill,
+    /// the data collector moves a spilled partial sum here from the queue of its sender
+    pub queue_id_write: usize,
+    /// the writes and reads of the region, served in order by its `SpillPort`
+    pub queue_id_access: usize,
+    pub queue_id_read_back: usize,
+}
+
+/// a partial sum to write into the region, or to read back into the read back queue
+#[derive(Debug, Clone)]
+pub enum SpillAccess {
+    Write(PushPartialSumType),
+    Read(PushPartialSumType),
+}
+
+/// moves the partial sums between a buffer and its spill region, one access at a time.
+/// the access is a task in the queue of a bank below the buffer: it waits behind the reads of B
+/// and opens the rows of the region, so the next read of B opens its row again
+#[derive(Debug)]
+pub struct SpillPort {
+    pub level_id: LevelId,
+    pub region: SpillRegion,
+    /// the task queues of the banks holding the region, a partial sum is in the bank of its target row
+    pub bank_task_in: Vec<ResourceId>,
+    /// the banks push `PushSpillDone` here
+    pub queue_id_done: ResourceId,
+    pub named_sim_time: NamedTimeId,
+}
+
+impl Component for SpillPort {
+    fn run(self, original_status: SpmmStatus) -> Box<SpmmGenerator> {
+        let function =
+            |co: Co<SpmmStatus, SpmmContex>| async move {
+                let mut current_time = 0.;
+                let buffer_name = format!("{:?}", self.level_id);
+                loop {
+                    let context = co
+                        .yield_(
+                            original_status
+                                .clone_with_state(SpmmStatusEnum::Pop(self.region.queue_id_access)),
+                        )
+                        .await;
+                    let (time, status) = context.into_inner();
+                    let StateWithSharedStatus {
+                        status,
+                        shared_status,
+                    } = status.into_inner();
+                    shared_status.shared_named_time.add_idle_time(
+                        &self.named_sim_time,
+                        "get_access",
+                        time - current_time,
+                    );
+                    current_time = time;
+                    let access = status.into_push_spill_access().unwrap().1;
+                    let (SpillAccess::Write(data) | SpillAccess::Read(data)) = &access;
+                    let bank = self.bank_task_in[data.target_row % self.bank_task_in.len()];
+                    debug!(
+                        "SpillPort-{:?}: access of target row {} in bank queue {}, read: {}",
+                        self.level_id,
+                        data.target_row,
+                        bank,
+                        matches!(access, SpillAccess::Read(_))
+                    );
+                    let task = SpillTaskType {
+                        bytes: self.region.spill.bytes(data.target_result.indices.len()),
+                        done: self.queue_id_done,
+                    };
+                    co.yield_(
+                        original_status.clone_with_state(SpmmStatusEnum::PushBankTask(
+                            bank,
+                            BankTaskEnum::Spill(task),
+                        )),
+                    )
+                    .await;
+                    co.yield_(
+                        original_status.clone_with_state(SpmmStatusEnum::Pop(self.queue_id_done)),
+                    )
+                    .await;
+                    let context = co
+                        .yield_(original_status.clone_with_state(SpmmStatusEnum::Wait(
+                            self.region.spill.transfer_cycles,
+                        )))
+                        .await;
+                    // the cycles from the push of the task to the end of the transfer
+                    let (time, _status) = context.into_inner();
+                    let cycles = time - current_time;
+                    current_time = time;
+                    match access {
+                        SpillAccess::Write(_) => {
+                            shared_status.shared_named_time.add_idle_time(
+                                &self.named_sim_time,
+                                "spill_write",
+                                cycles,
+                            );
+                            shared_status.shared_spill.add_write(&buffer_name, cycles);
+                        }
+                        SpillAccess::Read(data) => {
+                            shared_status.shared_named_time.add_idle_time(
+                                &self.named_sim_time,
+                                "spill_read",
+                                cycles,
+                            );
+                            shared_status.shared_spill.add_read(&buffer_name, cycles);
+                            let context = co
+                                .yield_(original_status.clone_with_state(
+                                    SpmmStatusEnum::PushPartialTask(
+                                        self.region.queue_id_read_back,
+                                        data,
+                                    ),
+                                ))
+                                .await;
+                            let (time, _status) = context.into_inner();
+                            shared_status.shared_named_time.add_idle_time(
+                                &self.named_sim_time,
+                                "push_read_back",
+                                time - current_time,
+                            );
+                            current_time = time;
+                        }
+                    }
+                }
+            };
+        Box::new(Gen::new(function))
+    }
+}
+
+/// the spills of one buffer
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct SpillCounter {
+    pub spills: usize,
+    pub write_cycles: f64,
+    pub read_cycles: f64,
+    /// the most lines used in the region at the same time
+    pub max_lines: usize,
+}
+
+/// the spills of all buffers, written by `PartialSumSignalCollector`
+#[derive(Debug, Default)]
+pub struct SharedSpillStats {
+    inner: RefCell<BTreeMap<String, SpillCounter>>,
+}
+
+impl SharedSpillStats {
+    /// a partial sum is spilled, `used_lines` is the lines of the region with it
+    pub fn add_spill(&self, buffer: &str, used_lines: usize) {
+        let mut inner = self.inner.borrow_mut();
+        let counter = inner.entry(buffer.to_string()).or_default();
+        counter.spills += 1;
+        counter.max_lines = counter.max_lines.max(used_lines);
+    }
+
+    pub fn add_write(&self, buffer: &str, cycles: f64) {
+        let mut inner = self.inner.borrow_mut();
+        inner.entry(buffer.to_string()).or_default().write_cycles += cycles;
+    }
+
+    pub fn add_read(&self, buffer: &str, cycles: f64) {
+        let mut inner = self.inner.borrow_mut();
+        inner.entry(buffer.to_string()).or_default().read_cycles += cycles;
+    }
+
+    pub fn get_stats(&self) -> SpillStats {
+        let inner = self.inner.borrow();
+        SpillStats {
+            spills: inner.values().map(|c| c.spills).sum(),
+            extra_cycles: inner.values().map(|c| c.write_cycles + c.read_cycles).sum(),
+            buffers: inner.clone(),
+        }
+    }
+}
+
+/// the spill counts and the cycles spent to write and read them, waiting in the bank queues included.
+/// all zero when the spill is disabled
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SpillStats {
+    pub spills: usize,
+    pub extra_cycles: f64,
+    pub buffers: BTreeMap<String, SpillCounter>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bytes() {
+        let spill = Spill {
+            region_lines: 4,
+            nnz_bytes: 8,
+            transfer_cycles: 2.,
+        };
+        assert_eq!(spill.bytes(100), 800);
+        assert!(spill.is_enabled());
+        assert!(!Spill::default().is_enabled());
+    }
+
+    #[test]
+    fn stats() {
+        let stats = SharedSpillStats::default();
+        stats.add_spill("Chip((0, 0))", 1);
+        stats.add_write("Chip((0, 0))", 16.);
+        stats.add_spill("Chip((0, 0))", 2);
+        stats.add_write("Chip((0, 0))", 16.);
+        stats.add_read("Chip((0, 0))", 16.);
+        stats.add_spill("Channel(1)", 1);
+        stats.add_write("Channel(1)", 32.);
+        let stats = stats.get_stats();
+        assert_eq!(stats.spills, 3);
+        assert_eq!(stats.extra_cycles, 80.);
+        assert_eq!(stats.buffers["Chip((0, 0))"].max_lines, 2);
+    }
+}
@@ -0,0 +1,244 @@
+//! a stream of A batches against a B that stays in the banks, B is placed once before the first batch
+
+use std::path::{Path, PathBuf};
+
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use sprs::CsMat;
+
+use super::sim_time::EndTimeId;
+
+/// the stream of the config. in toml:
+/// ```toml
+/// [stream]
+/// b_file = "mtx/b.mtx"
+/// a_files = ["mtx/a0.mtx", "mtx/a1.mtx"]
+/// batch_rows = 16
+/// interval = 500.0
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct StreamSpec {
+    /// the resident matrix, A^T of the first A if not set
+    pub b_file: Option<PathBuf>,
+    pub a_files: Vec<PathBuf>,
+    /// split each A into batches of this many rows, 0 keeps each A as one batch
+    pub batch_rows: usize,
+    /// the arrival cycle of each batch, the batches after them arrive `interval` cycles after the previous one
+    pub arrivals: Vec<f64>,
+    pub interval: f64,
+}
+
+impl StreamSpec {
+    /// the arrival cycle of each of `batches` batches
+    pub fn arrivals(&self, batches: usize) -> Vec<f64> {
+        let mut arrivals = self
+            .arrivals
+            .iter()
+            .cloned()
+            .take(batches)
+            .collect::<Vec<_>>();
+        while arrivals.len() < batches {
+            let next = arrivals.last().map_or(0., |last| last + self.interval);
+            arrivals.push(next);
+        }
+        arrivals
+    }
+
+    /// read the resident matrix and the batches of A, the batches arrive at `arrivals`
+    pub fn load(&self) -> eyre::Result<(CsMat<i32>, Vec<Batch>)> {
+        let read = |file: &Path| -> eyre::Result<CsMat<i32>> {
+            Ok(sprs::io::read_matrix_market(file)
+                .wrap_err(format!("{:?} is error!", file))?
+                .to_csr())
+        };
+        let mut matrix_b = self.b_file.as_deref().map(read).transpose()?;
+        let mut matrices = vec![];
+        for file in &self.a_files {
+            let a = read(file)?;
+            // B is the transpose of the whole first A, not of its first batch
+            if matrix_b.is_none() {
+                matrix_b = Some(a.transpose_view().to_csr());
+            }
+            matrices.extend(split_rows(&a, self.batch_rows));
+        }
+        let matrix_b = matrix_b.ok_or_else(|| eyre::eyre!("the stream has no a_files"))?;
+        let arrivals = self.arrivals(matrices.len());
+        let batches = matrices
+            .into_iter()
+            .zip(arrivals)
+            .map(|(a, arrival)| Batch { a, arrival })
+            .collect();
+        Ok((matrix_b, batches))
+    }
+}
+
+/// split `a` into batches of `batch_rows` rows, 0 keeps it whole
+pub fn split_rows(a: &CsMat<i32>, batch_rows: usize) -> Vec<CsMat<i32>> {
+    if batch_rows == 0 || a.rows() <= batch_rows {
+        return vec![a.clone()];
+    }
+    (0..a.rows())
+        .step_by(batch_rows)
+        .map(|start| {
+            a.slice_outer(start..(start + batch_rows).min(a.rows()))
+                .to_owned()
+        })
+        .collect()
+}
+
+/// one A of `Simulator::run_stream`
+pub struct Batch {
+    pub a: CsMat<i32>,
+    pub arrival: f64,
+}
+
+/// a batch in the task sender, its tasks follow the ones of the batch before
+#[derive(Debug, Clone, Copy)]
+pub struct BatchArrival {
+    pub tasks: usize,
+    pub arrival: f64,
+    /// records the cycle the first task of the batch is sent
+    pub start_time_id: EndTimeId,
+}
+
+/// the batch of each final row, the rows of the batches are stacked in order
+#[derive(Debug, Clone)]
+pub struct BatchEndTimes {
+    /// the first row of each batch
+    pub row_starts: Vec<usize>,
+    /// records the cycle the last row of each batch is received
+    pub end_time_ids: Vec<EndTimeId>,
+}
+
+impl BatchEndTimes {
+    pub fn end_time_id(&self, row: usize) -> EndTimeId {
+        self.end_time_ids[self.row_starts.partition_point(|&start| start <= row) - 1]
+    }
+}
+
+/// the time of one batch
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchReport {
+    pub rows: usize,
+    pub nnz: usize,
+    pub arrival: f64,
+    /// the cycle the first task is sent, later than `arrival` when the batches before are still sent
+    pub start: f64,
+    /// the cycle the last row is received
+    pub finish: f64,
+    /// `finish - arrival`
+    pub latency: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamReport {
+    pub cycles: f64,
+    pub rows: usize,
+    /// the rows per cycle from the first arrival to the last finish
+    pub throughput: f64,
+    pub batches_per_cycle: f64,
+    pub mean_latency: f64,
+    pub max_latency: f64,
+    pub batches: Vec<BatchReport>,
+}
+
+impl StreamReport {
+    pub fn new(cycles: f64, batches: Vec<BatchReport>) -> Self {
+        let rows = batches.iter().map(|batch| batch.rows).sum();
+        let first_arrival = batches
+            .iter()
+            .map(|batch| batch.arrival)
+            .fold(f64::INFINITY, f64::min);
+        let last_finish = batches.iter().map(|batch| batch.finish).fold(0., f64::max);
+        let window = last_finish - first_arrival;
+        let (throughput, batches_per_cycle) = if window > 0. {
+            (rows as f64 / window, batches.len() as f64 / window)
+        } else {
+            (0., 0.)
+        };
+        let latencies = batches.iter().map(|batch| batch.latency);
+        Self {
+            cycles,
+            rows,
+            throughput,
+            batches_per_cycle,
+            mean_latency: latencies.clone().sum::<f64>() / batches.len().max(1) as f64,
+            max_latency: latencies.fold(0., f64::max),
+            batches,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::fixtures::ones_at;
+
+    #[test]
+    fn arrivals_and_batches() {
+        let spec = StreamSpec {
+            arrivals: vec![0., 50.],
+            interval: 100.,
+            ..Default::default()
+        };
+        assert_eq!(spec.arrivals(4), vec![0., 50., 150., 250.]);
+        assert_eq!(spec.arrivals(1), vec![0.]);
+
+        let a = ones_at((5, 3), (0..5).map(|row| (row, row % 3)));
+        let batches = split_rows(&a, 2);
+        assert_eq!(
+            batches.iter().map(|batch| batch.rows()).collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+        assert_eq!(batches[2].outer_view(0).unwrap().indices(), &[1]);
+        assert_eq!(split_rows(&a, 0).len(), 1);
+
+        let ends = BatchEndTimes {
+            row_starts: vec![0, 2, 4],
+            end_time_ids: (0..3).map(|id| EndTimeId { id }).collect(),
+        };
+        assert_eq!(ends.end_time_id(3).id, 1);
+        assert_eq!(ends.end_time_id(4).id, 2);
+    }
+
+    #[test]
+    fn load_without_b_file() {
+        let spec = StreamSpec {
+            a_files: vec!["mtx/test.mtx".into()],
+            batch_rows: 4,
+            ..Default::default()
+        };
+        let (matrix_b, batches) = spec.load().unwrap();
+        // B is the transpose of the whole 6x6 A, the A is split into 4 and 2 rows
+        let a: CsMat<i32> = sprs::io::read_matrix_market("mtx/test.mtx")
+            .unwrap()
+            .to_csr();
+        assert_eq!(matrix_b, a.transpose_view().to_csr());
+        assert_eq!(
+            batches
+                .iter()
+                .map(|batch| batch.a.rows())
+                .collect::<Vec<_>>(),
+            vec![4, 2]
+        );
+        assert!(StreamSpec::default().load().is_err());
+    }
+
+    #[test]
+    fn report() {
+        let batch = |arrival, finish| BatchReport {
+            rows: 10,
+            nnz: 20,
+            arrival,
+            start: arrival,
+            finish,
+            latency: finish - arrival,
+        };
+        let report = StreamReport::new(300., vec![batch(0., 100.), batch(100., 300.)]);
+        assert_eq!(report.rows, 20);
+        assert_eq!(report.throughput, 20. / 300.);
+        assert_eq!(report.mean_latency, 150.);
+        assert_eq!(report.max_latency, 200.);
+    }
+}
@@ -1,7 +1,8 @@
 use std::iter::Enumerate;
 
 use itertools::Itertools;
-use rand::seq::SliceRandom;
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_xorshift::XorShiftRng;
 use sprs::CsMat;
 
 use crate::csv_nodata::CsVecNodata;
@@ -32,9 +33,9 @@ pub struct RandomTaskScheduler {
     data: std::vec::IntoIter<(usize, CsVecNodata<usize>)>,
 }
 impl RandomTaskScheduler {
-    pub fn new(data: Vec<CsVecNodata<usize>>) -> Self {
+    pub fn new(data: Vec<CsVecNodata<usize>>, seed: u64) -> Self {
         let mut iter = data.into_iter().enumerate().collect_vec();
-        let mut rng = rand::thread_rng();
+        let mut rng = XorShiftRng::seed_from_u64(seed);
         iter.shuffle(&mut rng);
         Self {
             data: iter.into_iter(),
@@ -55,11 +56,11 @@ pub struct BatchShuffleScheduler {
     iter_data: Vec<(usize, CsVecNodata<usize>)>,
 }
 impl BatchShuffleScheduler {
-    pub fn new(chunk_size: usize, data: Vec<CsVecNodata<usize>>) -> Self {
+    pub fn new(chunk_size: usize, data: Vec<CsVecNodata<usize>>, seed: u64) -> Self {
         let grouped_task = data.into_iter().enumerate().chunks(chunk_size);
         let mut grouped_task = grouped_task.into_iter().collect_vec();
 
-        let mut rng = rand::thread_rng();
+        let mut rng = XorShiftRng::seed_from_u64(seed);
         grouped_task.shuffle(&mut rng);
         let task_iter = grouped_task.into_iter().flatten().collect_vec();
         Self {
@@ -1,9 +1,10 @@
-use std::fmt::Debug;
+use std::{collections::VecDeque, fmt::Debug, rc::Rc};
 
 use crate::{
     csv_nodata::CsVecNodata,
-    pim::get_bank_id_from_row_id,
-    settings::RealRowMapping,
+    inspect::TaskDistribution,
+    pim::{get_bank_id_from_row_id, ResolvedRowMapping},
+    replication::ReplicationPlan,
     sim::types::{BankTaskEnum, PushBankTaskType, StateWithSharedStatus},
 };
 use genawaiter::rc::{Co, Gen};
@@ -15,8 +16,10 @@ use sprs::CsMat;
 
 use super::{
     component::Component,
+    job::{JobPort, JobSignal},
     queue_tracker::QueueTrackerId,
-    types::{SpmmContex, SpmmGenerator},
+    stream::BatchArrival,
+    types::{SharedRow, SpmmContex, SpmmGenerator},
     SpmmStatus,
 };
 
@@ -29,8 +32,14 @@ pub struct TaskSender<T> {
     channels: usize,
     chips: usize,
     banks: usize,
-    row_mapping: RealRowMapping,
+    row_mapping: ResolvedRowMapping,
     queue_tracker_id_send: QueueTrackerId,
+    /// the copies of the hot rows of B, empty by default
+    replication: ReplicationPlan,
+    /// the arbiter of the host link when several jobs share the dimm, None for a single job
+    job: Option<JobPort>,
+    /// the batches of a stream in the order of the tasks, empty if all tasks are sent at once
+    batches: Vec<BatchArrival>,
 
     // contructor
     pub task_generator: T,
@@ -46,6 +55,62 @@ impl<T> Debug for TaskSender<T> {
     }
 }
 
+/// wait for a credit of the link to the dimm, nothing if the link has no credits
+async fn take_credit(
+    co: &Co<SpmmStatus, SpmmContex>,
+    original_status: &SpmmStatus,
+    queue_tracker_id: &QueueTrackerId,
+) {
+    if let Some(credits) = original_status
+        .shared_status
+        .shared_credits
+        .get(queue_tracker_id)
+    {
+        co.yield_(original_status.clone_with_state(super::SpmmStatusEnum::Pop(credits.credit_in)))
+            .await;
+    }
+}
+
+/// wait until the arbiter gives the host link to the job, return the id of the task
+async fn acquire_host_link(
+    co: &Co<SpmmStatus, SpmmContex>,
+    original_status: &SpmmStatus,
+    port: &JobPort,
+) -> usize {
+    co.yield_(
+        original_status.clone_with_state(super::SpmmStatusEnum::PushJobSignal(
+            port.queue_id_arbiter,
+            JobSignal::Request(port.job),
+        )),
+    )
+    .await;
+    co.yield_(original_status.clone_with_state(super::SpmmStatusEnum::Pop(port.queue_id_grant)))
+        .await;
+    original_status
+        .shared_status
+        .shared_jobs
+        .next_task(port.job)
+}
+
+/// wait until the cycle `arrival`, return the cycle after the wait
+async fn wait_until(
+    co: &Co<SpmmStatus, SpmmContex>,
+    original_status: &SpmmStatus,
+    arrival: f64,
+) -> f64 {
+    let context = co
+        .yield_(original_status.clone_with_state(super::SpmmStatusEnum::Continue))
+        .await;
+    let (time, _status) = context.into_inner();
+    if arrival > time {
+        co.yield_(original_status.clone_with_state(super::SpmmStatusEnum::Wait(arrival - time)))
+            .await;
+        arrival
+    } else {
+        time
+    }
+}
+
 impl<T> Component for TaskSender<T>
 where
     T: IntoIterator<Item = (usize, CsVecNodata<usize>)> + 'static,
@@ -68,40 +133,70 @@ where
             // print the result:
             debug!(target:"spmm_pim::sim::task_sender::histo","TaskSender: total rows: {:?}", num_rows);
             debug!(target:"spmm_pim::sim::task_sender::histo","TaskSender: source id distribution: {:?}", all_source_id);
-            let mut channel_dist = vec![0; self.channels];
-            let mut chip_dist = vec![0; self.chips * self.channels];
-            let mut chip_standalone = vec![0; self.chips];
-            let mut bank_dist = vec![0; self.banks * self.chips * self.channels];
-            let mut bank_standalone = vec![0; self.banks];
-            for (_, (_row_id, col_id)) in self.matrix_a.iter() {
-                let (((channel, chip), bank), _) = get_bank_id_from_row_id(
-                    col_id,
-                    self.channels,
-                    self.chips,
-                    self.banks,
-                    num_rows,
-                    &self.row_mapping,
-                );
-                channel_dist[channel] += 1;
-                chip_dist[chip + (channel * self.chips)] += 1;
-                chip_standalone[chip] += 1;
-                bank_dist[bank + (channel * self.chips * self.banks) + (chip * self.banks)] += 1;
-                bank_standalone[bank] += 1;
-            }
+            let dist = TaskDistribution::new(
+                &self.matrix_a,
+                num_rows,
+                self.channels,
+                self.chips,
+                self.banks,
+                &self.row_mapping,
+            );
             // print the result:
-            debug!(target:"spmm_pim::sim::task_sender::histo","TaskSender: channel distribution: {:?}", channel_dist);
-            debug!(target:"spmm_pim::sim::task_sender::histo","TaskSender: chip distribution: {:?}", chip_dist);
-            debug!(target:"spmm_pim::sim::task_sender::histo","TaskSender: chip standalone distribution: {:?}", chip_standalone);
-            debug!(target:"spmm_pim::sim::task_sender::histo","TaskSender: bank distribution: {:?}", bank_dist);
-            debug!(target:"spmm_pim::sim::task_sender::histo","TaskSender: bank standalone distribution: {:?}", bank_standalone);
+            debug!(target:"spmm_pim::sim::task_sender::histo","TaskSender: channel distribution: {:?}", dist.channel.counts);
+            debug!(target:"spmm_pim::sim::task_sender::histo","TaskSender: chip distribution: {:?}", dist.chip.counts);
+            debug!(target:"spmm_pim::sim::task_sender::histo","TaskSender: chip standalone distribution: {:?}", dist.chip_standalone.counts);
+            debug!(target:"spmm_pim::sim::task_sender::histo","TaskSender: bank distribution: {:?}", dist.bank.counts);
+            debug!(target:"spmm_pim::sim::task_sender::histo","TaskSender: bank standalone distribution: {:?}", dist.bank_standalone.counts);
             // then compute the level distribution
             let mut task_id = 0;
+            // every row of b is copied only once, all tasks share it
+            let b_rows: Vec<SharedRow> = self
+                .matrix_b
+                .outer_iterator()
+                .map(|row| {
+                    Rc::new(CsVecNodata {
+                        dim: row.dim(),
+                        indices: row.indices().to_vec(),
+                    })
+                })
+                .collect_vec();
+            let mut replication = self.replication;
+            let (row_offset, nnz_offset) = self
+                .job
+                .map_or((0, 0), |port| (port.row_offset, port.nnz_offset));
+            if let Some(port) = &self.job {
+                co.yield_(
+                    original_status.clone_with_state(super::SpmmStatusEnum::Wait(port.arrival)),
+                )
+                .await;
+            }
+            // the index of the first task of each batch
+            let mut batches = self
+                .batches
+                .iter()
+                .scan(0, |first, batch| {
+                    let this = *first;
+                    *first += batch.tasks;
+                    Some((this, *batch))
+                })
+                .collect::<VecDeque<_>>();
             // for each row, first send the index to lower pe, then send a end signal
-            for (target_idx, vector) in self.task_generator.into_iter() {
+            for (sent, (target_idx, vector)) in self.task_generator.into_iter().enumerate() {
+                while batches.front().map_or(false, |(first, _)| *first == sent) {
+                    let (_, batch) = batches.pop_front().unwrap();
+                    let start = wait_until(&co, &original_status, batch.arrival).await;
+                    original_status
+                        .shared_status
+                        .shared_end_time
+                        .set_end_time(batch.start_time_id, start);
+                }
                 let all_source = vector.iter().cloned().collect_vec();
+                if let Some(port) = &self.job {
+                    task_id = acquire_host_link(&co, &original_status, port).await;
+                }
                 // for every col in this row, push a task to lower pe
                 for source_idx in all_source {
-                    let bank_id = get_bank_id_from_row_id(
+                    let (home_bank, row_in_bank) = get_bank_id_from_row_id(
                         source_idx,
                         self.channels,
                         self.chips,
@@ -109,27 +204,28 @@ where
                         num_rows,
                         &self.row_mapping,
                     );
+                    // the copies of a replicated row are stored at the same place of their banks
+                    let bank_id = (
+                        replication.route(source_idx, home_bank, b_rows[source_idx].indices.len()),
+                        row_in_bank,
+                    );
 
-                    let row = self
-                        .matrix_b
-                        .outer_view(source_idx)
-                        .unwrap()
-                        .to_owned()
-                        .into();
+                    let row = b_rows[source_idx].clone();
                     debug!(target:"spmm_pim::sim::task_sender::histo","TASKSENDER:target_idx: {} source_idx: {} target_bank: {:?}", target_idx, source_idx, bank_id);
                     debug!("SENDER: {}:{}:{:?}", target_idx, source_idx, row);
                     let row_start = self.matrix_b.indptr().outer_inds_sz(source_idx);
+                    take_credit(&co, &original_status, &self.queue_tracker_id_send).await;
                     let context = co
                         .yield_(original_status.clone_with_state(
                             super::SpmmStatusEnum::PushBankTask(
                                 self.task_sender,
                                 BankTaskEnum::PushBankTask(PushBankTaskType {
                                     task_id,
-                                    from: source_idx,
+                                    from: source_idx + row_offset,
                                     to: target_idx,
                                     row,
                                     bank_id: bank_id.0,
-                                    row_shift: row_start.start,
+                                    row_shift: row_start.start + nnz_offset,
                                     row_size: row_start.end - row_start.start,
                                 }),
                             ),
@@ -143,6 +239,7 @@ where
                     shared_status.queue_tracker.enq(&self.queue_tracker_id_send);
                 }
                 // then send a end signal
+                take_credit(&co, &original_status, &self.queue_tracker_id_send).await;
                 let context = co
                     .yield_(
                         original_status.clone_with_state(super::SpmmStatusEnum::PushBankTask(
@@ -157,6 +254,15 @@ where
                     shared_status,
                 } = status.into_inner();
                 shared_status.queue_tracker.enq(&self.queue_tracker_id_send);
+                if let Some(port) = &self.job {
+                    co.yield_(original_status.clone_with_state(
+                        super::SpmmStatusEnum::PushJobSignal(
+                            port.queue_id_arbiter,
+                            JobSignal::Done(port.job),
+                        ),
+                    ))
+                    .await;
+                }
                 task_id += 1;
             }
         };
@@ -172,7 +278,7 @@ impl<T> TaskSender<T> {
         channels: usize,
         chips: usize,
         banks: usize,
-        row_mapping: RealRowMapping,
+        row_mapping: ResolvedRowMapping,
         queue_tracker_id_send: QueueTrackerId,
         task_generator: T,
     ) -> Self {
@@ -185,7 +291,28 @@ impl<T> TaskSender<T> {
             banks,
             row_mapping,
             queue_tracker_id_send,
+            replication: Default::default(),
+            job: None,
+            batches: vec![],
             task_generator,
         }
     }
+
+    /// send the tasks of the replicated rows to the least loaded copy
+    pub fn with_replication(mut self, replication: ReplicationPlan) -> Self {
+        self.replication = replication;
+        self
+    }
+
+    /// send the tasks of one of several jobs, the task ids are given by the arbiter
+    pub fn with_job(mut self, port: JobPort) -> Self {
+        self.job = Some(port);
+        self
+    }
+
+    /// send the tasks in batches, each batch waits for its arrival
+    pub fn with_batches(mut self, batches: Vec<BatchArrival>) -> Self {
+        self.batches = batches;
+        self
+    }
 }
@@ -7,26 +7,33 @@ use qsim::{resources::CopyDefault, Effect, ResourceId, SimContext, SimState};
 use crate::csv_nodata::CsVecNodata;
 
 use super::{
+    bank_cache::SharedBankCacheStats,
     buffer_status::SharedBufferStatus,
+    credit::SharedCredits,
     id_translation::{BankID, LevelId, PeID},
+    job::{JobSignal, JobTable},
     merger_status::SharedMergerStatus,
     queue_tracker::QueueTracker,
     sim_time::{LevelTime, SharedEndTime, SharedNamedTime, SharedSimTime},
+    spill::{SharedSpillStats, SpillAccess},
 };
+/// a row of B or a partial sum, shared by all tasks that refer to it, never copied in the simulator
+pub type SharedRow = Rc<CsVecNodata<usize>>;
+
 // target row, sender_id, target result
 #[derive(Debug, Clone)]
 pub struct PushPartialSumType {
     pub task_id: usize,
     pub target_row: usize,
     pub sender_id: ResourceId,
-    pub target_result: CsVecNodata<usize>,
+    pub target_result: SharedRow,
 }
 #[derive(Debug, Clone)]
 
 pub struct PushFullSumType {
     pub task_id: usize,
     pub target_row: usize,
-    pub target_result: Vec<CsVecNodata<usize>>,
+    pub target_result: Vec<SharedRow>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -34,12 +41,19 @@ pub struct PushBankTaskType {
     pub task_id: usize,
     pub from: usize,
     pub to: usize,
-    pub row: CsVecNodata<usize>,
+    pub row: SharedRow,
     pub bank_id: BankID,
     pub row_shift: usize,
     pub row_size: usize,
 }
 
+/// a write or read of a spill region, the bank pushes a `PushSpillDone` to `done` when its rows are accessed
+#[derive(Debug, Clone)]
+pub struct SpillTaskType {
+    pub bytes: usize,
+    pub done: ResourceId,
+}
+
 pub type SpmmContex = SimContext<SpmmStatus>;
 pub type SpmmGenerator =
     dyn Coroutine<Resume = SpmmContex, Yield = SpmmStatus, Return = ()> + Unpin;
@@ -47,6 +61,8 @@ pub type SpmmGenerator =
 #[derive(Debug, Clone, EnumAsInner, Default)]
 pub enum BankTaskEnum {
     PushBankTask(PushBankTaskType),
+    /// served by `BankTaskReorder`, never sent to the bank pe
+    Spill(SpillTaskType),
     #[default]
     EndThisTask,
 }
@@ -68,6 +84,8 @@ pub struct ReadyQueueIdType {
     pub target_row: usize,
     pub queue_id: usize,
     pub is_finished: bool,
+    /// the partial sum is moved to this queue instead of the buffer, set when the signal collector spills it
+    pub spill_to: Option<usize>,
 }
 
 #[derive(Default, Debug, Clone, EnumAsInner)]
@@ -82,6 +100,14 @@ pub enum SpmmStatusEnum {
     PushFullPartialTask(ResourceId, PushFullSumType),
     PushBufferPopSignal(ResourceId),
     PushMergerFinishedSignal(ResourceId),
+    /// a credit of a task link, see `credit`
+    PushCredit(ResourceId),
+    /// a request or the end of a task of a job, see `job`
+    PushJobSignal(ResourceId, JobSignal),
+    /// a partial sum to write into or read back from a spill region, see `spill`
+    PushSpillAccess(ResourceId, SpillAccess),
+    /// a spill task is finished by the bank
+    PushSpillDone(ResourceId),
     Pop(ResourceId),
 }
 
@@ -98,6 +124,10 @@ pub struct SharedStatus {
     pub shared_merger_status: Rc<SharedMergerStatus>,
     pub shared_end_time: Rc<SharedEndTime>,
     pub queue_tracker: Rc<QueueTracker>,
+    pub shared_bank_cache: Rc<SharedBankCacheStats>,
+    pub shared_spill: Rc<SharedSpillStats>,
+    pub shared_credits: Rc<SharedCredits>,
+    pub shared_jobs: Rc<JobTable>,
 }
 pub struct StateWithSharedStatus {
     pub status: SpmmStatusEnum,
@@ -210,6 +240,10 @@ impl SimState for SpmmStatus {
             SpmmStatusEnum::PushFullPartialTask(rid, _) => Effect::Push(*rid),
             SpmmStatusEnum::PushBufferPopSignal(rid) => Effect::Push(*rid),
             SpmmStatusEnum::PushMergerFinishedSignal(rid) => Effect::Push(*rid),
+            SpmmStatusEnum::PushCredit(rid) => Effect::Push(*rid),
+            SpmmStatusEnum::PushJobSignal(rid, _) => Effect::Push(*rid),
+            SpmmStatusEnum::PushSpillAccess(rid, _) => Effect::Push(*rid),
+            SpmmStatusEnum::PushSpillDone(rid) => Effect::Push(*rid),
         }
     }
 
@@ -13,8 +13,10 @@ use tracing::instrument;
 use crate::{
     csv_nodata::CsVecNodata,
     non_pim::NonPim,
-    pim::{self, AdderTaskBuilder, MergeCycle, MultiplicatableTo, PartialSum, Pim},
-    settings::{MemSettings, RealRowMapping, RowMapping},
+    pim::{
+        self, AdderTaskBuilder, MergeCycle, MultiplicatableTo, PartialSum, Pim, ResolvedRowMapping,
+    },
+    settings::MemSettings,
 };
 
 /// two matrix which are going to be multiplied
@@ -120,7 +122,11 @@ where
     /// return the number of cycles to read the rows of the matrix
     /// - input: mem_settings
     /// - output: number of cycles for each bank
-    fn mem_rows(&self, mem_settings: &MemSettings) -> Vec<(usize, usize)> {
+    fn mem_rows(
+        &self,
+        mem_settings: &MemSettings,
+        row_mapping: &ResolvedRowMapping,
+    ) -> Vec<(usize, usize)> {
         let num_banks = mem_settings.banks * mem_settings.chips * mem_settings.channels;
 
         // fisrt calculate the access row stream of each bank
@@ -130,10 +136,6 @@ where
         // contains the rows to read for each bank
         let mut row_stream = vec![vec![]; num_banks];
         // return the bank id and the row id in bank
-        let real_row_mapping = match mem_settings.row_mapping {
-            RowMapping::Chunk => RealRowMapping::Chunk,
-            RowMapping::Interleaved => RealRowMapping::Interleaved(mem_settings.interleaved_chunk),
-        };
         let mut bank_read_size = vec![0; num_banks];
         for (_node, (_a_row, a_col)) in self.a.iter() {
             // the row in matrix B
@@ -144,7 +146,7 @@ where
                 mem_settings.chips,
                 mem_settings.banks,
                 num_rows,
-                &real_row_mapping,
+                row_mapping,
             );
             let bank_id = channel_id * mem_settings.chips * mem_settings.banks
                 + chip_id * mem_settings.banks
@@ -192,14 +194,14 @@ where
     /// return:
     /// - Vec<MergeCycle>: the merge cycles for each bank
     /// - Vec<PartialSum<usize>>: the partial sums for each bank
-    fn bank_merge(&self, mem_settings: &MemSettings) -> (Vec<MergeCycle>, Vec<PartialSum<usize>>) {
+    fn bank_merge(
+        &self,
+        mem_settings: &MemSettings,
+        row_mapping: &ResolvedRowMapping,
+    ) -> (Vec<MergeCycle>, Vec<PartialSum<usize>>) {
         let merger_size = mem_settings.bank_merger_size;
         let num_banks = mem_settings.banks * mem_settings.chips * mem_settings.channels;
         let mut bank_tasks = vec![AdderTaskBuilder::default(); num_banks];
-        let real_row_mapping = match mem_settings.row_mapping {
-            RowMapping::Chunk => RealRowMapping::Chunk,
-            RowMapping::Interleaved => RealRowMapping::Interleaved(mem_settings.interleaved_chunk),
-        };
         for i in self.a.iter() {
             let row_select = i.1 .1.index();
             tracing::debug!("row_select: {:?}", row_select);
@@ -211,7 +213,7 @@ where
                 mem_settings.chips,
                 mem_settings.banks,
                 self.a.cols(),
-                &real_row_mapping,
+                row_mapping,
             );
             let bank_id = channel_id * mem_settings.chips * mem_settings.banks
                 + chip_id * mem_settings.banks
@@ -371,7 +373,12 @@ mod towmatrix_test {
     use sprs::CsMat;
     use tracing::Level;
 
-    use crate::{init_logger, non_pim::NonPim, pim::Pim, settings::MemSettings};
+    use crate::{
+        init_logger,
+        non_pim::NonPim,
+        pim::{Pim, ResolvedRowMapping},
+        settings::MemSettings,
+    };
 
     use super::{TwoMatrix, TwoMatrixWrapperForNonPim};
 
@@ -411,9 +418,10 @@ mod towmatrix_test {
         tracing::info!(?trans_pose);
         let two_matrix = TwoMatrix::new(csr, trans_pose);
         let mem_settings = MemSettings::default();
-        let mem_rows = two_matrix.mem_rows(&mem_settings);
+        let row_mapping = ResolvedRowMapping::new(&mem_settings, &two_matrix.b)?;
+        let mem_rows = two_matrix.mem_rows(&mem_settings, &row_mapping);
         tracing::info!(?mem_rows);
-        let (bank_cycle, bank_partial_sum) = two_matrix.bank_merge(&mem_settings);
+        let (bank_cycle, bank_partial_sum) = two_matrix.bank_merge(&mem_settings, &row_mapping);
         tracing::info!(?bank_cycle);
         let (bank_sent, chip_recv) = two_matrix.chip_fetch_data(&mem_settings, &bank_partial_sum);
         tracing::info!(?bank_sent, ?chip_recv);
@@ -0,0 +1,85 @@
+//! the small settings, matrices and records shared by the tests
+
+use sprs::{CsMat, TriMat};
+
+use crate::{
+    analyze::RunRecord,
+    settings::MemSettings,
+    sim::{SimReport, Simulator},
+    two_matrix::TwoMatrix,
+    utils::generator::{GeneratorKind, WorkloadSpec},
+};
+
+/// `channels` x `chips` x `banks` banks, the rest are the defaults
+pub fn small_settings(channels: usize, chips: usize, banks: usize) -> MemSettings {
+    MemSettings {
+        channels,
+        chips,
+        banks,
+        ..Default::default()
+    }
+}
+
+/// a matrix with a 1 at each of `entries`
+pub fn ones_at(
+    shape: (usize, usize),
+    entries: impl IntoIterator<Item = (usize, usize)>,
+) -> CsMat<i32> {
+    let mut tri = TriMat::new(shape);
+    for (row, col) in entries {
+        tri.add_triplet(row, col, 1);
+    }
+    tri.to_csr()
+}
+
+/// a matrix with the given row lengths, the nonzeros of a row are in its first columns
+pub fn ones_in_rows(lens: &[usize]) -> CsMat<i32> {
+    let cols = lens.iter().cloned().max().unwrap_or(0);
+    ones_at(
+        (lens.len(), cols),
+        lens.iter()
+            .enumerate()
+            .flat_map(|(row, &len)| (0..len).map(move |col| (row, col))),
+    )
+}
+
+/// the 62 x 62 matrix of the simulator tests
+pub fn bfwa62() -> CsMat<i32> {
+    sprs::io::read_matrix_market("mtx/bfwa62.mtx")
+        .unwrap()
+        .to_csr()
+}
+
+/// A x A^T like the cli
+pub fn a_times_at(a: &CsMat<i32>) -> TwoMatrix<i32, i32> {
+    TwoMatrix::new(a.clone(), a.transpose_view().to_csr())
+}
+
+/// simulate A x A^T of `bfwa62`
+pub fn simulate(mem_settings: &MemSettings) -> SimReport {
+    Simulator::run(mem_settings, a_times_at(&bfwa62())).unwrap()
+}
+
+/// a 256 x 256 workload with about 1311 nonzeros
+pub fn workload_spec(kind: GeneratorKind, skew: f64) -> WorkloadSpec {
+    WorkloadSpec {
+        name: None,
+        kind,
+        size: 256,
+        density: 0.02,
+        skew,
+        seed: 7,
+    }
+}
+
+pub fn run_record(run: &str, matrix: &str, config: &[(&str, &str)], cycles: f64) -> RunRecord {
+    RunRecord {
+        run: run.to_string(),
+        matrix: matrix.to_string(),
+        config: config
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        cycles: Some(cycles),
+    }
+}
@@ -1,5 +1,303 @@
+//! synthetic sparse matrices, used as workloads in the config and by the `gen` subcommand
+
+use std::{collections::BTreeSet, path::Path};
+
+use eyre::{ensure, Context, Result};
+use itertools::Itertools;
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_distr::{Distribution, Uniform, Zipf};
+use rand_xorshift::XorShiftRng;
+use serde::{Deserialize, Serialize};
+use sprs::{CsMat, TriMat};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, clap::ArgEnum)]
+pub enum GeneratorKind {
+    /// recursive matrix (Kronecker) graph, `skew` is the probability of the top-left quadrant
+    Rmat,
+    /// uniform random nonzeros, `skew` is unused
+    ErdosRenyi,
+    /// nonzeros around the diagonal, `skew` in [0,1] makes the band grow from the first row to the last
+    Banded,
+    /// dense blocks on the diagonal, the largest block is `1 + skew` times the smallest
+    BlockDiagonal,
+    /// the row lengths follow a zipf distribution with exponent `skew`
+    PowerLaw,
+}
+
+/// a synthetic matrix, the same spec always generates the same matrix
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, clap::Args)]
+#[serde(default)]
+pub struct WorkloadSpec {
+    /// the name of the matrix file, default is built from the parameters
+    #[clap(long)]
+    pub name: Option<String>,
+    #[clap(arg_enum)]
+    pub kind: GeneratorKind,
+    /// the number of rows and columns
+    #[clap(long, default_value = "1024")]
+    pub size: usize,
+    /// the nonzeros over size * size
+    #[clap(long, default_value = "0.01")]
+    pub density: f64,
+    #[clap(long, default_value = "0")]
+    pub skew: f64,
+    #[clap(long, default_value = "0")]
+    pub seed: u64,
+}
+
+impl Default for WorkloadSpec {
+    fn default() -> Self {
+        Self {
+            name: None,
+            kind: GeneratorKind::ErdosRenyi,
+            size: 1024,
+            density: 0.01,
+            skew: 0.,
+            seed: 0,
+        }
+    }
+}
+
+impl WorkloadSpec {
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            format!(
+                "{:?}_n{}_d{}_s{}_seed{}",
+                self.kind, self.size, self.density, self.skew, self.seed
+            )
+            .to_lowercase()
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(self.size > 0, "the size of {} should be > 0", self.name());
+        ensure!(
+            self.density > 0. && self.density <= 1.,
+            "the density of {} should be in (0, 1]",
+            self.name()
+        );
+        match self.kind {
+            GeneratorKind::Rmat => ensure!(
+                (0.25..1.).contains(&self.skew),
+                "the skew of rmat {} should be in [0.25, 1)",
+                self.name()
+            ),
+            GeneratorKind::Banded => ensure!(
+                (0. ..=1.).contains(&self.skew),
+                "the skew of banded {} should be in [0, 1]",
+                self.name()
+            ),
+            _ => ensure!(
+                self.skew >= 0.,
+                "the skew of {} should be >= 0",
+                self.name()
+            ),
+        }
+        Ok(())
+    }
+
+    /// generate the matrix, all values are 1 and no row is empty
+    pub fn generate(&self) -> Result<CsMat<i32>> {
+        self.validate()?;
+        let mut rng = XorShiftRng::seed_from_u64(self.seed);
+        let n = self.size;
+        let target = ((self.density * (n * n) as f64).round() as usize).clamp(1, n * n);
+        let mut entries: BTreeSet<_> = match self.kind {
+            GeneratorKind::Rmat => rmat(n, target, self.skew, &mut rng),
+            GeneratorKind::ErdosRenyi => rand::seq::index::sample(&mut rng, n * n, target)
+                .into_iter()
+                .map(|i| (i / n, i % n))
+                .collect(),
+            GeneratorKind::Banded => banded(n, self.density, self.skew),
+            GeneratorKind::BlockDiagonal => block_diagonal(n, self.density, self.skew, &mut rng),
+            GeneratorKind::PowerLaw => power_law(n, target, self.skew, &mut rng),
+        };
+        // the simulator expects a result for every row, so the empty rows get a diagonal nonzero
+        let filled = entries.iter().map(|(row, _)| *row).collect::<BTreeSet<_>>();
+        entries.extend(
+            (0..n)
+                .filter(|row| !filled.contains(row))
+                .map(|row| (row, row)),
+        );
+        let mut tri = TriMat::new((n, n));
+        for (row, col) in entries {
+            tri.add_triplet(row, col, 1);
+        }
+        Ok(tri.to_csr())
+    }
+
+    /// generate the matrix and write it as a matrix market file
+    pub fn write(&self, path: &Path) -> Result<CsMat<i32>> {
+        let matrix = self.generate()?;
+        sprs::io::write_matrix_market(path, &matrix).wrap_err(format!("fail to write {path:?}"))?;
+        Ok(matrix)
+    }
+}
+
+/// draw positions until `target` different ones are found, give up after a few rounds for dense settings
+fn sample_unique(
+    target: usize,
+    mut sample: impl FnMut() -> Option<(usize, usize)>,
+) -> BTreeSet<(usize, usize)> {
+    let mut entries = BTreeSet::new();
+    for _ in 0..target * 10 {
+        if entries.len() == target {
+            break;
+        }
+        if let Some(entry) = sample() {
+            entries.insert(entry);
+        }
+    }
+    entries
+}
+
+fn rmat(n: usize, target: usize, a: f64, rng: &mut impl Rng) -> BTreeSet<(usize, usize)> {
+    // the classic split of the rest: b = c = 0.4 * (1 - a), d = 0.2 * (1 - a)
+    let b = 0.4 * (1. - a);
+    let levels = n.next_power_of_two().trailing_zeros();
+    sample_unique(target, || {
+        let (mut row, mut col) = (0, 0);
+        for level in (0..levels).rev() {
+            let p: f64 = rng.gen();
+            let (down, right) = if p < a {
+                (0, 0)
+            } else if p < a + b {
+                (0, 1)
+            } else if p < a + 2. * b {
+                (1, 0)
+            } else {
+                (1, 1)
+            };
+            row |= down << level;
+            col |= right << level;
+        }
+        (row < n && col < n).then_some((row, col))
+    })
+}
+
+fn banded(n: usize, density: f64, skew: f64) -> BTreeSet<(usize, usize)> {
+    // each row has about 2 * width + 1 nonzeros
+    let width = ((density * n as f64 - 1.) / 2.).max(0.);
+    (0..n)
+        .flat_map(|row| {
+            let position = if n > 1 {
+                row as f64 / (n - 1) as f64
+            } else {
+                0.5
+            };
+            let row_width = (width * (1. + skew * (2. * position - 1.))).round() as usize;
+            (row.saturating_sub(row_width)..(row + row_width + 1).min(n)).map(move |col| (row, col))
+        })
+        .collect()
+}
+
+fn block_diagonal(
+    n: usize,
+    density: f64,
+    skew: f64,
+    rng: &mut impl Rng,
+) -> BTreeSet<(usize, usize)> {
+    // equal blocks of size n / k have density 1 / k
+    let blocks = ((1. / density).round() as usize).clamp(1, n);
+    let weights = (0..blocks)
+        .map(|i| (1. + skew).powf(i as f64 / (blocks - 1).max(1) as f64))
+        .collect_vec();
+    let total: f64 = weights.iter().sum();
+    // round the ends of the blocks so the rounding error is spread over all blocks
+    let ends = weights
+        .iter()
+        .scan(0., |sum, w| {
+            *sum += w;
+            Some((*sum / total * n as f64).round() as usize)
+        })
+        .collect_vec();
+    let mut sizes = ends
+        .iter()
+        .scan(0, |start, &end| {
+            let size = end - *start;
+            *start = end;
+            Some(size)
+        })
+        .filter(|&size| size > 0)
+        .collect_vec();
+    sizes.shuffle(rng);
+    let mut start = 0;
+    let mut entries = BTreeSet::new();
+    for size in sizes {
+        let end = start + size;
+        entries.extend((start..end).cartesian_product(start..end));
+        start = end;
+    }
+    entries
+}
+
+fn power_law(n: usize, target: usize, skew: f64, rng: &mut impl Rng) -> BTreeSet<(usize, usize)> {
+    // the heavy rows are spread over the matrix
+    let mut rows = (0..n).collect_vec();
+    rows.shuffle(rng);
+    let zipf = Zipf::new(n as u64, skew).unwrap();
+    let cols = Uniform::new(0, n);
+    sample_unique(target, || {
+        let rank = zipf.sample(rng) as usize - 1;
+        Some((rows[rank], cols.sample(rng)))
+    })
+}
+
 #[cfg(test)]
 mod test {
+    use super::*;
+    use crate::utils::fixtures::workload_spec as spec;
+
+    #[test]
+    fn generate_test() -> Result<()> {
+        for (kind, skew) in [
+            (GeneratorKind::Rmat, 0.57),
+            (GeneratorKind::ErdosRenyi, 0.),
+            (GeneratorKind::Banded, 0.),
+            (GeneratorKind::BlockDiagonal, 0.5),
+            (GeneratorKind::PowerLaw, 1.),
+        ] {
+            let matrix = spec(kind, skew).generate()?;
+            assert_eq!(matrix.shape(), (256, 256));
+            // about 0.02 * 256 * 256 = 1311 nonzeros
+            let nnz = matrix.nnz();
+            assert!((900..1800).contains(&nnz), "{kind:?} has {nnz} nonzeros");
+            assert!(matrix.outer_iterator().all(|row| row.nnz() > 0));
+            assert_eq!(
+                matrix,
+                spec(kind, skew).generate()?,
+                "{kind:?} is not stable"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn skew_test() -> Result<()> {
+        let max_row = |matrix: CsMat<i32>| matrix.outer_iterator().map(|r| r.nnz()).max().unwrap();
+        let flat = max_row(spec(GeneratorKind::PowerLaw, 0.).generate()?);
+        let skewed = max_row(spec(GeneratorKind::PowerLaw, 1.2).generate()?);
+        assert!(skewed > flat * 4, "{skewed} vs {flat}");
+
+        let banded = spec(GeneratorKind::Banded, 1.).generate()?;
+        let first = banded.outer_view(0).unwrap().nnz();
+        let last = banded.outer_view(255).unwrap().nnz();
+        assert!(first < last);
+        assert!(spec(GeneratorKind::Rmat, 0.2).generate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn write_test() -> Result<()> {
+        let path = std::env::temp_dir().join("spmm_pim_gen_test.mtx");
+        let spec = spec(GeneratorKind::Rmat, 0.57);
+        assert_eq!(spec.name(), "rmat_n256_d0.02_s0.57_seed7");
+        let matrix = spec.write(&path)?;
+        let read: CsMat<i32> = sprs::io::read_matrix_market(&path)?.to_csr();
+        assert_eq!(matrix, read);
+        Ok(())
+    }
+
     use genawaiter::{rc::gen, yield_};
 
     #[test]
@@ -4,7 +4,9 @@ use sprs::CsMat;
 
 use crate::two_matrix::TwoMatrix;
 
-mod generator;
+#[cfg(test)]
+pub mod fixtures;
+pub mod generator;
 pub mod plot;
 pub mod run;
 mod serial_test;
@@ -1,3 +1,539 @@
+//! draw the charts of the simulation results, used by the `plot` subcommand
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use eyre::{Context, Result};
+use itertools::Itertools;
+use plotters::{coord::Shift, prelude::*};
+use tracing::info;
+
+use crate::{
+    analyze::{self, RunRecord},
+    args::{ImageFormat, PlotArgs},
+    run_output::Manifest,
+};
+
+/// the size of all charts
+const CHART_SIZE: (u32, u32) = (1600, 900);
+
+/// bars that are split into stacked parts, like the idle reasons and the busy time of a component
+#[derive(Debug, Clone)]
+pub struct StackedBars {
+    pub title: String,
+    /// the name of each bar
+    pub bars: Vec<String>,
+    /// the name of each part and its value in every bar
+    pub parts: Vec<(String, Vec<f64>)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Chart {
+    Stacked(StackedBars),
+    /// several stacked bar charts in one picture
+    Grid(Vec<StackedBars>),
+    Histogram {
+        title: String,
+        x_desc: String,
+        values: Vec<f64>,
+        bins: usize,
+    },
+    /// one line for each series, the x can be the index of `x_labels` if the values are not numbers
+    Lines {
+        title: String,
+        x_desc: String,
+        x_labels: Option<Vec<String>>,
+        series: Vec<(String, Vec<(f64, f64)>)>,
+    },
+}
+
+impl Chart {
+    /// a histogram of `values` in `bins` bins of the same width, at least one value and one bin
+    pub fn histogram(
+        title: impl Into<String>,
+        x_desc: impl Into<String>,
+        values: Vec<f64>,
+        bins: usize,
+    ) -> Result<Self> {
+        if bins == 0 {
+            eyre::bail!("a histogram needs at least one bin");
+        }
+        if values.is_empty() {
+            eyre::bail!("a histogram needs at least one value");
+        }
+        Ok(Chart::Histogram {
+            title: title.into(),
+            x_desc: x_desc.into(),
+            values,
+            bins,
+        })
+    }
+}
+
+/// the width of the legend on the right of the stacked bar charts
+const LEGEND_WIDTH: u32 = 260;
+
+/// the same part has the same color in all charts, the busy part is always gray
+fn part_colors<'a>(charts: impl IntoIterator<Item = &'a StackedBars>) -> Vec<(String, RGBAColor)> {
+    let names = charts
+        .into_iter()
+        .flat_map(|c| c.parts.iter().map(|(name, _)| name.clone()))
+        .filter(|name| name != "busy")
+        .unique()
+        .sorted()
+        .collect_vec();
+    names
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| (name, Palette99::pick(i).to_rgba()))
+        .chain([("busy".to_string(), RGBColor(200, 200, 200).to_rgba())])
+        .collect()
+}
+
+fn draw_legend<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    colors: &[(String, RGBAColor)],
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    for (i, (name, color)) in colors.iter().enumerate() {
+        let y = 40 + i as i32 * 18;
+        area.draw(&Rectangle::new([(10, y), (22, y + 12)], color.filled()))?;
+        area.draw(&Text::new(name.as_str(), (28, y), ("sans-serif", 14)))?;
+    }
+    Ok(())
+}
+
+/// draw horizontal stacked bars, the parts of a bar are put one after another
+fn draw_stacked<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    stacked: &StackedBars,
+    colors: &[(String, RGBAColor)],
+    small: bool,
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let totals = (0..stacked.bars.len())
+        .map(|bar| stacked.parts.iter().map(|(_, v)| v[bar]).sum::<f64>())
+        .collect_vec();
+    let max = totals.iter().cloned().fold(0., f64::max).max(f64::EPSILON);
+    // the names of the big chart are long
+    let (caption_size, label_size, label_area) = if small { (16, 10, 25) } else { (24, 14, 220) };
+    let mut chart = ChartBuilder::on(area)
+        .caption(&stacked.title, ("sans-serif", caption_size))
+        .margin(10)
+        .x_label_area_size(20)
+        .y_label_area_size(label_area)
+        .build_cartesian_2d(0f64..max * 1.05, (0..stacked.bars.len()).into_segmented())?;
+    chart
+        .configure_mesh()
+        .disable_y_mesh()
+        .y_labels(stacked.bars.len())
+        .y_label_style(("sans-serif", label_size))
+        .y_label_formatter(&|y| match y {
+            SegmentValue::CenterOf(i) => stacked.bars.get(*i).cloned().unwrap_or_default(),
+            _ => String::new(),
+        })
+        .draw()?;
+    let mut starts = vec![0.; stacked.bars.len()];
+    for (name, values) in stacked.parts.iter() {
+        let color = colors
+            .iter()
+            .find(|(part, _)| part == name)
+            .map_or(BLACK.to_rgba(), |(_, color)| *color);
+        let rects = values
+            .iter()
+            .enumerate()
+            .map(|(bar, value)| {
+                let start = starts[bar];
+                starts[bar] += value;
+                let mut rect = Rectangle::new(
+                    [
+                        (start, SegmentValue::Exact(bar)),
+                        (start + value, SegmentValue::Exact(bar + 1)),
+                    ],
+                    color.filled(),
+                );
+                rect.set_margin(1, 1, 0, 0);
+                rect
+            })
+            .collect_vec();
+        chart.draw_series(rects)?;
+    }
+    Ok(())
+}
+
+impl Chart {
+    pub fn draw<DB: DrawingBackend>(&self, area: &DrawingArea<DB, Shift>) -> Result<()>
+    where
+        DB::ErrorType: 'static,
+    {
+        area.fill(&WHITE)?;
+        match self {
+            Chart::Stacked(stacked) => {
+                let colors = part_colors([stacked]);
+                let (main, legend) = area.split_horizontally(area.dim_in_pixel().0 - LEGEND_WIDTH);
+                draw_stacked(&main, stacked, &colors, false)?;
+                draw_legend(&legend, &colors)?;
+            }
+            Chart::Grid(charts) => {
+                let colors = part_colors(charts);
+                let (main, legend) = area.split_horizontally(area.dim_in_pixel().0 - LEGEND_WIDTH);
+                let cols = (charts.len() as f64).sqrt().ceil().max(1.) as usize;
+                let rows = (charts.len() + cols - 1) / cols;
+                for (sub_area, stacked) in main.split_evenly((rows.max(1), cols)).iter().zip(charts)
+                {
+                    draw_stacked(sub_area, stacked, &colors, true)?;
+                }
+                draw_legend(&legend, &colors)?;
+            }
+            Chart::Histogram {
+                title,
+                x_desc,
+                values,
+                bins,
+            } => {
+                if *bins == 0 {
+                    eyre::bail!("a histogram needs at least one bin");
+                }
+                if values.is_empty() {
+                    eyre::bail!("a histogram needs at least one value");
+                }
+                let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+                let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                let (min, max) = if min < max {
+                    (min, max)
+                } else {
+                    (min - 0.5, min + 0.5)
+                };
+                let width = (max - min) / *bins as f64;
+                let mut counts = vec![0u32; *bins];
+                for value in values {
+                    let bin = (((value - min) / width) as usize).min(bins - 1);
+                    counts[bin] += 1;
+                }
+                let mut chart = ChartBuilder::on(area)
+                    .caption(title, ("sans-serif", 24))
+                    .margin(10)
+                    .x_label_area_size(40)
+                    .y_label_area_size(50)
+                    .build_cartesian_2d(min..max, 0u32..counts.iter().max().unwrap_or(&0) + 1)?;
+                chart
+                    .configure_mesh()
+                    .disable_x_mesh()
+                    .x_desc(x_desc)
+                    .y_desc("count")
+                    .draw()?;
+                chart.draw_series(counts.iter().enumerate().map(|(bin, count)| {
+                    let left = min + width * bin as f64;
+                    let mut rect =
+                        Rectangle::new([(left, 0), (left + width, *count)], BLUE.mix(0.6).filled());
+                    rect.set_margin(0, 0, 1, 1);
+                    rect
+                }))?;
+            }
+            Chart::Lines {
+                title,
+                x_desc,
+                x_labels,
+                series,
+            } => {
+                let points = series.iter().flat_map(|(_, points)| points).collect_vec();
+                let x_min = points.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+                let x_max = points.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max);
+                let y_max = points.iter().map(|p| p.1).fold(0., f64::max);
+                let (x_min, x_max) = if x_min < x_max {
+                    (x_min, x_max)
+                } else {
+                    (x_min - 1., x_min + 1.)
+                };
+                let mut chart = ChartBuilder::on(area)
+                    .caption(title, ("sans-serif", 24))
+                    .margin(10)
+                    .x_label_area_size(40)
+                    .y_label_area_size(70)
+                    .build_cartesian_2d(x_min..x_max, 0f64..y_max.max(1.) * 1.05)?;
+                let label = |x: &f64| match x_labels {
+                    Some(labels) if x.fract() == 0. => {
+                        labels.get(*x as usize).cloned().unwrap_or_default()
+                    }
+                    Some(_) => String::new(),
+                    None => format!("{x}"),
+                };
+                chart
+                    .configure_mesh()
+                    .x_desc(x_desc)
+                    .y_desc("cycles")
+                    .x_label_formatter(&label)
+                    .draw()?;
+                for (color, (name, points)) in series.iter().enumerate() {
+                    let style = Palette99::pick(color).stroke_width(2);
+                    chart
+                        .draw_series(LineSeries::new(points.iter().cloned(), style))?
+                        .label(name)
+                        .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style));
+                    chart.draw_series(
+                        points
+                            .iter()
+                            .map(|p| Circle::new(*p, 3, Palette99::pick(color).filled())),
+                    )?;
+                }
+                chart
+                    .configure_series_labels()
+                    .background_style(WHITE.mix(0.8))
+                    .border_style(BLACK)
+                    .draw()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// render the chart into a svg or png file
+    pub fn save(&self, path: &Path, format: &ImageFormat) -> Result<()> {
+        match format {
+            ImageFormat::Svg => {
+                let area = SVGBackend::new(path, CHART_SIZE).into_drawing_area();
+                self.draw(&area)?;
+                area.present()?;
+            }
+            ImageFormat::Png => {
+                let area = BitMapBackend::new(path, CHART_SIZE).into_drawing_area();
+                self.draw(&area)?;
+                area.present()?;
+            }
+        }
+        info!("chart saved to {:?}", path);
+        Ok(())
+    }
+}
+
+/// split `tag:idle_name` into the tag and the idle name
+fn split_name(name: &str) -> (&str, &str) {
+    name.split_once(':').unwrap_or((name, ""))
+}
+
+/// the idle breakdown of each level (the tags of the components), the rest of the time is busy
+pub fn level_breakdown(time_stats: &[(String, f64)]) -> StackedBars {
+    let levels = time_stats
+        .iter()
+        .map(|(name, _)| split_name(name).0.to_string())
+        .unique()
+        .collect_vec();
+    let idle_names = time_stats
+        .iter()
+        .map(|(name, _)| split_name(name).1.to_string())
+        .unique()
+        .sorted()
+        .collect_vec();
+    let mut parts = idle_names
+        .iter()
+        .map(|idle| {
+            let values = levels
+                .iter()
+                .map(|level| {
+                    time_stats
+                        .iter()
+                        .find(|(name, _)| split_name(name) == (level.as_str(), idle.as_str()))
+                        .map_or(0., |(_, rate)| *rate)
+                })
+                .collect_vec();
+            (idle.clone(), values)
+        })
+        .collect_vec();
+    let busy = (0..levels.len())
+        .map(|level| (1. - parts.iter().map(|(_, v)| v[level]).sum::<f64>()).max(0.))
+        .collect();
+    parts.push(("busy".to_string(), busy));
+    StackedBars {
+        title: "idle/busy breakdown per level".to_string(),
+        bars: levels,
+        parts,
+    }
+}
+
+/// the idle breakdown of each component, one chart per level.
+/// a component without an idle reason has no time for it
+pub fn component_breakdown(detailed_stats: &[(String, Vec<(String, f64)>)]) -> Vec<StackedBars> {
+    let grouped = detailed_stats
+        .iter()
+        .map(|(name, rates)| {
+            let (level, idle) = split_name(name);
+            (level.to_string(), (idle.to_string(), rates))
+        })
+        .into_group_map();
+    grouped
+        .into_iter()
+        .sorted_by(|a, b| a.0.cmp(&b.0))
+        .map(|(level, idle_rates)| {
+            let components = idle_rates
+                .iter()
+                .flat_map(|(_, rates)| rates.iter().map(|(component, _)| component.clone()))
+                .unique()
+                .collect_vec();
+            let mut parts = idle_rates
+                .iter()
+                .map(|(idle, rates)| {
+                    let values = components
+                        .iter()
+                        .map(|component| {
+                            rates
+                                .iter()
+                                .find(|(name, _)| name == component)
+                                .map_or(0., |(_, rate)| *rate)
+                        })
+                        .collect_vec();
+                    (idle.clone(), values)
+                })
+                .collect_vec();
+            let busy = (0..components.len())
+                .map(|c| (1. - parts.iter().map(|(_, v)| v[c]).sum::<f64>()).max(0.))
+                .collect();
+            parts.push(("busy".to_string(), busy));
+            StackedBars {
+                title: level,
+                bars: components,
+                parts,
+            }
+        })
+        .collect()
+}
+
+/// the finish time of the banks relative to the whole run, from `end_time_stats.json`
+pub fn bank_loads(end_time_stats: &[(String, f64)]) -> Vec<f64> {
+    end_time_stats
+        .iter()
+        .filter(|(name, _)| name.starts_with("bank_reorder"))
+        .map(|(_, rate)| *rate)
+        .collect()
+}
+
+/// the cycles of each matrix along one config axis, the axis can be the full key or the last part like `sender_store_size`
+pub fn sweep_curves(records: &[RunRecord], axis: &str) -> Result<Chart> {
+    let key = records
+        .iter()
+        .flat_map(|r| r.config.keys())
+        .find(|key| *key == axis || key.ends_with(&format!(".{axis}")))
+        .ok_or_else(|| eyre::eyre!("no setting named {axis}"))?
+        .clone();
+    let values = records
+        .iter()
+        .map(|r| r.config.get(&key).cloned().unwrap_or_default())
+        .unique()
+        .collect_vec();
+    // the values that are not numbers are put at their index
+    let x_labels = if values.iter().all(|v| v.parse::<f64>().is_ok()) {
+        None
+    } else {
+        Some(values.iter().cloned().sorted().collect_vec())
+    };
+    let position = |value: &str| match &x_labels {
+        Some(labels) => labels.iter().position(|x| x == value).unwrap() as f64,
+        None => value.parse().unwrap(),
+    };
+    let series = records
+        .iter()
+        .filter_map(|r| {
+            r.cycles.map(|cycles| {
+                let x = position(r.config.get(&key).map_or("", String::as_str));
+                (r.matrix.clone(), (x, cycles))
+            })
+        })
+        .into_group_map()
+        .into_iter()
+        .sorted_by(|a, b| a.0.cmp(&b.0))
+        .map(|(matrix, mut points)| {
+            points.sort_by(|a, b| a.0.total_cmp(&b.0));
+            (matrix, points)
+        })
+        .collect();
+    Ok(Chart::Lines {
+        title: format!("cycles vs {axis}"),
+        x_desc: key,
+        x_labels,
+        series,
+    })
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
+    serde_json::from_reader(File::open(path).wrap_err(format!("fail to open {path:?}"))?)
+        .wrap_err(format!("fail to parse {path:?}"))
+}
+
+/// draw the breakdowns and the bank loads of every matrix of a run into `{run}/plots/{matrix}`
+fn plot_run(run_dir: &Path, format: &ImageFormat) -> Result<Vec<PathBuf>> {
+    let manifest = Manifest::load(run_dir)?;
+    let ext = format!("{format:?}").to_lowercase();
+    let mut saved = vec![];
+    for matrix in manifest.matrices.iter().filter(|m| m.error.is_none()) {
+        let artifact = |name: &str| {
+            matrix
+                .artifacts
+                .iter()
+                .find(|a| a.ends_with(name))
+                .map(|a| run_dir.join(a))
+        };
+        let stem = matrix.file.file_stem().unwrap_or_default();
+        let plot_dir = run_dir.join("plots").join(stem);
+        std::fs::create_dir_all(&plot_dir)?;
+        let mut save = |chart: Chart, name: &str| -> Result<()> {
+            let path = plot_dir.join(format!("{name}.{ext}"));
+            chart.save(&path, format)?;
+            saved.push(path);
+            Ok(())
+        };
+        if let Some(path) = artifact("time_stats.json") {
+            save(
+                Chart::Stacked(level_breakdown(&read_json::<Vec<_>>(&path)?)),
+                "levels",
+            )?;
+        }
+        if let Some(path) = artifact("detailed_time_stats.json") {
+            save(
+                Chart::Grid(component_breakdown(&read_json::<Vec<_>>(&path)?)),
+                "components",
+            )?;
+        }
+        if let Some(path) = artifact("end_time_stats.json") {
+            let values = bank_loads(&read_json::<Vec<_>>(&path)?);
+            if !values.is_empty() {
+                let bins = values.len().min(20);
+                let chart =
+                    Chart::histogram("bank load", "finish time / total time", values, bins)?;
+                save(chart, "bank_load")?;
+            }
+        }
+    }
+    Ok(saved)
+}
+
+/// run the `plot` subcommand
+pub fn main(args: &PlotArgs) -> Result<()> {
+    match &args.sweep {
+        Some(axis) => {
+            let records = analyze::load_runs(&args.runs)?;
+            let output = args.output.clone().unwrap_or_else(|| {
+                PathBuf::from(format!(
+                    "sweep_{axis}.{}",
+                    format!("{:?}", args.format).to_lowercase()
+                ))
+            });
+            sweep_curves(&records, axis)?.save(&output, &args.format)?;
+        }
+        None => {
+            for path in &args.runs {
+                for run in analyze::find_runs(path)? {
+                    plot_run(&run, &args.format)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use eyre::Result;
@@ -264,4 +800,69 @@ mod tests {
         // let 🍡="🦀";
         // let 🦀="🍡";
     }
+
+    #[test]
+    fn breakdown_test() {
+        let time_stats = vec![
+            ("bank_pe:merge".to_string(), 0.25),
+            ("bank_pe:wait".to_string(), 0.25),
+            ("chip_pe:merge".to_string(), 0.5),
+        ];
+        let levels = super::level_breakdown(&time_stats);
+        assert_eq!(levels.bars, vec!["bank_pe", "chip_pe"]);
+        assert_eq!(
+            levels.parts,
+            vec![
+                ("merge".to_string(), vec![0.25, 0.5]),
+                ("wait".to_string(), vec![0.25, 0.]),
+                ("busy".to_string(), vec![0.5, 0.5]),
+            ]
+        );
+
+        // the bank 1 never waits, its rates are matched by name and not by position
+        let rate = |component: &str, rate| (component.to_string(), rate);
+        let detailed = vec![
+            (
+                "bank_pe:merge".to_string(),
+                vec![rate("bank0", 0.5), rate("bank1", 1.)],
+            ),
+            ("bank_pe:wait".to_string(), vec![rate("bank0", 0.25)]),
+        ];
+        let components = super::component_breakdown(&detailed);
+        assert_eq!(components[0].bars, vec!["bank0", "bank1"]);
+        assert_eq!(components[0].parts[1], ("wait".to_string(), vec![0.25, 0.]));
+        assert_eq!(components[0].parts[2], ("busy".to_string(), vec![0.25, 0.]));
+
+        assert!(super::Chart::histogram("bank load", "finish time", vec![1.], 0).is_err());
+        assert!(super::Chart::histogram("bank load", "finish time", vec![], 4).is_err());
+        assert!(super::Chart::histogram("bank load", "finish time", vec![1.], 4).is_ok());
+    }
+
+    #[test]
+    fn sweep_test() -> Result<()> {
+        use crate::analyze::RunRecord;
+        let record = |size: &str, cycles| RunRecord {
+            run: size.to_string(),
+            matrix: "m1".to_string(),
+            config: [(
+                "mem_settings.sender_store_size".to_string(),
+                size.to_string(),
+            )]
+            .into_iter()
+            .collect(),
+            cycles: Some(cycles),
+        };
+        let records = vec![record("64", 20.), record("32", 30.)];
+        let chart = super::sweep_curves(&records, "sender_store_size")?;
+        match &chart {
+            super::Chart::Lines { series, .. } => {
+                assert_eq!(series[0].1, vec![(32., 30.), (64., 20.)])
+            }
+            _ => panic!("not a line chart"),
+        }
+        let path = std::env::temp_dir().join("spmm_pim_sweep_test.svg");
+        chart.save(&path, &crate::args::ImageFormat::Svg)?;
+        assert!(std::fs::read_to_string(&path)?.contains("<svg"));
+        Ok(())
+    }
 }
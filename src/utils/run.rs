@@ -1,7 +1,11 @@
 use std::path::Path;
 
 use crate::{
-    bsr::Bsr, pim::Pim, result::SingleResult, settings::MemSettings, two_matrix::TwoMatrix,
+    bsr::Bsr,
+    pim::{Pim, ResolvedRowMapping},
+    result::SingleResult,
+    settings::MemSettings,
+    two_matrix::TwoMatrix,
 };
 
 use eyre::Result;
@@ -30,9 +34,12 @@ pub fn run_exp_csr<'a, const R: usize, const C: usize>(
     let csr: CsMat<_> = bsr.into();
     let csr_transpose: CsMat<_> = bsr_transpose.into();
     let two_mat = TwoMatrix::new(csr, csr_transpose);
+    // the steps below can not return an error, check the settings and resolve the mapping of this matrix first
+    mem_settings.validate_with_rows(two_mat.b.rows())?;
+    let row_mapping = ResolvedRowMapping::new(mem_settings, &two_mat.b)?;
 
-    let row_read = two_mat.mem_rows(mem_settings);
-    let (bank_merged_cycles, partial_sum) = two_mat.bank_merge(mem_settings);
+    let row_read = two_mat.mem_rows(mem_settings, &row_mapping);
+    let (bank_merged_cycles, partial_sum) = two_mat.bank_merge(mem_settings, &row_mapping);
     let (chip_merged_cycles, partial_sum) = two_mat.chip_merge(mem_settings, &partial_sum);
     let (channel_merged_cycles, partial_sum) = two_mat.channel_merge(mem_settings, &partial_sum);
     let (dimm_merged_cycles, _partial_sum) = two_mat.dimm_merge(mem_settings, &partial_sum);
@@ -21,10 +21,13 @@ fn test() -> Result<()> {
             let store_config = format!("configs/store_sizes/{store}.toml");
             let inter_config = format!("configs/interleaving/{inter}.toml");
             let scheduler_config = format!("configs/scheduler_modes/{scheduler_mode}.toml");
+            let run_name = format!("run_name=\"{scheduler_mode}_{store}_{inter}\"");
             let args = vec![
                 "spmm_pim",
                 "-r",
                 "sim",
+                "--set",
+                &run_name,
                 "configs/large.toml",
                 "configs/ddr4.toml",
                 &scheduler_config,
@@ -55,10 +58,13 @@ fn test_unlimited() -> Result<()> {
             let store_config = format!("configs/store_sizes/{store}.toml");
             let inter_config = format!("configs/interleaving/{inter}.toml");
             let scheduler_config = format!("configs/scheduler_modes/{scheduler_mode}.toml");
+            let run_name = format!("run_name=\"{scheduler_mode}_{store}_{inter}\"");
             let args = vec![
                 "spmm_pim",
                 "-r",
                 "sim",
+                "--set",
+                &run_name,
                 "configs/large.toml",
                 "configs/ddr4.toml",
                 &scheduler_config,
@@ -86,10 +92,13 @@ fn test_chunk() -> Result<()> {
         .for_each(|(store, scheduler_mode)| {
             let store_config = format!("configs/store_sizes/{store}.toml");
             let scheduler_config = format!("configs/scheduler_modes/{scheduler_mode}.toml");
+            let run_name = format!("run_name=\"chunk_{scheduler_mode}_{store}\"");
             let args = vec![
                 "spmm_pim",
                 "-r",
                 "sim",
+                "--set",
+                &run_name,
                 "configs/large.toml",
                 "configs/ddr4chunk.toml",
                 &scheduler_config,
@@ -124,10 +133,13 @@ fn test_batch() -> Result<()> {
             let scheduler_config = format!("configs/scheduler_modes/{scheduler_mode}.toml");
             let batch_size_config =
                 format!("configs/scheduler_modes/batch_sizes/{batch_size}.toml");
+            let run_name = format!("run_name=\"{scheduler_mode}_{store}_{inter}_{batch_size}\"");
             let args = vec![
                 "spmm_pim",
                 "-r",
                 "sim",
+                "--set",
+                &run_name,
                 "configs/large.toml",
                 "configs/ddr4.toml",
                 &scheduler_config,
@@ -163,7 +175,8 @@ fn collect_data() {
                     let mut inter_results = vec![];
                     for inter in [16] {
                         let result_file = format!(
-                            "results/full_time_Chunk_{mode}_{store}_{inter}_{batch}_{graph}.json",
+                            "results/{}_{store}_{inter}/{graph}/full_time.json",
+                            mode.to_lowercase()
                         );
                         let time: f64 = serde_json::from_reader(
                             std::fs::File::open(&result_file)
@@ -189,10 +202,13 @@ fn collect_data() {
 fn run_chunck() -> Result<()> {
     for store in [32, 64, 128, 256, 512] {
         let store_config = format!("configs/store_sizes/{store}.toml");
+        let run_name = format!("run_name=\"chunk_{store}\"");
         let args = vec![
             "spmm_pim",
             "-r",
             "sim",
+            "--set",
+            &run_name,
             "configs/large.toml",
             "configs/ddr4chunk.toml",
             &store_config,
@@ -202,10 +218,7 @@ fn run_chunck() -> Result<()> {
         run_main::main(args).unwrap();
     }
     for store in [32, 64, 128, 256, 512] {
-        let result_file = format!(
-            "results/full_time_Chunk_{}_16_Groebner_id2003_aug.json",
-            store
-        );
+        let result_file = format!("results/chunk_{store}/Groebner_id2003_aug/full_time.json");
         let time: f64 = serde_json::from_reader(std::fs::File::open(result_file).unwrap()).unwrap();
         println!("{}", time);
     }
@@ -216,10 +229,7 @@ fn run_chunck() -> Result<()> {
 #[ignore]
 fn get_chunk() {
     for store in [32, 64, 128, 256, 512] {
-        let result_file = format!(
-            "results/full_time_Chunk_{}_16_Groebner_id2003_aug.json",
-            store
-        );
+        let result_file = format!("results/chunk_{store}/Groebner_id2003_aug/full_time.json");
         let time: f64 = serde_json::from_reader(std::fs::File::open(result_file).unwrap()).unwrap();
         println!("{}", time);
     }
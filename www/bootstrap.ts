@@ -18,4 +18,14 @@ import("./index")
          });
 
       }
+      for (const [id, simulate] of [["run_local", false], ["simulate_local", true]] as [string, boolean][]) {
+         (document.getElementById(id)).onclick = function () {
+            let indext = index as typeof import("./index");
+            indext.run_local(simulate).then((x) => {
+               document.getElementById("result").innerHTML = JSON.stringify(x, null, 2);
+            }).catch((e) => {
+               document.getElementById("log").innerHTML = "Error: " + e;
+            });
+         }
+      }
    });
@@ -54,5 +54,19 @@ export async function run() {
 
 }
 
+// run the uploaded mtx file without the server, the settings are the json of the changed fields of mem_settings
+export async function run_local(simulate: boolean) {
+    let file = (document.getElementById("local_file") as HTMLInputElement).files[0];
+    if (file == null) {
+        throw new Error("no mtx file selected");
+    }
+    let mtx = await file.text();
+    let settings = (document.getElementById("settings") as HTMLTextAreaElement).value;
+    if (simulate) {
+        return JSON.parse(wasm.run_simulator(mtx, settings));
+    }
+    return JSON.parse(wasm.run_analytical(file.name, mtx, settings));
+}
+
 
 